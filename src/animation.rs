@@ -0,0 +1,422 @@
+use serde::{Deserialize, Serialize};
+use super::error::{FracError, FracResult};
+use super::my_complex::MyComplex;
+use super::scene::{FractalSpec, Scene, ViewSpec};
+use super::viewport::DEFAULT_SPAN;
+
+// How the progress along the path speeds up and slows down over the
+// animation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    // Start slowly
+    In,
+    // End slowly
+    Out,
+    // Start and end slowly
+    InOut,
+}
+
+pub const EASING_NAMES: [&str; 4] = ["linear", "in", "out", "in-out"];
+
+impl Easing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "in" => Some(Easing::In),
+            "out" => Some(Easing::Out),
+            "in-out" => Some(Easing::InOut),
+            _ => None,
+        }
+    }
+
+    // Map the fraction of the animation's time that has passed (0 to 1) to
+    // the fraction of the path that has been covered
+    pub fn apply(&self, time: f64) -> f64 {
+        let time: f64 = time.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => time,
+            Easing::In => time * time,
+            Easing::Out => 1.0 - (1.0 - time) * (1.0 - time),
+            Easing::InOut => time * time * (3.0 - 2.0 * time),
+        }
+    }
+}
+
+// The center and zoom of a view, working them out from the axis ranges if
+// the view is given that way (from the real range, if the pixels aren't
+// square)
+fn center_and_zoom(view: &ViewSpec, width: u32, height: u32) -> (MyComplex<f64>, f64) {
+    if view.real_range.is_none() && view.imag_range.is_none() {
+        return (MyComplex::new(view.center.r() as f64, view.center.i() as f64), view.zoom);
+    }
+    let (real_start, real_end): (f64, f64) = view.real_range.unwrap_or((-2.0, 2.0));
+    let (imag_start, imag_end): (f64, f64) = view.imag_range.unwrap_or((-2.0, 2.0));
+    let center = MyComplex::new((real_start + real_end) / 2.0, (imag_start + imag_end) / 2.0);
+    let pixel_size: f64 = (real_end - real_start) / width.max(1) as f64;
+    (center, DEFAULT_SPAN as f64 / (pixel_size * width.min(height).max(1) as f64))
+}
+
+// A zoom from the view of one scene to the view of another. The zoom
+// changes exponentially, so every frame magnifies the last one by the same
+// amount, and the center moves so the point the two views zoom around stays
+// put on the screen. The rotation and the iteration limit change along with
+// the zoom. Everything else comes from the start scene.
+pub struct ZoomAnimation {
+    start: Scene,
+    frames: u32,
+    easing: Easing,
+    start_center: MyComplex<f64>,
+    end_center: MyComplex<f64>,
+    start_zoom: f64,
+    end_zoom: f64,
+    end_rotate: f64,
+    end_max_iters: u32,
+}
+
+impl ZoomAnimation {
+    pub fn new(start: Scene, end: &Scene, frames: u32) -> FracResult<Self> {
+        if frames == 0 {
+            return Err(FracError::InvalidInput(String::from("An animation needs at least 1 frame")));
+        }
+        // Make sure both ends are views that can be rendered
+        start.viewport()?;
+        end.viewport()?;
+
+        let (width, height): (u32, u32) = (start.output.width, start.output.height);
+        let (start_center, start_zoom) = center_and_zoom(&start.view, width, height);
+        let (end_center, end_zoom) = center_and_zoom(&end.view, width, height);
+        Ok(ZoomAnimation {
+            frames,
+            easing: Easing::Linear,
+            start_center, end_center, start_zoom, end_zoom,
+            end_rotate: end.view.rotate,
+            end_max_iters: end.max_iters,
+            start,
+        })
+    }
+
+    pub fn set_easing(&mut self, val: Easing) {
+        self.easing = val;
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // The scene of frame `frame` (counting from 0)
+    pub fn frame(&self, frame: u32) -> Scene {
+        let time: f64 = if self.frames > 1 { frame as f64 / (self.frames - 1) as f64 } else { 0.0 };
+        let progress: f64 = self.easing.apply(time);
+
+        let zoom: f64 = self.start_zoom * (self.end_zoom / self.start_zoom).powf(progress);
+        // How far along the center is. Moving it in step with the span of
+        // the view (rather than the zoom) keeps the point being zoomed
+        // around in the same spot on the screen.
+        let center_progress: f64 = if self.start_zoom == self.end_zoom {
+            progress
+        } else {
+            (1.0 / self.start_zoom - 1.0 / zoom) / (1.0 / self.start_zoom - 1.0 / self.end_zoom)
+        };
+        let center: MyComplex<f64> = self.start_center + (self.end_center - self.start_center) * MyComplex::new(center_progress, 0.0);
+
+        let mut scene: Scene = self.start.clone();
+        scene.view = ViewSpec {
+            center: MyComplex::new(center.r() as f32, center.i() as f32),
+            zoom,
+            rotate: self.start.view.rotate + (self.end_rotate - self.start.view.rotate) * progress,
+            real_range: None,
+            imag_range: None,
+        };
+        let start_iters: f64 = self.start.max_iters as f64;
+        scene.max_iters = (start_iters + (self.end_max_iters as f64 - start_iters) * progress).round() as u32;
+        scene
+    }
+}
+
+// A path a point of the complex plane moves along over an animation.
+// Angles are in degrees.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "path", rename_all = "lowercase", deny_unknown_fields)]
+pub enum PathSpec {
+    Circle {
+        center: MyComplex<f32>,
+        radius: f64,
+        #[serde(default)]
+        start_angle: f64,
+        #[serde(default = "full_turn")]
+        end_angle: f64,
+    },
+    Line { from: MyComplex<f32>, to: MyComplex<f32> },
+    // A Bézier curve with the points as its control points. It starts at the
+    // first one and ends at the last, but only passes near the others.
+    Bezier { points: Vec<MyComplex<f32>> },
+    // A smooth (Catmull-Rom) curve through every point, reaching them at
+    // evenly spaced times. A closed spline goes back to the first point.
+    Spline {
+        points: Vec<MyComplex<f32>>,
+        #[serde(default)]
+        closed: bool,
+    },
+}
+
+fn full_turn() -> f64 { 360.0 }
+
+fn complex_f64(val: MyComplex<f32>) -> MyComplex<f64> {
+    MyComplex::new(val.r() as f64, val.i() as f64)
+}
+
+fn lerp(start: MyComplex<f64>, end: MyComplex<f64>, frac: f64) -> MyComplex<f64> {
+    start + (end - start) * MyComplex::new(frac, 0.0)
+}
+
+// A point on the Catmull-Rom segment from `p1` to `p2`
+fn catmull_rom(p0: MyComplex<f64>, p1: MyComplex<f64>, p2: MyComplex<f64>, p3: MyComplex<f64>, frac: f64) -> MyComplex<f64> {
+    let weight = |val: f64| MyComplex::new(val, 0.0);
+    let (frac2, frac3): (f64, f64) = (frac * frac, frac * frac * frac);
+    (p1 * weight(2.0)
+        + (p2 - p0) * weight(frac)
+        + (p0 * weight(2.0) - p1 * weight(5.0) + p2 * weight(4.0) - p3) * weight(frac2)
+        + (p1 * weight(3.0) - p0 - p2 * weight(3.0) + p3) * weight(frac3)) * weight(0.5)
+}
+
+impl PathSpec {
+    fn check(&self) -> FracResult<()> {
+        let (points, min_points, name): (usize, usize, &str) = match self {
+            PathSpec::Circle{radius, start_angle, end_angle, ..} => {
+                if !(radius.is_finite() && start_angle.is_finite() && end_angle.is_finite()) {
+                    return Err(FracError::InvalidInput(String::from("The radius and angles of a circle path have to be numbers")));
+                }
+                return Ok(());
+            }
+            PathSpec::Line{..} => return Ok(()),
+            PathSpec::Bezier{points} => (points.len(), 2, "Bézier"),
+            PathSpec::Spline{points, ..} => (points.len(), 2, "spline"),
+        };
+        if points < min_points {
+            return Err(FracError::InvalidInput(format!("A {} path needs at least {} points", name, min_points)));
+        }
+        Ok(())
+    }
+
+    // The point `progress` (0 to 1) of the way along the path, or an error
+    // if the path can't be followed
+    pub fn point_at(&self, progress: f64) -> FracResult<MyComplex<f32>> {
+        self.check()?;
+        let progress: f64 = progress.clamp(0.0, 1.0);
+        let point: MyComplex<f64> = match self {
+            PathSpec::Circle{center, radius, start_angle, end_angle} => {
+                let angle: f64 = (start_angle + (end_angle - start_angle) * progress).to_radians();
+                complex_f64(*center) + MyComplex::new(radius * angle.cos(), radius * angle.sin())
+            }
+            PathSpec::Line{from, to} => lerp(complex_f64(*from), complex_f64(*to), progress),
+            PathSpec::Bezier{points} => {
+                // De Casteljau's algorithm
+                let mut points: Vec<MyComplex<f64>> = points.iter().map(|point| complex_f64(*point)).collect();
+                while points.len() > 1 {
+                    points = points.windows(2).map(|pair| lerp(pair[0], pair[1], progress)).collect();
+                }
+                points[0]
+            }
+            PathSpec::Spline{points, closed} => {
+                let count: usize = points.len();
+                let segments: usize = if *closed { count } else { count - 1 };
+                let pos: f64 = progress * segments as f64;
+                let segment: usize = (pos.floor() as usize).min(segments - 1);
+                // Past the ends of an open spline, the end points are
+                // repeated
+                let point = |idx: isize| -> MyComplex<f64> {
+                    let idx: usize = if *closed {
+                        idx.rem_euclid(count as isize) as usize
+                    } else {
+                        idx.clamp(0, count as isize - 1) as usize
+                    };
+                    complex_f64(points[idx])
+                };
+                let seg: isize = segment as isize;
+                catmull_rom(point(seg - 1), point(seg), point(seg + 1), point(seg + 2), pos - segment as f64)
+            }
+        };
+        Ok(MyComplex::new(point.r() as f32, point.i() as f32))
+    }
+}
+
+// How a scene's fractal changes over an animation: the path its Julia seed
+// follows, or the paths of its Newton roots (one for each root). In a scene
+// file:
+//
+//   [animation]
+//   frames = 120
+//   seed = { path = "circle", center = "0", radius = 0.7885 }
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnimationSpec {
+    pub frames: u32,
+    #[serde(default = "default_easing")]
+    pub easing: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<PathSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roots: Vec<PathSpec>,
+}
+
+fn default_easing() -> String { String::from("linear") }
+
+impl AnimationSpec {
+    pub fn new(frames: u32) -> Self {
+        AnimationSpec { frames, easing: default_easing(), seed: None, roots: Vec::new() }
+    }
+
+    // Make sure the paths can be followed by `fractal`
+    pub fn check(&self, fractal: &FractalSpec) -> FracResult<()> {
+        Easing::from_name(&self.easing).ok_or_else(|| FracError::InvalidInput(format!("Unknown easing: {}", self.easing)))?;
+        match (fractal, &self.seed, self.roots.is_empty()) {
+            (_, None, true) => Ok(()),
+            (_, Some(_), false) => Err(FracError::InvalidInput(String::from("An animation can't move both a seed and roots"))),
+            (FractalSpec::Julia{..}, _, true) | (FractalSpec::Newton{..}, None, _) => Ok(()),
+            (_, Some(_), _) => Err(FracError::InvalidInput(String::from("Only a Julia set has a seed to move"))),
+            (_, None, _) => Err(FracError::InvalidInput(String::from("Only a Newton fractal has roots to move"))),
+        }?;
+        self.seed.iter().chain(self.roots.iter()).try_for_each(|path| path.check())
+    }
+
+    // The fractal at `time` (0 to 1) through the animation. Paths that
+    // don't fit the fractal are left out, so call `check` with the fractal
+    // first to find those.
+    pub fn fractal_at(&self, fractal: &FractalSpec, time: f64) -> FracResult<FractalSpec> {
+        let progress: f64 = Easing::from_name(&self.easing).unwrap_or(Easing::Linear).apply(time);
+        Ok(match (fractal, &self.seed) {
+            (FractalSpec::Julia{..}, Some(path)) => FractalSpec::Julia{seed: path.point_at(progress)?},
+            (FractalSpec::Newton{..}, None) if !self.roots.is_empty() => FractalSpec::Newton {
+                roots: self.roots.iter().map(|path| path.point_at(progress)).collect::<FracResult<_>>()?,
+            },
+            _ => fractal.clone(),
+        })
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::scene::FractalSpec;
+
+    fn zoom_scenes() -> (Scene, Scene) {
+        let start = Scene::new(FractalSpec::Mandelbrot, 64, 48);
+        let mut end: Scene = start.clone();
+        end.view.center = MyComplex::new(-0.75, 0.1);
+        end.view.zoom = 100.0;
+        end.view.rotate = 90.0;
+        end.max_iters = 3000;
+        (start, end)
+    }
+
+    #[test]
+    fn easing_ends() {
+        for name in EASING_NAMES {
+            let easing: Easing = Easing::from_name(name).unwrap();
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert!(easing.apply(0.3) < easing.apply(0.6));
+        }
+        assert!(Easing::In.apply(0.5) < 0.5 && Easing::Out.apply(0.5) > 0.5);
+    }
+
+    // The first and last frames are the two views, and the zoom changes by
+    // the same factor every frame
+    #[test]
+    fn zoom_path() {
+        let (start, end) = zoom_scenes();
+        let anim = ZoomAnimation::new(start.clone(), &end, 5).unwrap();
+        assert_eq!(anim.frame(0), start);
+
+        let last: Scene = anim.frame(4);
+        assert!((last.view.zoom - 100.0).abs() < 1e-9);
+        assert_eq!((last.view.center, last.view.rotate, last.max_iters), (end.view.center, 90.0, 3000));
+
+        let ratios: Vec<f64> = (0..4).map(|frame| anim.frame(frame + 1).view.zoom / anim.frame(frame).view.zoom).collect();
+        assert!(ratios.iter().all(|ratio| (ratio - ratios[0]).abs() < 1e-9));
+    }
+
+    // The point the zoom closes in on is in the same place in every frame
+    #[test]
+    fn fixed_point() {
+        let (start, end) = zoom_scenes();
+        let anim = ZoomAnimation::new(start, &end, 7).unwrap();
+        // Where the spans shrink toward: c0 + (c1 - c0) / (1 - z0 / z1)
+        let fixed = MyComplex::new(-0.75_f32, 0.1) * MyComplex::new(1.0 / (1.0 - 1.0 / 100.0), 0.0);
+        let screen_pos = |frame: u32| -> (f32, f32) {
+            let mut scene: Scene = anim.frame(frame);
+            scene.view.rotate = 0.0;
+            scene.viewport().unwrap().complex_to_point(fixed)
+        };
+        let first: (f32, f32) = screen_pos(0);
+        for frame in 1..7 {
+            let pos: (f32, f32) = screen_pos(frame);
+            assert!((pos.0 - first.0).abs() < 0.01 && (pos.1 - first.1).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn paths() {
+        let close = |a: FracResult<MyComplex<f32>>, b: MyComplex<f32>| (a.unwrap() - b).mag_sqr() < 1e-10;
+        let circle = PathSpec::Circle{center: MyComplex::new(1.0, 0.0), radius: 0.5, start_angle: 0.0, end_angle: 360.0};
+        assert!(close(circle.point_at(0.25), MyComplex::new(1.0, 0.5)));
+        assert!(close(circle.point_at(1.0), circle.point_at(0.0).unwrap()));
+
+        let line = PathSpec::Line{from: MyComplex::new(0.0, 0.0), to: MyComplex::new(2.0, -2.0)};
+        assert!(close(line.point_at(0.5), MyComplex::new(1.0, -1.0)));
+
+        let points = vec![MyComplex::new(0.0, 0.0), MyComplex::new(1.0, 1.0), MyComplex::new(2.0, 0.0)];
+        let bezier = PathSpec::Bezier{points: points.clone()};
+        assert!(close(bezier.point_at(0.0), points[0]) && close(bezier.point_at(1.0), points[2]));
+        assert!(close(bezier.point_at(0.5), MyComplex::new(1.0, 0.5)));
+
+        // A spline passes through each of its points in turn
+        let spline = PathSpec::Spline{points: points.clone(), closed: false};
+        for (idx, point) in points.iter().enumerate() {
+            assert!(close(spline.point_at(idx as f64 / 2.0), *point));
+        }
+        let closed = PathSpec::Spline{points: points.clone(), closed: true};
+        assert!(close(closed.point_at(1.0 / 3.0), points[1]) && close(closed.point_at(1.0), points[0]));
+
+        // Paths without enough points don't have any points along them
+        assert!(PathSpec::Spline{points: Vec::new(), closed: false}.point_at(0.5).is_err());
+        assert!(PathSpec::Bezier{points: vec![points[0]]}.point_at(0.0).is_err());
+    }
+
+    // Paths are read from the scene file, and have to fit the fractal
+    #[test]
+    fn parameter_animation() {
+        let scene: Scene = toml::from_str("[fractal]\ntype = \"julia\"\nseed = \"-0.8\"\n\
+            [output]\nwidth = 10\nheight = 10\n\
+            [animation]\nframes = 30\nseed = { path = \"line\", from = \"-0.8\", to = \"-0.8+0.2i\" }\n").unwrap();
+        let anim: &AnimationSpec = scene.animation.as_ref().unwrap();
+        anim.check(&scene.fractal).unwrap();
+        assert_eq!(anim.fractal_at(&scene.fractal, 0.5).unwrap(), FractalSpec::Julia{seed: MyComplex::new(-0.8, 0.1)});
+
+        let newton = FractalSpec::Newton{roots: vec![MyComplex::new(1.0, 0.0)]};
+        assert!(anim.check(&newton).is_err());
+        assert!(AnimationSpec::new(10).check(&FractalSpec::Mandelbrot).is_ok());
+        let mut roots_anim = AnimationSpec::new(10);
+        roots_anim.roots = vec![PathSpec::Bezier{points: vec![MyComplex::new(1.0, 0.0)]}];
+        assert!(roots_anim.check(&newton).is_err());
+        roots_anim.roots = vec![PathSpec::Line{from: MyComplex::new(1.0, 0.0), to: MyComplex::new(2.0, 0.0)}; 3];
+        roots_anim.check(&newton).unwrap();
+        assert_eq!(roots_anim.fractal_at(&newton, 1.0).unwrap(), FractalSpec::Newton{roots: vec![MyComplex::new(2.0, 0.0); 3]});
+    }
+
+    #[test]
+    fn range_views() {
+        let (mut start, end) = zoom_scenes();
+        start.view.real_range = Some((-2.0, 1.0));
+        start.view.imag_range = Some((-1.0, 1.0));
+        let anim = ZoomAnimation::new(start, &end, 3).unwrap();
+        let first: Scene = anim.frame(0);
+        assert_eq!(first.view.center, MyComplex::new(-0.5, 0.0));
+        assert!((first.view.zoom - 4.0 / (3.0 / 64.0 * 48.0)).abs() < 1e-9);
+        assert!(ZoomAnimation::new(first, &end, 0).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+use super::error::{FracError, FracResult};
+use super::fractals::{Fractal, FractalType, FracOutput};
+use super::graphics::PixPos;
+use super::my_complex::MyComplex;
+use super::orbit_trap::OrbitTrap;
+use super::render::{PixelSource, UNRENDERED};
+use super::viewport::Viewport;
+
+// How the view of a scene is split into a grid of Julia sets. In a scene
+// file:
+//
+//   [atlas]
+//   columns = 12
+//   rows = 8
+//   background = true
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AtlasSpec {
+    pub columns: u32,
+    pub rows: u32,
+    // Pixels left empty between the cells
+    #[serde(default = "default_gap")]
+    pub gap: u32,
+    // How far each Julia set is magnified inside its cell
+    #[serde(default = "default_cell_zoom")]
+    pub cell_zoom: f64,
+    // Show the scene's fractal in the gaps instead of leaving them empty
+    #[serde(default)]
+    pub background: bool,
+}
+
+fn default_gap() -> u32 { 2 }
+fn default_cell_zoom() -> f64 { 1.0 }
+
+impl AtlasSpec {
+    pub fn new(columns: u32, rows: u32) -> Self {
+        AtlasSpec { columns, rows, gap: default_gap(), cell_zoom: default_cell_zoom(), background: false }
+    }
+}
+
+// A grid of small Julia sets, each one seeded with the complex number at the
+// middle of its cell in the view. The gaps between the cells show the
+// background fractal over the same view, or are left like the inside of a
+// set if there's no background.
+pub struct JuliaAtlas<'a> {
+    viewport: Viewport,
+    columns: u32,
+    rows: u32,
+    gap: u32,
+    cell_zoom: f32,
+    // The seed and Julia set of every cell, a row at a time from the bottom
+    // left
+    seeds: Vec<MyComplex<f32>>,
+    cells: Vec<Fractal>,
+    background: Option<&'a Fractal>,
+}
+
+// Where cell `cell` of `cells` starts along an axis `size` pixels long
+fn cell_start(cell: u32, cells: u32, size: u32) -> u32 {
+    (cell as u64 * size as u64 / cells as u64) as u32
+}
+
+// The cell pixel `pos` of an axis falls in
+fn cell_of(pos: u32, cells: u32, size: u32) -> u32 {
+    (((pos as u64 + 1) * cells as u64 - 1) / size as u64) as u32
+}
+
+impl<'a> JuliaAtlas<'a> {
+    pub fn new(viewport: Viewport, columns: u32, rows: u32, max_iter: u32) -> FracResult<Self> {
+        let (width, height): (u32, u32) = (viewport.width(), viewport.height());
+        if columns == 0 || rows == 0 || columns > width || rows > height {
+            return Err(FracError::InvalidInput(format!(
+                "A {}x{} image can't be split into {} columns and {} rows of Julia sets", width, height, columns, rows)));
+        }
+
+        let mut seeds: Vec<MyComplex<f32>> = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for col in 0..columns {
+                let mid_x: f32 = (cell_start(col, columns, width) + cell_start(col + 1, columns, width)) as f32 / 2.0;
+                let mid_y: f32 = (cell_start(row, rows, height) + cell_start(row + 1, rows, height)) as f32 / 2.0;
+                seeds.push(viewport.point_to_complex(mid_x, mid_y));
+            }
+        }
+        let cells: Vec<Fractal> = seeds.iter().map(|seed| Fractal::new(max_iter, FractalType::Julia(*seed)))
+            .collect::<FracResult<_>>()?;
+        Ok(JuliaAtlas { viewport, columns, rows, gap: default_gap(), cell_zoom: 1.0, seeds, cells, background: None })
+    }
+
+    // The smallest cell still has to have a pixel left inside the gaps
+    pub fn set_gap(&mut self, val: u32) -> FracResult<()> {
+        let min_width: u32 = self.viewport.width() / self.columns;
+        let min_height: u32 = self.viewport.height() / self.rows;
+        if val >= min_width.min(min_height) {
+            return Err(FracError::InvalidInput(format!(
+                "A gap of {} pixels leaves no room in {}x{} pixel cells", val, min_width, min_height)));
+        }
+        self.gap = val;
+        Ok(())
+    }
+
+    pub fn set_cell_zoom(&mut self, val: f32) -> FracResult<()> {
+        if !(val > 0.0 && val.is_finite()) {
+            return Err(FracError::InvalidInput(format!("The zoom of the cells has to be a positive number, not {}", val)));
+        }
+        self.cell_zoom = val;
+        Ok(())
+    }
+
+    pub fn set_distance_estimate(&mut self, val: bool) {
+        for cell in self.cells.iter_mut() {
+            cell.set_distance_estimate(val);
+        }
+    }
+
+    pub fn set_trap(&mut self, val: Option<OrbitTrap>) {
+        for cell in self.cells.iter_mut() {
+            cell.set_trap(val.clone());
+        }
+    }
+
+    pub fn set_interior(&mut self, val: bool) {
+        for cell in self.cells.iter_mut() {
+            cell.set_interior(val);
+        }
+    }
+
+    pub fn set_background(&mut self, val: Option<&'a Fractal>) {
+        self.background = val;
+    }
+
+    // The seed of the Julia set in the cell at `col` across and `row` up
+    pub fn seed(&self, col: u32, row: u32) -> MyComplex<f32> {
+        self.seeds[(row * self.columns + col) as usize]
+    }
+
+    fn background_pixel(&self, pos: PixPos) -> FracOutput {
+        match self.background {
+            Some(fractal) => fractal.complex_to_frac_output(self.viewport.pixel_to_complex(pos)),
+            None => UNRENDERED,
+        }
+    }
+}
+
+impl<'a> PixelSource for JuliaAtlas<'a> {
+    fn pixel(&self, pos: PixPos) -> FracOutput {
+        let (width, height): (u32, u32) = (self.viewport.width(), self.viewport.height());
+        let col: u32 = cell_of(pos.col, self.columns, width);
+        let row: u32 = cell_of(pos.row, self.rows, height);
+
+        // The part of the cell inside the gaps
+        let (left, right): (u32, u32) = (cell_start(col, self.columns, width), cell_start(col + 1, self.columns, width));
+        let (bottom, top): (u32, u32) = (cell_start(row, self.rows, height), cell_start(row + 1, self.rows, height));
+        let (left, right): (u32, u32) = (left + self.gap / 2, right - (self.gap - self.gap / 2));
+        let (bottom, top): (u32, u32) = (bottom + self.gap / 2, top - (self.gap - self.gap / 2));
+        if pos.col < left || pos.col >= right || pos.row < bottom || pos.row >= top {
+            return self.background_pixel(pos);
+        }
+
+        let cell_view = Viewport::from_zoom(right - left, top - bottom, MyComplex::new(0.0, 0.0), self.cell_zoom);
+        let cell_pos = PixPos{row: pos.row - bottom, col: pos.col - left};
+        let mut output: FracOutput = self.cells[(row * self.columns + col) as usize].complex_to_frac_output(cell_view.pixel_to_complex(cell_pos));
+        // Distances are measured in the pixels of the whole view, so
+        // distance coloring looks the same in every cell
+        if let FracOutput::MandelJulia{ref mut distance, ..} = output {
+            *distance *= self.viewport.pixel_size().0 / cell_view.pixel_size().0;
+        }
+        output
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_bounds() {
+        for (cells, size) in [(3, 10), (7, 7), (4, 1000)] {
+            for pos in 0..size {
+                let cell: u32 = cell_of(pos, cells, size);
+                assert!(cell_start(cell, cells, size) <= pos && pos < cell_start(cell + 1, cells, size));
+            }
+        }
+    }
+
+    // Each cell is seeded from the middle of its part of the view
+    #[test]
+    fn cell_seeds() {
+        let viewport = Viewport::from_ranges(40, 20, (-2.0, 2.0), (-1.0, 1.0));
+        let atlas = JuliaAtlas::new(viewport, 4, 2, 50).unwrap();
+        assert_eq!(atlas.seed(0, 0), MyComplex::new(-1.5, -0.5));
+        assert_eq!(atlas.seed(3, 1), MyComplex::new(1.5, 0.5));
+    }
+
+    // The gaps show the background, or are left empty without one
+    #[test]
+    fn gaps_and_background() {
+        let viewport = Viewport::from_ranges(40, 20, (-2.0, 2.0), (-1.0, 1.0));
+        let mandelbrot = Fractal::new(50, FractalType::Mandelbrot()).unwrap();
+        let mut atlas = JuliaAtlas::new(viewport, 4, 2, 50).unwrap();
+        atlas.set_gap(4).unwrap();
+        assert_eq!(atlas.pixel(PixPos{row: 0, col: 5}), UNRENDERED);
+        // The corner of a cell is far outside its Julia set
+        assert!(matches!(atlas.pixel(PixPos{row: 2, col: 2}), FracOutput::MandelJulia{iters, ..} if iters < 50));
+
+        atlas.set_background(Some(&mandelbrot));
+        let background: FracOutput = mandelbrot.complex_to_frac_output(viewport.pixel_to_complex(PixPos{row: 0, col: 5}));
+        assert_eq!(atlas.pixel(PixPos{row: 0, col: 5}), background);
+        assert!(matches!(atlas.pixel(PixPos{row: 2, col: 2}), FracOutput::MandelJulia{iters, ..} if iters < 50));
+
+        assert!(atlas.set_gap(10).is_err());
+        assert!(atlas.set_cell_zoom(0.0).is_err());
+        assert!(JuliaAtlas::new(viewport, 41, 2, 50).is_err());
+    }
+}
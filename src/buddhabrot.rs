@@ -0,0 +1,294 @@
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering}};
+use std::thread;
+use serde::{Deserialize, Serialize};
+use super::error::{FracError, FracResult};
+use super::my_complex::MyComplex;
+use super::viewport::Viewport;
+
+// The orbits are traced in chunks, each with its own random number stream,
+// so the result doesn't depend on how many threads traced them
+const CHUNK_ORBITS: u64 = 4096;
+
+// The most orbit points each thread makes room for up front. Longer orbits
+// still fit, but a huge iteration limit doesn't reserve memory that only
+// the rare long orbit would use.
+const ORBIT_CAPACITY: usize = 65_536;
+
+// The square the random `c` values are picked from. Every point of the
+// Mandelbrot set is inside it.
+const SAMPLE_MIN: f64 = -2.0;
+const SAMPLE_SIZE: f64 = 4.0;
+
+// How a scene is rendered as a Buddhabrot: the density of the orbits of
+// random points, instead of a value for each pixel. In a scene file:
+//
+//   [density]
+//   orbits = 20000000
+//   nebula = [5000, 500, 50]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DensitySpec {
+    // The number of random points traced
+    pub orbits: u64,
+    // Trace the points that never escape instead (the Anti-Buddhabrot)
+    #[serde(default)]
+    pub anti: bool,
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+    // The density is raised to this power after it's scaled to 0 - 1, to
+    // bring out the faint orbits
+    #[serde(default = "default_gamma")]
+    pub gamma: f64,
+    // The iteration limits of the red, green and blue channels of a
+    // Nebulabrot. Without them, the scene's iteration limit is used for a
+    // single channel colored with the palette (or in gray).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nebula: Vec<u32>,
+}
+
+fn default_seed() -> u64 { 1 }
+fn default_gamma() -> f64 { 0.5 }
+
+impl DensitySpec {
+    pub fn new(orbits: u64) -> Self {
+        DensitySpec { orbits, anti: false, seed: default_seed(), gamma: default_gamma(), nebula: Vec::new() }
+    }
+}
+
+// SplitMix64: a small, fast random number generator that gives the same
+// numbers for the same seed on every machine
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut val: u64 = self.state;
+        val = (val ^ (val >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        val = (val ^ (val >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        val ^ (val >> 31)
+    }
+
+    // A number from 0 up to (not including) 1
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+// How many orbit points landed in each pixel, a row at a time from the
+// bottom left (the same order as FracImage)
+pub struct DensityMap {
+    width: u32,
+    height: u32,
+    counts: Vec<u32>,
+}
+
+impl DensityMap {
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn counts(&self) -> &[u32] { &self.counts }
+
+    // The brightness (0 - 1) of every pixel. The counts are scaled so the
+    // brightest 0.1% of the pixels that were hit are fully bright (a few
+    // pixels on the real axis collect far more points than the rest), then
+    // raised to `gamma`.
+    pub fn brightness(&self, gamma: f32) -> Vec<f32> {
+        let mut hit: Vec<u32> = self.counts.iter().copied().filter(|count| *count > 0).collect();
+        if hit.is_empty() {
+            return vec![0.0; self.counts.len()];
+        }
+        let idx: usize = ((hit.len() - 1) as f64 * 0.999).round() as usize;
+        let (_, white, _) = hit.select_nth_unstable(idx);
+        let white: f32 = *white as f32;
+        self.counts.iter().map(|count| (*count as f32 / white).min(1.0).powf(gamma)).collect()
+    }
+}
+
+// Whether `c` is inside the main cardioid or the period 2 bulb of the
+// Mandelbrot set, where no point escapes
+fn in_main_bulbs(c: MyComplex<f64>) -> bool {
+    let (x, y): (f64, f64) = (c.r(), c.i());
+    let q: f64 = (x - 0.25) * (x - 0.25) + y * y;
+    q * (q + (x - 0.25)) <= 0.25 * y * y || (x + 1.0) * (x + 1.0) + y * y <= 0.0625
+}
+
+// Traces the Mandelbrot orbits of random points and counts the orbit
+// points that land in each pixel of a view
+pub struct OrbitTracer {
+    viewport: Viewport,
+    max_iter: u32,
+    anti: bool,
+    seed: u64,
+    threads: usize,
+    cancel: Arc<AtomicBool>,
+}
+
+impl OrbitTracer {
+    pub fn new(viewport: Viewport, max_iter: u32) -> FracResult<Self> {
+        if max_iter == 0 {
+            return Err(FracError::InvalidInput(String::from("The iteration limit has to be at least 1")));
+        }
+        Ok(OrbitTracer {
+            viewport, max_iter,
+            anti: false,
+            seed: default_seed(),
+            threads: 1,
+            cancel: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    // Count the orbits of the points that never escape instead of the ones
+    // that do
+    pub fn set_anti(&mut self, val: bool) {
+        self.anti = val;
+    }
+
+    pub fn set_seed(&mut self, val: u64) {
+        self.seed = val;
+    }
+
+    pub fn set_threads(&mut self, val: usize) {
+        self.threads = val.max(1);
+    }
+
+    // Setting this flag stops the tracing after the chunks currently being
+    // traced. The counts so far are still returned.
+    pub fn set_cancel_flag(&mut self, val: Arc<AtomicBool>) {
+        self.cancel = val;
+    }
+
+    pub fn was_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    // Trace `orbits` random points. The same seed always gives the same
+    // counts.
+    pub fn trace(&self, orbits: u64) -> DensityMap {
+        let (width, height): (u32, u32) = (self.viewport.width(), self.viewport.height());
+        let chunks: u64 = orbits.div_ceil(CHUNK_ORBITS);
+        let next_chunk: AtomicU64 = AtomicU64::new(0);
+
+        // Every thread adds to the same counts. The sums don't depend on the
+        // order the points are added in, so the result is still the same
+        // for the same seed.
+        let counts: Vec<AtomicU32> = (0..(width as usize) * (height as usize)).map(|_| AtomicU32::new(0)).collect();
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let (next_chunk, counts): (&AtomicU64, &[AtomicU32]) = (&next_chunk, &counts);
+                scope.spawn(move || {
+                    let mut orbit: Vec<MyComplex<f64>> = Vec::with_capacity((self.max_iter as usize).min(ORBIT_CAPACITY));
+                    while !self.cancel.load(Ordering::Relaxed) {
+                        let chunk: u64 = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        if chunk >= chunks {
+                            break;
+                        }
+                        let chunk_orbits: u64 = CHUNK_ORBITS.min(orbits - chunk * CHUNK_ORBITS);
+                        // Each chunk's stream starts from a scrambled mix of
+                        // the seed and the chunk number
+                        let mut rng = SplitMix64::new(SplitMix64::new(self.seed ^ chunk.wrapping_mul(0xd1b5_4a32_d192_ed03)).next_u64());
+                        for _ in 0..chunk_orbits {
+                            let c = MyComplex::new(SAMPLE_MIN + SAMPLE_SIZE * rng.next_f64(), SAMPLE_MIN + SAMPLE_SIZE * rng.next_f64());
+                            self.trace_orbit(c, &mut orbit, counts);
+                        }
+                    }
+                });
+            }
+        });
+
+        DensityMap { width, height, counts: counts.into_iter().map(AtomicU32::into_inner).collect() }
+    }
+
+    // Iterate z = z^2 + c, and if the point escapes (or doesn't, for the
+    // Anti-Buddhabrot) count every point of its orbit that's in the view
+    fn trace_orbit(&self, c: MyComplex<f64>, orbit: &mut Vec<MyComplex<f64>>, counts: &[AtomicU32]) {
+        if !self.anti && in_main_bulbs(c) {
+            return;
+        }
+        orbit.clear();
+        let mut z: MyComplex<f64> = MyComplex::new(0.0, 0.0);
+        let mut escaped: bool = false;
+        for _ in 0..self.max_iter {
+            z = z * z + c;
+            if z.mag_sqr() > 4.0 {
+                escaped = true;
+                break;
+            }
+            orbit.push(z);
+        }
+        if escaped == self.anti {
+            return;
+        }
+
+        let (width, height): (u32, u32) = (self.viewport.width(), self.viewport.height());
+        for point in orbit.iter() {
+            let (x, y): (f32, f32) = self.viewport.complex_to_point(MyComplex::new(point.r() as f32, point.i() as f32));
+            if x >= 0.0 && y >= 0.0 && (x as u32) < width && (y as u32) < height {
+                let idx: usize = (y as usize) * (width as usize) + x as usize;
+                // Counts stop at the largest u32 rather than wrapping
+                let _ = counts[idx].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| count.checked_add(1));
+            }
+        }
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_rng() {
+        let mut first = SplitMix64::new(7);
+        let mut second = SplitMix64::new(7);
+        let vals: Vec<f64> = (0..1000).map(|_| first.next_f64()).collect();
+        assert!(vals.iter().all(|val| (0.0..1.0).contains(val) && *val == second.next_f64()));
+        assert_ne!(SplitMix64::new(8).next_u64(), SplitMix64::new(7).next_u64());
+    }
+
+    // The same seed gives the same counts however many threads trace them
+    #[test]
+    fn reproducible_density() {
+        let viewport = Viewport::from_zoom(24, 16, MyComplex::new(-0.5, 0.0), 1.0);
+        let mut tracer = OrbitTracer::new(viewport, 100).unwrap();
+        let single: DensityMap = tracer.trace(20_000);
+        tracer.set_threads(3);
+        let multi: DensityMap = tracer.trace(20_000);
+        assert_eq!(single.counts(), multi.counts());
+        assert!(single.counts().iter().any(|count| *count > 0));
+
+        tracer.set_seed(2);
+        assert_ne!(tracer.trace(20_000).counts(), single.counts());
+    }
+
+    // The orbits are symmetric across the real axis, so the density should
+    // be too (roughly, with random points)
+    #[test]
+    fn buddhabrot_shape() {
+        let viewport = Viewport::from_zoom(20, 20, MyComplex::new(-0.5, 0.0), 1.0);
+        let mut tracer = OrbitTracer::new(viewport, 200).unwrap();
+        let total = |map: &DensityMap, rows: std::ops::Range<usize>| -> u64 {
+            map.counts()[rows.start * 20..rows.end * 20].iter().map(|count| *count as u64).sum()
+        };
+        for anti in [false, true] {
+            tracer.set_anti(anti);
+            let map: DensityMap = tracer.trace(50_000);
+            let (bottom, top): (u64, u64) = (total(&map, 0..10), total(&map, 10..20));
+            assert!(bottom > 0 && (bottom as f64 / top as f64 - 1.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn brightness_scale() {
+        let map = DensityMap { width: 2, height: 2, counts: vec![0, 4, 16, 16] };
+        assert_eq!(map.brightness(0.5), vec![0.0, 0.5, 1.0, 1.0]);
+        let empty = DensityMap { width: 1, height: 1, counts: vec![0] };
+        assert_eq!(empty.brightness(0.5), vec![0.0]);
+    }
+}
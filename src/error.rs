@@ -0,0 +1,81 @@
+use std::{fmt, io};
+
+// Everything that can stop the program from making an image
+#[derive(Debug)]
+pub enum FracError {
+    // A value given to the program doesn't make sense (an image with no
+    // pixels, a Newton fractal without roots, text where a number was
+    // expected, ...)
+    InvalidInput(String),
+    // Reading or writing a file failed
+    Io {path: String, source: io::Error},
+    // A file isn't in the format it should be, or the image doesn't fit in
+    // the format it's being written in
+    Format(String),
+    // The render was stopped with Ctrl-C (the finished part of the image is
+    // still written out)
+    Cancelled,
+}
+
+pub type FracResult<T> = Result<T, FracError>;
+
+impl FracError {
+    pub fn io(path: &str, source: io::Error) -> Self {
+        FracError::Io {path: String::from(path), source}
+    }
+
+    // The exit code the program stops with, so scripts can tell the kinds
+    // of failures apart. Bad input uses the same code as command line
+    // usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FracError::InvalidInput(_) => 2,
+            FracError::Format(_) => 3,
+            FracError::Io{..} => 4,
+            FracError::Cancelled => 130,
+        }
+    }
+}
+
+impl fmt::Display for FracError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FracError::InvalidInput(msg) => write!(f, "{}", msg),
+            FracError::Io{path, source} => write!(f, "{}: {}", path, source),
+            FracError::Format(msg) => write!(f, "{}", msg),
+            FracError::Cancelled => write!(f, "The render was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for FracError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FracError::Io{source, ..} => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_differ() {
+        let errors: [FracError; 4] = [
+            FracError::InvalidInput(String::from("bad")),
+            FracError::Format(String::from("bad")),
+            FracError::io("out.bmp", io::Error::from(io::ErrorKind::PermissionDenied)),
+            FracError::Cancelled,
+        ];
+        for (idx, err) in errors.iter().enumerate() {
+            assert_ne!(err.exit_code(), 0);
+            assert!(errors[idx + 1..].iter().all(|other| other.exit_code() != err.exit_code()));
+        }
+        assert!(errors[2].to_string().starts_with("out.bmp: "));
+    }
+}
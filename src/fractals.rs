@@ -1,4 +1,9 @@
-use super::my_complex::MyComplex;
+use super::error::{FracError, FracResult};
+use super::my_complex::{MyComplex, MyComplexLanes};
+use super::orbit_trap::{OrbitTrap, TrapHit, NO_TRAP_HIT};
+
+// The number of points the batched Mandelbrot/Julia kernel works on at once
+pub const LANES: usize = 8;
 
 // Fractals can come in three varieties: Mandelbrot fractals, Julia fractals,
 // and Newton fractals.
@@ -17,48 +22,188 @@ pub enum FractalType {
 pub struct Fractal {
     max_iter: u32,
     kind: FractalType,
+    // Whether Mandelbrot/Julia outputs get a distance estimate (it costs a
+    // derivative alongside every step of the orbit)
+    distance: bool,
+    // Every orbit point of a Mandelbrot/Julia set is measured against this
+    trap: Option<OrbitTrap>,
+    // Whether Mandelbrot/Julia outputs get their InteriorInfo filled in
+    interior: bool,
 }
 
 // The output of a Fractal algorithm comes in two varieties:
-// 1) The number of iterations before the value diverges (Mandelbrot/Julia)
+// 1) The number of iterations before the value diverges (Mandelbrot/Julia),
+//    along with the magnitude of 'z' when the iteration stopped. Points
+//    found to be stuck in a cycle stop early with `iters` set to `max_iters`
+//    and `period` set to the length of the cycle (0 when no cycle was found).
+//    For escaping points, `distance` is an estimate of how far the point is
+//    from the edge of the set (within a factor of 4 or so), if the fractal
+//    was asked for one; otherwise it's 0. `trap` is the closest the orbit
+//    came to the fractal's orbit trap (NO_TRAP_HIT without one), and
+//    `interior` has more about the orbit if the fractal was asked for it
+//    (NO_INTERIOR_INFO otherwise).
 // 2) The closest root after `n` iterations (Newton)
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum FracOutput {
-    MandelJulia {iters:u32, max_iters:u32},
+    MandelJulia {iters:u32, max_iters:u32, final_mag:f32, period:u32, distance:f32, trap:TrapHit, interior:InteriorInfo},
     Newton {closest:usize, roots:usize},
 }
 
+// More about a Mandelbrot/Julia orbit, for coloring the inside of the set:
+// the direction of the average orbit point (as an angle from -pi to pi),
+// and for points found in a cycle, the multiplier of the cycle (its
+// derivative, which is under 1 for the attracting cycles inside the set)
+// and an estimate of the distance to the edge of the set (Mandelbrot only)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InteriorInfo {
+    pub angle: f32,
+    pub multiplier: MyComplex<f32>,
+    pub distance: f32,
+}
+
+pub const NO_INTERIOR_INFO: InteriorInfo = InteriorInfo { angle: 0.0, multiplier: MyComplex::new(0.0, 0.0), distance: 0.0 };
+
+impl FracOutput {
+    // A continuous ("smooth") version of the iteration count, which removes
+    // the banding between iteration levels. Points that never escaped just
+    // return their plain iteration count, and Newton outputs (which don't
+    // keep one) return 0.
+    pub fn smooth_iters(&self) -> f32 {
+        match *self {
+            FracOutput::MandelJulia{iters, max_iters, final_mag, ..} => {
+                if iters >= max_iters || final_mag <= 1.0 {
+                    iters as f32
+                } else {
+                    iters as f32 + 1.0 - final_mag.ln().log2()
+                }
+            }
+            FracOutput::Newton{..} => 0.0,
+        }
+    }
+
+    // The output at the mirror image (complex conjugate) of the point this
+    // is the output of, for a fractal that's symmetric across the real
+    // axis. `root_map` is from Fractal::conjugate_root_map.
+    pub fn conjugate(&self, root_map: &[usize]) -> FracOutput {
+        match *self {
+            FracOutput::Newton{closest, roots} => FracOutput::Newton{closest: root_map[closest], roots},
+            // The orbit of the conjugate point is the conjugate of this one
+            FracOutput::MandelJulia{iters, max_iters, final_mag, period, distance, trap, interior} => FracOutput::MandelJulia {
+                iters, max_iters, final_mag, period, distance,
+                trap: TrapHit{pos: MyComplex::new(trap.pos.r(), -trap.pos.i()), ..trap},
+                // Angles run from -pi to pi, and pi (the negative real
+                // axis) is its own mirror image
+                interior: InteriorInfo {
+                    angle: if interior.angle == std::f32::consts::PI { interior.angle } else { -interior.angle },
+                    multiplier: MyComplex::new(interior.multiplier.r(), -interior.multiplier.i()),
+                    ..interior
+                },
+            },
+        }
+    }
+}
+
+fn check_max_iter(max_iter: u32) -> FracResult<()> {
+    if max_iter == 0 {
+        return Err(FracError::InvalidInput(String::from("The maximum number of iterations has to be at least 1")));
+    }
+    Ok(())
+}
+
+// The numbers that define a fractal have to be finite, and a Newton fractal
+// needs at least one root
+fn check_kind(kind: &FractalType) -> FracResult<()> {
+    let finite = |z: &MyComplex<f32>| -> bool { z.r().is_finite() && z.i().is_finite() };
+    match kind {
+        FractalType::Mandelbrot() => Ok(()),
+        FractalType::Julia(z_const) if !finite(z_const) => {
+            Err(FracError::InvalidInput(format!("The Julia seed {}{:+}i isn't a finite number", z_const.r(), z_const.i())))
+        }
+        FractalType::Julia(_) => Ok(()),
+        FractalType::Newton(roots) if roots.is_empty() => {
+            Err(FracError::InvalidInput(String::from("A Newton fractal needs at least one root")))
+        }
+        FractalType::Newton(roots) => match roots.iter().find(|root| !finite(root)) {
+            Some(root) => Err(FracError::InvalidInput(format!("The Newton root {}{:+}i isn't a finite number", root.r(), root.i()))),
+            None => Ok(()),
+        },
+    }
+}
+
 // All that needs to be implemented for a Fractal is the algorithm that
 // converts a complex number to some divergence / nearest root value
 impl Fractal {
-    pub fn new(max_iter: u32, kind: FractalType) -> Self {
-        Fractal {max_iter, kind}
+    pub fn new(max_iter: u32, kind: FractalType) -> FracResult<Self> {
+        check_max_iter(max_iter)?;
+        check_kind(&kind)?;
+        Ok(Fractal {max_iter, kind, distance: false, trap: None, interior: false})
     }
 
-    pub fn set_max_iter(&mut self, val: u32) {
+    pub fn set_max_iter(&mut self, val: u32) -> FracResult<()> {
+        check_max_iter(val)?;
         self.max_iter = val;
+        Ok(())
     }
 
-    pub fn set_kind(&mut self, val: FractalType) {
+    pub fn set_kind(&mut self, val: FractalType) -> FracResult<()> {
+        check_kind(&val)?;
         self.kind = val;
+        Ok(())
+    }
+
+    // Estimate the distance to the edge of the set for escaping
+    // Mandelbrot/Julia points. Newton fractals ignore it.
+    pub fn set_distance_estimate(&mut self, val: bool) {
+        self.distance = val;
+    }
+
+    // Follow Mandelbrot/Julia orbits into an orbit trap. Orbits are measured
+    // against the trap point by point, so this goes through the scalar
+    // algorithm, and Mandelbrot points inside the biggest bulbs are
+    // iterated like any other point.
+    pub fn set_trap(&mut self, val: Option<OrbitTrap>) {
+        self.trap = val;
+    }
+
+    // Record the InteriorInfo of Mandelbrot/Julia orbits. Like orbit traps,
+    // this goes through the scalar algorithm and iterates every point.
+    pub fn set_interior(&mut self, val: bool) {
+        self.interior = val;
+    }
+
+    // If the fractal is symmetric across the real axis (the output at
+    // conj(z) is the same as at z), returns which root each Newton root is
+    // the conjugate of, for swapping the root indices of mirrored pixels
+    // (empty for Mandelbrot/Julia). Returns None if it isn't symmetric,
+    // which includes Mandelbrot/Julia orbits followed into a trap that
+    // isn't symmetric itself.
+    pub fn conjugate_root_map(&self) -> Option<Vec<usize>> {
+        if !matches!(self.kind, FractalType::Newton(_)) && self.trap.as_ref().is_some_and(|trap| !trap.is_conjugate_symmetric()) {
+            return None;
+        }
+        match &self.kind {
+            FractalType::Mandelbrot() => Some(Vec::new()),
+            FractalType::Julia(z_const) => if z_const.i() == 0.0 { Some(Vec::new()) } else { None },
+            FractalType::Newton(roots) => roots.iter().map(|root| {
+                let conj: MyComplex<f32> = MyComplex::new(root.r(), -root.i());
+                roots.iter().position(|other| (*other - conj).mag_sqr() < 1e-10)
+            }).collect(),
+        }
     }
 
     pub fn complex_to_frac_output(&self, z_in: MyComplex<f32>) -> FracOutput {
 
-        let mut iterations: u32 = 0_u32;
         match &self.kind {
             FractalType::Mandelbrot() => {
-                // The iteration for a Mandelbrot set
-                // Iterate while the magniture is less than 2
-                let mut z: MyComplex<f32> = MyComplex::new(0.0, 0.0);
-                for i in 1..=self.max_iter {
-                    z = z*z + z_in;
-                    iterations = i;
-                    if z.mag_sqr() > 4.0_f32 { 
-                        break;
-                    }
+                // Points inside the two biggest pieces of the set can be
+                // found without iterating at all
+                let bulb_period: u32 = mandelbrot_bulb_period(z_in);
+                if bulb_period > 0 && !self.follows_orbits() {
+                    return FracOutput::MandelJulia{iters:self.max_iter, max_iters:self.max_iter, final_mag:0.0, period:bulb_period,
+                                                   distance:0.0, trap:NO_TRAP_HIT, interior:NO_INTERIOR_INFO};
                 }
-                FracOutput::MandelJulia{iters:iterations, max_iters:self.max_iter}
+                // The iteration for a Mandelbrot set
+                escape_time(MyComplex::new(0.0, 0.0), z_in, self.max_iter, &self.orbit_extras(MANDELBROT_DERIVATIVE, true))
             }
             // NOTE: since self is a borrowed value (aka immutable pass by
             // reference), z_const is a reference to an enum value. To use it,
@@ -66,15 +211,7 @@ impl Fractal {
             FractalType::Julia(z_const) => {
                 // The iteration for a Julia set (increment by z_const in the
                 // iterating algorithm)
-                let mut z: MyComplex<f32> = z_in;
-                for i in 1..=self.max_iter {
-                    z = z*z + *z_const;
-                    iterations = i;
-                    if z.mag_sqr() > 4.0 {
-                        break;
-                    }
-                }
-                FracOutput::MandelJulia{iters:iterations, max_iters:self.max_iter}
+                escape_time(z_in, *z_const, self.max_iter, &self.orbit_extras(JULIA_DERIVATIVE, false))
             }
             FractalType::Newton(roots) => {
 
@@ -101,15 +238,15 @@ impl Fractal {
                     deriv = MyComplex::new(0.0, 0.0);
 
                     // Loop over all of the roots in the polynomial
-                    for j in 0..num_of_roots {
+                    for (j, root_j) in roots.iter().enumerate() {
                         // Calculate the polynomial by multiplying each of
                         // the terms together
-                        poly_term = z - roots[j];
+                        poly_term = z - *root_j;
                         poly *= poly_term;
 
                         partial = MyComplex::new(1.0, 0.0);
-                        for k in 0..num_of_roots {
-                            if k != j { partial *= z - roots[k]; }
+                        for (k, root_k) in roots.iter().enumerate() {
+                            if k != j { partial *= z - *root_k; }
                         }
 
                         // Calculate the derivative by summing together the
@@ -126,8 +263,8 @@ impl Fractal {
                 let mut closest_root: usize = 0;
                 let mut smallest_diff: f32 = (z - roots[0]).mag_sqr();
                 let mut diff: f32;
-                for j in 0..num_of_roots {
-                    diff = (z - roots[j]).mag_sqr();
+                for (j, root) in roots.iter().enumerate() {
+                    diff = (z - *root).mag_sqr();
                     if diff < smallest_diff {
                         smallest_diff = diff;
                         closest_root = j;
@@ -139,6 +276,364 @@ impl Fractal {
             }
         }
     }
+
+    fn orbit_extras(&self, deriv: Derivative, mandelbrot: bool) -> OrbitExtras<'_> {
+        OrbitExtras {
+            deriv: if self.distance { Some(deriv) } else { None },
+            trap: self.trap.as_ref(),
+            interior: self.interior,
+            mandelbrot,
+        }
+    }
+
+    // Whether every point has to be followed through its whole orbit (so
+    // neither the bulb check nor the batched kernel can be used)
+    fn follows_orbits(&self) -> bool {
+        self.trap.is_some() || self.interior
+    }
+}
+
+// How close (squared) the orbit has to come back to a saved value for the
+// point to be counted as stuck in a cycle
+const PERIOD_EPSILON_SQR: f32 = 1e-12;
+
+// Check whether a point is inside the main cardioid or the period-2 bulb of
+// the Mandelbrot set, which never escape. Returns the period of the cycle
+// the point ends up in (1 or 2), or 0 if it's in neither.
+fn mandelbrot_bulb_period(c: MyComplex<f32>) -> u32 {
+    let y_sqr: f32 = c.i() * c.i();
+    let q: f32 = (c.r() - 0.25) * (c.r() - 0.25) + y_sqr;
+    if q * (q + (c.r() - 0.25)) <= 0.25 * y_sqr {
+        1
+    } else if (c.r() + 1.0) * (c.r() + 1.0) + y_sqr <= 0.0625 {
+        2
+    } else {
+        0
+    }
+}
+
+// The derivative of the orbit, tracked alongside it for the distance
+// estimate: dz/dc for a Mandelbrot set (starting at 0, and each step is
+// dz = 2*z*dz + 1), or dz/dz_start for a Julia set (starting at 1, and each
+// step is dz = 2*z*dz)
+#[derive(Clone, Copy)]
+struct Derivative {
+    start: f32,
+    step: f32,
+}
+
+const MANDELBROT_DERIVATIVE: Derivative = Derivative { start: 0.0, step: 1.0 };
+const JULIA_DERIVATIVE: Derivative = Derivative { start: 1.0, step: 0.0 };
+
+// The distance estimate is only accurate once 'z' is well past the escape
+// radius, so escaped orbits are followed a few more steps until |z| is past
+// this (squared)
+const DISTANCE_RADIUS_SQR: f32 = 1e6;
+const DISTANCE_EXTRA_ITERS: u32 = 8;
+
+// One step of the derivative: 2*z*dz + step
+fn derivative_step(z: MyComplex<f32>, dz: MyComplex<f32>, step: f32) -> MyComplex<f32> {
+    MyComplex::new(2.0 * (z.r()*dz.r() - z.i()*dz.i()) + step, 2.0 * (z.r()*dz.i() + z.i()*dz.r()))
+}
+
+// The exterior distance estimate 2*|z|*ln|z| / |dz| of an escaped orbit
+// that stopped at 'z' with the derivative 'dz'. Returns 0 if the derivative
+// blew up, which only happens right up against the edge of the set.
+fn exterior_distance(mut z: MyComplex<f32>, mut dz: MyComplex<f32>, z_const: MyComplex<f32>, step: f32) -> f32 {
+    for _ in 0..DISTANCE_EXTRA_ITERS {
+        if z.mag_sqr() > DISTANCE_RADIUS_SQR {
+            break;
+        }
+        dz = derivative_step(z, dz, step);
+        z = z*z + z_const;
+    }
+    let mag: f32 = z.mag_sqr().sqrt();
+    let distance: f32 = 2.0 * mag * mag.ln() / dz.mag_sqr().sqrt();
+    if distance.is_finite() { distance } else { 0.0 }
+}
+
+// How many Newton's method steps are taken to move onto a cycle exactly
+const CYCLE_NEWTON_STEPS: u32 = 8;
+
+// The attracting cycle the orbit point 'z' has settled into: its period,
+// multiplier and (for a Mandelbrot set) the interior distance estimate.
+// Near the edge of a component the orbit closes in on the cycle slowly,
+// so cycle detection can come back with the wrong length (a period-1 orbit
+// spiraling in can come back near where it was after any number of steps).
+// Every length up to `max_period` is tried, shortest first, and the first
+// one that's an attracting cycle is the one (there's only ever one).
+fn attracting_cycle(z: MyComplex<f32>, z_const: MyComplex<f32>, max_period: u32, mandelbrot: bool) -> Option<(u32, MyComplex<f32>, f32)> {
+    (1..=max_period).find_map(|period| {
+        cycle_of_length(z, z_const, period, mandelbrot).map(|(multiplier, distance)| (period, multiplier, distance))
+    })
+}
+
+// The multiplier of the cycle of length `period` the orbit point 'z' is
+// close to, and for a Mandelbrot set the interior distance estimate
+// (1 - |dz|^2) / |dcdz + dzdz*dc / (1 - dz)| from the derivatives of the
+// whole cycle (dz = d/dz, dc = d/dc). 'z' is first moved onto the cycle
+// exactly with Newton's method. Worked out in f64, since the second
+// derivatives get big. Returns None if the cycle isn't attracting.
+fn cycle_of_length(z: MyComplex<f32>, z_const: MyComplex<f32>, period: u32, mandelbrot: bool) -> Option<(MyComplex<f32>, f32)> {
+    let c: MyComplex<f64> = MyComplex::new(z_const.r() as f64, z_const.i() as f64);
+    let (zero, one, two): (MyComplex<f64>, MyComplex<f64>, MyComplex<f64>) =
+        (MyComplex::new(0.0, 0.0), MyComplex::new(1.0, 0.0), MyComplex::new(2.0, 0.0));
+
+    let mut z_cycle: MyComplex<f64> = MyComplex::new(z.r() as f64, z.i() as f64);
+    for _ in 0..CYCLE_NEWTON_STEPS {
+        let (mut z_n, mut dz): (MyComplex<f64>, MyComplex<f64>) = (z_cycle, one);
+        for _ in 0..period {
+            dz = two * z_n * dz;
+            z_n = z_n * z_n + c;
+        }
+        let step: MyComplex<f64> = (z_n - z_cycle) / (dz - one);
+        z_cycle -= step;
+        if step.mag_sqr() < 1e-24 {
+            break;
+        }
+    }
+
+    let (mut z_n, mut dz, mut dc): (MyComplex<f64>, MyComplex<f64>, MyComplex<f64>) = (z_cycle, one, zero);
+    let (mut dzdz, mut dcdz): (MyComplex<f64>, MyComplex<f64>) = (zero, zero);
+    for _ in 0..period {
+        dcdz = two * (dc * dz + z_n * dcdz);
+        dzdz = two * (dz * dz + z_n * dzdz);
+        dc = two * z_n * dc + one;
+        dz = two * z_n * dz;
+        z_n = z_n * z_n + c;
+    }
+    // Also catches a Newton's method that went off to infinity
+    if dz.mag_sqr() >= 1.0 || dz.mag_sqr().is_nan() {
+        return None;
+    }
+    let distance: f64 = if mandelbrot {
+        (1.0 - dz.mag_sqr()) / (dcdz + dzdz * dc / (one - dz)).mag_sqr().sqrt()
+    } else {
+        0.0
+    };
+    Some((MyComplex::new(dz.r() as f32, dz.i() as f32), if distance.is_finite() { distance as f32 } else { 0.0 }))
+}
+
+// What the scalar iteration records besides the escape time
+struct OrbitExtras<'a> {
+    // For the exterior distance estimate
+    deriv: Option<Derivative>,
+    trap: Option<&'a OrbitTrap>,
+    // Fill in the InteriorInfo (with the interior distance too for a
+    // Mandelbrot set)
+    interior: bool,
+    mandelbrot: bool,
+}
+
+// The Mandelbrot/Julia iteration (z = z*z + z_const). Iterate while the
+// magnitude is less than 2, stopping early if the orbit comes back around
+// to where it was (Brent's cycle detection: save 'z' after 1, 2, 4, 8, ...
+// steps and compare every new value against the saved one). With a
+// derivative, escaping points also get a distance estimate, and with a trap
+// every orbit point before the escape is measured against it.
+fn escape_time(z_start: MyComplex<f32>, z_const: MyComplex<f32>, max_iter: u32, extras: &OrbitExtras) -> FracOutput {
+    let (deriv, trap): (Option<Derivative>, Option<&OrbitTrap>) = (extras.deriv, extras.trap);
+    let mut z: MyComplex<f32> = z_start;
+    let mut trap_hit: TrapHit = NO_TRAP_HIT;
+    // The sum of the directions (unit vectors) of the orbit points
+    let mut direction_sum: MyComplex<f32> = MyComplex::new(0.0, 0.0);
+    let mut dz: MyComplex<f32> = MyComplex::new(deriv.map_or(0.0, |deriv| deriv.start), 0.0);
+    let mut iterations: u32 = 0;
+    let mut period: u32 = 0;
+
+    let mut saved: MyComplex<f32> = z_start;
+    let mut steps: u32 = 0;
+    let mut check_len: u32 = 1;
+    for i in 1..=max_iter {
+        if let Some(deriv) = deriv {
+            dz = derivative_step(z, dz, deriv.step);
+        }
+        z = z*z + z_const;
+        iterations = i;
+        if z.mag_sqr() > 4.0 {
+            break;
+        }
+        if let Some(trap) = trap {
+            let distance: f32 = trap.distance(z);
+            if distance < trap_hit.distance {
+                trap_hit = TrapHit{distance, iter: i, pos: z};
+            }
+        }
+        if extras.interior && z.mag_sqr() > 0.0 {
+            let mag: f32 = z.mag_sqr().sqrt();
+            direction_sum += MyComplex::new(z.r() / mag, z.i() / mag);
+        }
+
+        steps += 1;
+        if (z - saved).mag_sqr() < PERIOD_EPSILON_SQR {
+            // It's never going to escape
+            period = steps;
+            iterations = max_iter;
+            break;
+        }
+        if steps == check_len {
+            saved = z;
+            steps = 0;
+            check_len = check_len.saturating_mul(2);
+        }
+    }
+    let distance: f32 = match deriv {
+        Some(deriv) if z.mag_sqr() > 4.0 => exterior_distance(z, dz, z_const, deriv.step),
+        _ => 0.0,
+    };
+    let mut interior: InteriorInfo = NO_INTERIOR_INFO;
+    if extras.interior {
+        interior.angle = direction_sum.i().atan2(direction_sum.r());
+        if let Some((cycle_period, multiplier, distance)) = attracting_cycle(z, z_const, period, extras.mandelbrot) {
+            // The true period, which can be shorter than the detected one
+            period = cycle_period;
+            interior.multiplier = multiplier;
+            interior.distance = distance;
+        }
+    }
+    FracOutput::MandelJulia{iters:iterations, max_iters:max_iter, final_mag:z.mag_sqr().sqrt(), period, distance, trap:trap_hit, interior}
+}
+
+// The Mandelbrot/Julia iteration (z = z*z + z_const) on `N` lanes at once.
+// Lanes that start with a cleared `active` mask aren't iterated at all.
+// Returns the number of iterations each lane ran before stopping, the
+// period of any cycle found (the same as `escape_time`), and the value of
+// 'z' when it stopped, along with the derivative of 'z' if `DERIV` is set
+// (otherwise the derivative is left at its start value).
+// NOTE: Keeping this out of line lets the compiler keep all of the lanes in
+// registers, which makes a big difference to the speed.
+#[inline(never)]
+fn escape_time_lanes<const N: usize, const DERIV: bool>(z_start: MyComplexLanes<N>, z_const: MyComplexLanes<N>,
+                                                       mut active: [u32; N], max_iter: u32, deriv: Derivative)
+    -> ([u32; N], [u32; N], MyComplexLanes<N>, MyComplexLanes<N>)
+{
+    // Work on plain local arrays so they all stay in registers
+    let (mut z_r, mut z_i): ([f32; N], [f32; N]) = (z_start.r, z_start.i);
+    let (mut dz_r, mut dz_i): ([f32; N], [f32; N]) = ([deriv.start; N], [0.0; N]);
+    let (c_r, c_i): ([f32; N], [f32; N]) = (z_const.r, z_const.i);
+
+    // The escape mask is 1 for the lanes that are still iterating, so it can
+    // be added straight onto the iteration counts
+    let mut iterations: [u32; N] = [0; N];
+    let mut period: [u32; N] = [0; N];
+
+    // Every lane is on the same step, so they can all share one cycle
+    // detection schedule
+    let (mut saved_r, mut saved_i): ([f32; N], [f32; N]) = (z_r, z_i);
+    let mut steps: u32 = 0;
+    let mut check_len: u32 = 1;
+    for _ in 0..max_iter {
+        // Written without branches, one lane at a time, so the compiler can
+        // update all of the lanes together. The arithmetic is the same as
+        // `z*z + z_const` and `mag_sqr` on a MyComplex.
+        let mut any_active: u32 = 0;
+        for lane in 0..N {
+            let next_r: f32 = z_r[lane]*z_r[lane] - z_i[lane]*z_i[lane] + c_r[lane];
+            let next_i: f32 = z_r[lane]*z_i[lane] + z_i[lane]*z_r[lane] + c_i[lane];
+            let mag: f32 = next_r*next_r + next_i*next_i;
+            let diff_r: f32 = next_r - saved_r[lane];
+            let diff_i: f32 = next_i - saved_i[lane];
+            let escaped: bool = mag > 4.0;
+            let repeated: bool = !escaped & (diff_r*diff_r + diff_i*diff_i < PERIOD_EPSILON_SQR);
+
+            let is_active: bool = active[lane] != 0;
+            if DERIV {
+                // The same as `derivative_step`
+                let next_dz_r: f32 = 2.0 * (z_r[lane]*dz_r[lane] - z_i[lane]*dz_i[lane]) + deriv.step;
+                let next_dz_i: f32 = 2.0 * (z_r[lane]*dz_i[lane] + z_i[lane]*dz_r[lane]);
+                dz_r[lane] = if is_active { next_dz_r } else { dz_r[lane] };
+                dz_i[lane] = if is_active { next_dz_i } else { dz_i[lane] };
+            }
+            z_r[lane] = if is_active { next_r } else { z_r[lane] };
+            z_i[lane] = if is_active { next_i } else { z_i[lane] };
+            iterations[lane] += active[lane];
+            // A lane's period is still 0 while it's active
+            period[lane] |= (active[lane] & repeated as u32) * (steps + 1);
+            active[lane] &= (escaped | repeated) as u32 ^ 1;
+            any_active |= active[lane];
+        }
+
+        if any_active == 0 {
+            break;
+        }
+
+        steps += 1;
+        if steps == check_len {
+            saved_r = z_r;
+            saved_i = z_i;
+            steps = 0;
+            check_len = check_len.saturating_mul(2);
+        }
+    }
+
+    (iterations, period, MyComplexLanes { r: z_r, i: z_i }, MyComplexLanes { r: dz_r, i: dz_i })
+}
+
+// The batched versions of the fractal algorithms
+impl Fractal {
+    // Run the Mandelbrot/Julia iteration on `N` points at once. Every lane
+    // keeps iterating until all of them have stopped, but a lane stops
+    // updating (its escape mask is cleared) as soon as it escapes or is
+    // found in a cycle, so the results are exactly the same as
+    // `complex_to_frac_output`. Newton fractals, and fractals following
+    // whole orbits (for an orbit trap or InteriorInfo), just go through the
+    // scalar algorithm.
+    pub fn complex_lanes_to_frac_output<const N: usize>(&self, z_in: [MyComplex<f32>; N]) -> [FracOutput; N] {
+        if self.follows_orbits() {
+            return z_in.map(|z_val| self.complex_to_frac_output(z_val));
+        }
+        let mut bulb_period: [u32; N] = [0; N];
+        let (z_start, z_const, deriv): (MyComplexLanes<N>, MyComplexLanes<N>, Derivative) = match &self.kind {
+            FractalType::Mandelbrot() => {
+                bulb_period = z_in.map(mandelbrot_bulb_period);
+                (MyComplexLanes::splat(MyComplex::new(0.0, 0.0)), MyComplexLanes::from_complex(z_in), MANDELBROT_DERIVATIVE)
+            }
+            FractalType::Julia(z_const) => (MyComplexLanes::from_complex(z_in), MyComplexLanes::splat(*z_const), JULIA_DERIVATIVE),
+            FractalType::Newton(_) => return z_in.map(|z_val| self.complex_to_frac_output(z_val)),
+        };
+        // Lanes already known to be inside the set don't need iterating
+        let active: [u32; N] = bulb_period.map(|period| (period == 0) as u32);
+        let (iterations, period, z_end, dz_end): ([u32; N], [u32; N], MyComplexLanes<N>, MyComplexLanes<N>) = if self.distance {
+            escape_time_lanes::<N, true>(z_start, z_const, active, self.max_iter, deriv)
+        } else {
+            escape_time_lanes::<N, false>(z_start, z_const, active, self.max_iter, deriv)
+        };
+
+        let mag: [f32; N] = z_end.mag_sqr();
+        std::array::from_fn(|lane| {
+            if bulb_period[lane] > 0 {
+                FracOutput::MandelJulia{iters: self.max_iter, max_iters: self.max_iter, final_mag: 0.0, period: bulb_period[lane], distance: 0.0,
+                                        trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO}
+            } else if period[lane] > 0 {
+                FracOutput::MandelJulia{iters: self.max_iter, max_iters: self.max_iter, final_mag: mag[lane].sqrt(), period: period[lane], distance: 0.0,
+                                        trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO}
+            } else {
+                let distance: f32 = if self.distance && mag[lane] > 4.0 {
+                    exterior_distance(z_end.lane(lane), dz_end.lane(lane), z_const.lane(lane), deriv.step)
+                } else {
+                    0.0
+                };
+                FracOutput::MandelJulia{iters: iterations[lane], max_iters: self.max_iter, final_mag: mag[lane].sqrt(), period: 0, distance,
+                                        trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO}
+            }
+        })
+    }
+
+    // Run the fractal algorithm on a whole slice of points, `LANES` at a
+    // time, writing the results into `out`
+    pub fn complex_slice_to_frac_output(&self, z_in: &[MyComplex<f32>], out: &mut [FracOutput]) {
+        assert_eq!(z_in.len(), out.len(), "Need one output for every input point");
+
+        let mut z_chunks = z_in.chunks_exact(LANES);
+        let mut out_chunks = out.chunks_exact_mut(LANES);
+        for (z_chunk, out_chunk) in (&mut z_chunks).zip(&mut out_chunks) {
+            let z_lanes: [MyComplex<f32>; LANES] = z_chunk.try_into().unwrap();
+            out_chunk.copy_from_slice(&self.complex_lanes_to_frac_output(z_lanes));
+        }
+        for (z_val, out_val) in z_chunks.remainder().iter().zip(out_chunks.into_remainder()) {
+            *out_val = self.complex_to_frac_output(*z_val);
+        }
+    }
 }
 
 /*------------------------------------------------------------------------
@@ -148,9 +643,10 @@ impl Fractal {
 mod tests {
     use super::*;
     use super::super::my_complex::MyComplex;
-    // Check some known values on the Mandelbrot set, and make sure the divergence values make sense #[test]
+    // Check some known values on the Mandelbrot set, and make sure the divergence values make sense
+    #[test]
     fn mandelbrot_test () {
-        let fractal_to_test = Fractal {max_iter: 1_000_u32, kind: FractalType::Mandelbrot()};
+        let fractal_to_test = Fractal {max_iter: 1_000_u32, kind: FractalType::Mandelbrot(), distance: false, trap: None, interior: false};
 
         println!("testing...");
         let cmplx_in: [MyComplex::<f32>; 5] = [MyComplex::new(0.0, 0.0),
@@ -160,7 +656,7 @@ mod tests {
         let mut mandel_vals: [u32; 5] = [0; 5];
         let mut i: usize = 0;
         while i < 5 {
-            if let FracOutput::MandelJulia{iters:num, max_iters:_} = fractal_to_test.complex_to_frac_output(cmplx_in[i]) {
+            if let FracOutput::MandelJulia{iters:num, ..} = fractal_to_test.complex_to_frac_output(cmplx_in[i]) {
                 mandel_vals[i] = num;
             }
             i += 1;
@@ -172,7 +668,7 @@ mod tests {
     // Check some known values on a Julia set
     #[test]
     fn julia_test () {
-        let fractal_to_test = Fractal {max_iter: 1_000_u32, kind: FractalType::Julia(MyComplex::new(0.2, -0.17))};
+        let fractal_to_test = Fractal {max_iter: 1_000_u32, kind: FractalType::Julia(MyComplex::new(0.2, -0.17)), distance: false, trap: None, interior: false};
 
         let cmplx_in: [MyComplex::<f32>; 5] = [MyComplex::new(0.0, 0.0),
             MyComplex::new(1.0, 0.0), MyComplex::new(-0.3, -0.009),
@@ -181,7 +677,7 @@ mod tests {
         let mut julia_vals: [u32; 5] = [0; 5];
         let mut i: usize = 0;
         while i < 5 {
-            if let FracOutput::MandelJulia{iters:num, max_iters:_} = fractal_to_test.complex_to_frac_output(cmplx_in[i]) {
+            if let FracOutput::MandelJulia{iters:num, ..} = fractal_to_test.complex_to_frac_output(cmplx_in[i]) {
                 julia_vals[i] = num;
             }
             i += 1;
@@ -190,17 +686,25 @@ mod tests {
         assert_eq!(known_vals, julia_vals);
     }
 
-    // Check some known values on a Julia set
+    // Check some known values on a Newton fractal. The roots make the
+    // polynomial p(z) = (z - 1)(z^2 - i/2), and:
+    // - at 0, p = i/2 and p' = -i/2, so the first step lands right on 1
+    //   (root 0)
+    // - 1 is root 0 itself
+    // - -0.3-0.009i steps to 0.116-0.525i, then -0.093+0.113i, then
+    //   0.578-0.116i, and from there converges on 0.5+0.5i (root 1)
+    // - 0.75-0.1i is in the basin of the root closest to it, 1 (root 0)
+    // - 0.26 steps to 0.742+0.406i and converges on 0.5+0.5i (root 1)
     #[test]
     fn newton_test () {
         let test_roots: Vec<MyComplex<f32>> = vec![MyComplex::new(1.0, 0.0),
             MyComplex::new(0.5, 0.5), MyComplex::new(-0.5, -0.5)];
-        let fractal_to_test = Fractal {max_iter: 1_000_u32, kind: FractalType::Newton(test_roots)};
+        let fractal_to_test = Fractal {max_iter: 1_000_u32, kind: FractalType::Newton(test_roots), distance: false, trap: None, interior: false};
 
         let cmplx_in: [MyComplex::<f32>; 5] = [MyComplex::new(0.0, 0.0),
             MyComplex::new(1.0, 0.0), MyComplex::new(-0.3, -0.009),
             MyComplex::new(0.75, -0.1), MyComplex::new(0.26, 0.0)];
-        let known_vals: [usize; 5] = [2, 0, 2, 0, 2];
+        let known_vals: [usize; 5] = [0, 0, 1, 0, 1];
         let mut newton_vals: [usize; 5] = [0; 5];
         let mut i: usize = 0;
         while i < 5 {
@@ -212,4 +716,214 @@ mod tests {
 
         assert_eq!(known_vals, newton_vals);
     }
+
+    // Points inside the set should stop early with the period of the cycle
+    // they fall into, whether that's found by the cardioid/bulb check or by
+    // cycle detection
+    #[test]
+    fn interior_periods () {
+        let mandelbrot = Fractal {max_iter: 10_000_u32, kind: FractalType::Mandelbrot(), distance: false, trap: None, interior: false};
+        let julia = Fractal {max_iter: 10_000_u32, kind: FractalType::Julia(MyComplex::new(-1.0, 0.0)), distance: false, trap: None, interior: false};
+
+        let period_of = |fractal: &Fractal, z_in: MyComplex<f32>| -> u32 {
+            match fractal.complex_to_frac_output(z_in) {
+                FracOutput::MandelJulia{iters, max_iters, period, ..} => {
+                    assert_eq!(iters, max_iters);
+                    period
+                }
+                FracOutput::Newton{..} => panic!("Expected a Mandelbrot/Julia output"),
+            }
+        };
+        assert_eq!(period_of(&mandelbrot, MyComplex::new(0.0, 0.0)), 1);
+        assert_eq!(period_of(&mandelbrot, MyComplex::new(-1.1, 0.1)), 2);
+        assert_eq!(period_of(&mandelbrot, MyComplex::new(-0.12, 0.75)), 3);
+        assert_eq!(period_of(&mandelbrot, MyComplex::new(-1.76, 0.0)), 3);
+        assert_eq!(period_of(&mandelbrot, MyComplex::new(0.28, 0.53)), 4);
+        assert_eq!(period_of(&julia, MyComplex::new(0.1, 0.2)), 2);
+
+        // Escaping points never have a period
+        assert!(matches!(mandelbrot.complex_to_frac_output(MyComplex::new(0.26, 0.0)),
+                         FracOutput::MandelJulia{iters: 30, period: 0, ..}));
+    }
+
+    // Symmetric fractals should be spotted, and Newton roots matched up
+    // with their conjugates
+    #[test]
+    fn conjugate_symmetry () {
+        let conjugate_root_map = |kind: FractalType| -> Option<Vec<usize>> {
+            Fractal::new(100, kind).unwrap().conjugate_root_map()
+        };
+        assert_eq!(conjugate_root_map(FractalType::Mandelbrot()), Some(vec![]));
+        assert_eq!(conjugate_root_map(FractalType::Julia(MyComplex::new(-0.75, 0.0))), Some(vec![]));
+        assert_eq!(conjugate_root_map(FractalType::Julia(MyComplex::new(-0.75, 0.1))), None);
+        assert_eq!(conjugate_root_map(FractalType::Newton(vec![MyComplex::new(-0.5, 0.866),
+            MyComplex::new(1.0, 0.0), MyComplex::new(-0.5, -0.866)])), Some(vec![2, 1, 0]));
+        assert_eq!(conjugate_root_map(FractalType::Newton(vec![MyComplex::new(1.0, 0.0),
+            MyComplex::new(0.5, 0.5), MyComplex::new(-0.5, -0.5)])), None);
+
+        assert_eq!(FracOutput::Newton{closest: 0, roots: 3}.conjugate(&[2, 1, 0]), FracOutput::Newton{closest: 2, roots: 3});
+
+        // Traps have to be symmetric too
+        let mut fractal = Fractal::new(100, FractalType::Mandelbrot()).unwrap();
+        fractal.set_trap(Some(OrbitTrap::Circle{center: MyComplex::new(0.25, 0.0), radius: 0.5}));
+        assert_eq!(fractal.conjugate_root_map(), Some(vec![]));
+        fractal.set_trap(Some(OrbitTrap::Point(MyComplex::new(0.25, 0.5))));
+        assert_eq!(fractal.conjugate_root_map(), None);
+
+        let hit = TrapHit{distance: 0.5, iter: 4, pos: MyComplex::new(0.25, 0.5)};
+        let output = FracOutput::MandelJulia{iters: 10, max_iters: 100, final_mag: 3.0, period: 0, distance: 0.0, trap: hit, interior: NO_INTERIOR_INFO};
+        assert_eq!(output.conjugate(&[]), FracOutput::MandelJulia{iters: 10, max_iters: 100, final_mag: 3.0, period: 0, distance: 0.0,
+            trap: TrapHit{pos: MyComplex::new(0.25, -0.5), ..hit}, interior: NO_INTERIOR_INFO});
+
+        let info = InteriorInfo{angle: 1.0, multiplier: MyComplex::new(0.25, 0.5), distance: 0.01};
+        let inside = FracOutput::MandelJulia{iters: 100, max_iters: 100, final_mag: 0.5, period: 3, distance: 0.0, trap: NO_TRAP_HIT, interior: info};
+        assert_eq!(inside.conjugate(&[]), FracOutput::MandelJulia{iters: 100, max_iters: 100, final_mag: 0.5, period: 3, distance: 0.0,
+            trap: NO_TRAP_HIT, interior: InteriorInfo{angle: -1.0, multiplier: MyComplex::new(0.25, -0.5), distance: 0.01}});
+        let on_axis = FracOutput::MandelJulia{iters: 100, max_iters: 100, final_mag: 0.5, period: 0, distance: 0.0, trap: NO_TRAP_HIT,
+                                              interior: InteriorInfo{angle: std::f32::consts::PI, ..NO_INTERIOR_INFO}};
+        assert_eq!(on_axis.conjugate(&[]), on_axis);
+    }
+
+    // The batched kernels have to give exactly the same results as the
+    // scalar algorithm, including for the leftover points that don't fill a
+    // whole batch, with or without distance estimates
+    #[test]
+    fn batched_matches_scalar () {
+        let points: Vec<MyComplex<f32>> = (0..61).flat_map(|row| (0..67).map(move |col| {
+            MyComplex::new(-2.2 + col as f32 * 0.05, -1.5 + row as f32 * 0.05)
+        })).collect();
+
+        for kind in [FractalType::Mandelbrot(), FractalType::Julia(MyComplex::new(-0.8, 0.156)),
+                     FractalType::Newton(vec![MyComplex::new(1.0, 0.0), MyComplex::new(-1.0, 0.0)])] {
+            let mut fractal_to_test = Fractal {max_iter: 500_u32, kind, distance: false, trap: None, interior: false};
+            for distance in [false, true] {
+                fractal_to_test.set_distance_estimate(distance);
+                let mut batched: Vec<FracOutput> = vec![FracOutput::Newton{closest: 0, roots: 0}; points.len()];
+                fractal_to_test.complex_slice_to_frac_output(&points, &mut batched);
+
+                for (point, batched_val) in points.iter().zip(&batched) {
+                    assert_eq!(*batched_val, fractal_to_test.complex_to_frac_output(*point));
+                }
+            }
+        }
+    }
+
+    // The distance estimate should be close to the real distance from the
+    // set where that's known, and shrink towards the edge
+    #[test]
+    fn distance_estimates () {
+        let distance_of = |fractal: &Fractal, z_in: MyComplex<f32>| -> f32 {
+            match fractal.complex_to_frac_output(z_in) {
+                FracOutput::MandelJulia{distance, ..} => distance,
+                FracOutput::Newton{..} => panic!("Expected a Mandelbrot/Julia output"),
+            }
+        };
+        let mut mandelbrot = Fractal::new(1000, FractalType::Mandelbrot()).unwrap();
+        assert_eq!(distance_of(&mandelbrot, MyComplex::new(0.5, 0.0)), 0.0);
+        mandelbrot.set_distance_estimate(true);
+        // The set reaches 0.25 along the positive real axis
+        let far: f32 = distance_of(&mandelbrot, MyComplex::new(0.5, 0.0));
+        assert!(far > 0.25 / 4.0 && far < 0.25 * 4.0, "{}", far);
+        let near: f32 = distance_of(&mandelbrot, MyComplex::new(0.26, 0.0));
+        assert!(near > 0.0 && near < 0.01 * 4.0, "{}", near);
+        assert_eq!(distance_of(&mandelbrot, MyComplex::new(-0.1, 0.1)), 0.0);
+
+        // The Julia set for 0 is the unit circle
+        let mut julia = Fractal::new(1000, FractalType::Julia(MyComplex::new(0.0, 0.0))).unwrap();
+        julia.set_distance_estimate(true);
+        let distance: f32 = distance_of(&julia, MyComplex::new(1.5, 0.0));
+        assert!(distance > 0.5 / 4.0 && distance < 0.5 * 4.0, "{}", distance);
+    }
+
+    // Orbits record where they came closest to the trap, including points
+    // in the bulbs the Mandelbrot set otherwise skips iterating
+    #[test]
+    fn orbit_trap_hits () {
+        let trap_of = |fractal: &Fractal, z_in: MyComplex<f32>| -> TrapHit {
+            match fractal.complex_to_frac_output(z_in) {
+                FracOutput::MandelJulia{trap, ..} => trap,
+                FracOutput::Newton{..} => panic!("Expected a Mandelbrot/Julia output"),
+            }
+        };
+        let mut mandelbrot = Fractal::new(100, FractalType::Mandelbrot()).unwrap();
+        assert_eq!(trap_of(&mandelbrot, MyComplex::new(-1.0, 0.0)), NO_TRAP_HIT);
+        mandelbrot.set_trap(Some(OrbitTrap::Point(MyComplex::new(0.0, 0.0))));
+        // The orbit of -1 goes -1, 0, -1, 0, ...
+        assert_eq!(trap_of(&mandelbrot, MyComplex::new(-1.0, 0.0)),
+                   TrapHit{distance: 0.0, iter: 2, pos: MyComplex::new(0.0, 0.0)});
+        // 1 goes to 1, 2 and then escapes
+        assert_eq!(trap_of(&mandelbrot, MyComplex::new(1.0, 0.0)),
+                   TrapHit{distance: 1.0, iter: 1, pos: MyComplex::new(1.0, 0.0)});
+
+        let points: Vec<MyComplex<f32>> = (0..20).map(|idx| MyComplex::new(-2.0 + idx as f32 * 0.2, 0.3)).collect();
+        let mut batched: Vec<FracOutput> = vec![FracOutput::Newton{closest: 0, roots: 0}; points.len()];
+        mandelbrot.complex_slice_to_frac_output(&points, &mut batched);
+        for (point, batched_val) in points.iter().zip(&batched) {
+            assert_eq!(*batched_val, mandelbrot.complex_to_frac_output(*point));
+        }
+    }
+
+    // Points inside the set record the attracting cycle they fell into, with
+    // its shortest period even where cycle detection is thrown off by slow
+    // convergence near the edge of a component
+    #[test]
+    fn interior_info () {
+        let output_of = |fractal: &Fractal, z_in: MyComplex<f32>| -> (u32, InteriorInfo) {
+            match fractal.complex_to_frac_output(z_in) {
+                FracOutput::MandelJulia{period, interior, ..} => (period, interior),
+                FracOutput::Newton{..} => panic!("Expected a Mandelbrot/Julia output"),
+            }
+        };
+        let mut mandelbrot = Fractal::new(1000, FractalType::Mandelbrot()).unwrap();
+        assert_eq!(output_of(&mandelbrot, MyComplex::new(0.0, 0.0)).1, NO_INTERIOR_INFO);
+        mandelbrot.set_interior(true);
+
+        // 0 is the center of the main cardioid, 0.25 away from its edge
+        let (period, interior): (u32, InteriorInfo) = output_of(&mandelbrot, MyComplex::new(0.0, 0.0));
+        assert_eq!(period, 1);
+        assert_eq!(interior.multiplier, MyComplex::new(0.0, 0.0));
+        assert!(interior.distance > 0.25 / 4.0 && interior.distance < 0.25 * 4.0, "{}", interior.distance);
+        // The fixed point of 0.249 is (1 - sqrt(1 - 4c)) / 2, with a
+        // multiplier of twice that
+        let (period, interior): (u32, InteriorInfo) = output_of(&mandelbrot, MyComplex::new(0.249, 0.0));
+        assert_eq!(period, 1);
+        assert!((interior.multiplier.r() - (1.0 - 0.004_f32.sqrt())).abs() < 1e-3, "{:?}", interior.multiplier);
+        assert!(interior.distance > 0.0 && interior.distance < 0.001 * 4.0, "{}", interior.distance);
+        // The orbit of -1 goes -1, 0, -1, 0, ...
+        let (period, interior): (u32, InteriorInfo) = output_of(&mandelbrot, MyComplex::new(-1.0, 0.0));
+        assert_eq!(period, 2);
+        assert!((interior.angle.abs() - std::f32::consts::PI).abs() < 1e-6, "{}", interior.angle);
+        let (period, interior): (u32, InteriorInfo) = output_of(&mandelbrot, MyComplex::new(-1.1, 0.1));
+        assert_eq!(period, 2);
+        assert!(interior.multiplier.mag_sqr() > 0.0 && interior.multiplier.mag_sqr() < 1.0);
+        // Escaping points never have a cycle
+        let (period, interior): (u32, InteriorInfo) = output_of(&mandelbrot, MyComplex::new(0.5, 0.0));
+        assert_eq!((period, interior.multiplier, interior.distance), (0, MyComplex::new(0.0, 0.0), 0.0));
+
+        // Julia sets share one cycle, and don't get an interior distance
+        let mut julia = Fractal::new(1000, FractalType::Julia(MyComplex::new(-1.0, 0.0))).unwrap();
+        julia.set_interior(true);
+        let (period, interior): (u32, InteriorInfo) = output_of(&julia, MyComplex::new(0.1, 0.2));
+        assert_eq!((period, interior.multiplier, interior.distance), (2, MyComplex::new(0.0, 0.0), 0.0));
+
+        let points: Vec<MyComplex<f32>> = (0..20).map(|idx| MyComplex::new(-2.0 + idx as f32 * 0.2, 0.3)).collect();
+        let mut batched: Vec<FracOutput> = vec![FracOutput::Newton{closest: 0, roots: 0}; points.len()];
+        mandelbrot.complex_slice_to_frac_output(&points, &mut batched);
+        for (point, batched_val) in points.iter().zip(&batched) {
+            assert_eq!(*batched_val, mandelbrot.complex_to_frac_output(*point));
+        }
+    }
+
+    // Fractals that can't be rendered are turned down when they're made
+    #[test]
+    fn invalid_fractals () {
+        assert!(Fractal::new(0, FractalType::Mandelbrot()).is_err());
+        assert!(Fractal::new(100, FractalType::Newton(Vec::new())).is_err());
+        assert!(Fractal::new(100, FractalType::Julia(MyComplex::new(f32::NAN, 0.0))).is_err());
+
+        let mut fractal = Fractal::new(100, FractalType::Newton(vec![MyComplex::new(1.0, 0.0)])).unwrap();
+        assert!(fractal.set_kind(FractalType::Newton(vec![MyComplex::new(0.0, f32::INFINITY)])).is_err());
+        assert!(fractal.set_max_iter(0).is_err());
+        assert!(fractal.set_max_iter(50).is_ok());
+    }
 }
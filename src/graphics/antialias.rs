@@ -0,0 +1,91 @@
+use super::{Color, PixPos, color_space};
+
+// Helpers for supersampled (anti-aliased) images: averaging the colors of
+// the samples in each pixel, and finding the pixels worth supersampling.
+
+// Average colors the way light adds up, in linear light rather than sRGB,
+// so edges between light and dark areas don't come out too dark
+pub fn average_colors(colors: &[Color]) -> Color {
+    let mut sum: [f32; 3] = [0.0; 3];
+    for col in colors {
+        let lin: [f32; 3] = color_space::rgb_to_linear(*col);
+        for chan in 0..3 {
+            sum[chan] += lin[chan];
+        }
+    }
+    let count: f32 = colors.len().max(1) as f32;
+    color_space::linear_to_rgb(sum.map(|chan_sum| chan_sum / count))
+}
+
+// Mix `amount` (0 - 1) of `to` into `from`, in linear light like
+// `average_colors`
+pub fn blend_colors(from: Color, to: Color, amount: f32) -> Color {
+    let (from, to): ([f32; 3], [f32; 3]) = (color_space::rgb_to_linear(from), color_space::rgb_to_linear(to));
+    let amount: f32 = amount.clamp(0.0, 1.0);
+    color_space::linear_to_rgb(std::array::from_fn(|chan| from[chan] + (to[chan] - from[chan]) * amount))
+}
+
+// How far apart (in 8-bit sRGB levels, in any channel) the colors of two
+// neighboring pixels have to be for adaptive supersampling to count the
+// pixels as being on an edge
+pub const EDGE_THRESHOLD: u8 = 16;
+
+// The pixels (of an image of colors stored row by row) whose color differs
+// from one of the pixels left, right, above or below it by more than
+// `threshold`
+pub fn edge_pixels(colors: &[Color], width: u32, height: u32, threshold: u8) -> Vec<PixPos> {
+    let differs = |a: Color, b: Color| -> bool {
+        a.r.abs_diff(b.r) > threshold || a.g.abs_diff(b.g) > threshold || a.b.abs_diff(b.b) > threshold
+    };
+    let color = |row: u32, col: u32| -> Color { colors[(row * width + col) as usize] };
+
+    let mut edges: Vec<PixPos> = Vec::new();
+    for row in 0..height {
+        for col in 0..width {
+            let here: Color = color(row, col);
+            let on_edge: bool = (col > 0 && differs(here, color(row, col - 1)))
+                || (col + 1 < width && differs(here, color(row, col + 1)))
+                || (row > 0 && differs(here, color(row - 1, col)))
+                || (row + 1 < height && differs(here, color(row + 1, col)));
+            if on_edge {
+                edges.push(PixPos{row, col});
+            }
+        }
+    }
+    edges
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+    const WHITE: Color = Color { r: 255, g: 255, b: 255 };
+
+    // Half black and half white is half as much light as white, which is
+    // much brighter than halfway between the sRGB values
+    #[test]
+    fn average_in_linear_light() {
+        assert_eq!(average_colors(&[BLACK, WHITE]), Color { r: 188, g: 188, b: 188 });
+        assert_eq!(average_colors(&[WHITE, WHITE, WHITE, WHITE]), WHITE);
+        assert_eq!(blend_colors(BLACK, WHITE, 0.5), average_colors(&[BLACK, WHITE]));
+        assert_eq!(blend_colors(BLACK, WHITE, 2.0), WHITE);
+    }
+
+    // Only the pixels on either side of the change in color are edges
+    #[test]
+    fn finds_edge_pixels() {
+        let near_black = Color { r: 10, g: 10, b: 10 };
+        let colors: Vec<Color> = vec![
+            BLACK, near_black, WHITE, WHITE,
+            BLACK, near_black, near_black, WHITE,
+        ];
+        assert_eq!(edge_pixels(&colors, 4, 2, EDGE_THRESHOLD), vec![
+            PixPos{row: 0, col: 1}, PixPos{row: 0, col: 2}, PixPos{row: 1, col: 2},
+            PixPos{row: 1, col: 3},
+        ]);
+    }
+}
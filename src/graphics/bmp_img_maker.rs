@@ -1,4 +1,5 @@
-use std::{fs::{File, OpenOptions}, io::Write, path::Path};
+use std::{fs::{self, File, OpenOptions}, io::{BufWriter, Write}, path::Path};
+use super::super::error::{FracError, FracResult};
 use super::{Color, PixPos};
 
 // Size of BMP file header
@@ -13,12 +14,23 @@ pub struct BMPImg<'a> {
 }
 
 impl<'a> BMPImg<'a> {
-    pub fn new(width: u32, height: u32, file_name: &'a str) -> Self {
-        Self { width, height, file_name }
+    // The whole file size has to fit in the 4 bytes the BMP header has for
+    // it, and the width and height are stored as signed numbers
+    pub fn new(width: u32, height: u32, file_name: &'a str) -> FracResult<Self> {
+        if width == 0 || height == 0 {
+            return Err(FracError::InvalidInput(format!("A {}x{} image has no pixels", width, height)));
+        }
+        let row_size: u64 = (width as u64 * 3).next_multiple_of(4);
+        let file_size: u64 = (BMP_HEADER_SIZE + DIB_HEADER_SIZE) as u64 + row_size * height as u64;
+        if width > i32::MAX as u32 || height > i32::MAX as u32 || file_size > u32::MAX as u64 {
+            return Err(FracError::Format(format!("A {}x{} image is too big for a BMP file", width, height)));
+        }
+        Ok(Self { width, height, file_name })
     }
 }
 
 // A function to turn colors into a tuple of three bytes (blue, green, red)
+#[allow(non_snake_case)]
 fn Color_to_bytes(col: Color) -> [u8; 3] {
     [col.b, col.g, col.r]
 }
@@ -27,8 +39,9 @@ fn Color_to_bytes(col: Color) -> [u8; 3] {
 // function that takes a pixel-position-to-pixel-color mapping closure and
 // iterates it over all pixels.
 impl<'a> BMPImg<'a> {
-    pub fn set_header(&self) {
-        let mut pic_file = File::create(Path::new(self.file_name)).expect("Picture file creation failed");
+    pub fn set_header(&self) -> FracResult<()> {
+        let io_err = |err| FracError::io(self.file_name, err);
+        let mut pic_file = File::create(Path::new(self.file_name)).map_err(io_err)?;
 
         let mut bmp_header_data: [u8; BMP_HEADER_SIZE] = [0_u8; BMP_HEADER_SIZE];
         let mut dib_header_data: [u8; DIB_HEADER_SIZE] = [0_u8; DIB_HEADER_SIZE];
@@ -116,19 +129,186 @@ impl<'a> BMPImg<'a> {
         dib_header_data[36] = 0; dib_header_data[37] = 0;
         dib_header_data[38] = 0; dib_header_data[39] = 0;
         
-        pic_file.write_all(&bmp_header_data).expect("Failed to write the BMP header");
-        pic_file.write_all(&dib_header_data).expect("Failed to write the DIB header");
+        pic_file.write_all(&bmp_header_data).map_err(io_err)?;
+        pic_file.write_all(&dib_header_data).map_err(io_err)
     }
 
-    pub fn color_pixels(&self, pos_to_color: &dyn Fn(PixPos) -> Color) {
-        let mut pic_file = OpenOptions::new().append(true).open(Path::new(self.file_name)).expect("Failed to open BMP file to write pixels");
+    pub fn color_pixels(&self, pos_to_color: &dyn Fn(PixPos) -> Color) -> FracResult<()> {
+        let io_err = |err| FracError::io(self.file_name, err);
+        let mut pic_file = OpenOptions::new().append(true).open(Path::new(self.file_name)).map_err(io_err)?;
+
+        // Each row has to be padded out to 4 byte alignment
+        let padding: [u8; 3] = [0_u8; 3];
+        let pad_len: usize = ((4 - self.width * 3 % 4) % 4) as usize;
 
         let mut pixel: Color;
         for row in 0..self.height {
             for col in 0..self.width {
                 pixel = pos_to_color(PixPos{row, col});
-                pic_file.write_all(&Color_to_bytes(pixel)).expect("Failed to write pixels to BMP file")
+                pic_file.write_all(&Color_to_bytes(pixel)).map_err(io_err)?;
+            }
+            pic_file.write_all(&padding[..pad_len]).map_err(io_err)?;
+        }
+        Ok(())
+    }
+
+    // Write out pixels that have already been colored, in the same order
+    // `color_pixels` goes through them (left to right, bottom to top)
+    pub fn write_colors(&self, colors: &[Color]) -> FracResult<()> {
+        if colors.len() != (self.width as usize) * (self.height as usize) {
+            return Err(FracError::InvalidInput(format!("{} colors don't make up a {}x{} image", colors.len(), self.width, self.height)));
+        }
+        let io_err = |err| FracError::io(self.file_name, err);
+        let pic_file = OpenOptions::new().append(true).open(Path::new(self.file_name)).map_err(io_err)?;
+        let mut writer = BufWriter::new(pic_file);
+
+        let padding: [u8; 3] = [0_u8; 3];
+        let pad_len: usize = ((4 - self.width * 3 % 4) % 4) as usize;
+        for row in colors.chunks(self.width as usize) {
+            for color in row {
+                writer.write_all(&Color_to_bytes(*color)).map_err(io_err)?;
+            }
+            writer.write_all(&padding[..pad_len]).map_err(io_err)?;
+        }
+        writer.flush().map_err(io_err)
+    }
+
+    // Add a block of text after the pixels. The file size in the header
+    // only covers the image, so viewers skip over it, and `read_comment`
+    // finds it by starting where the header says the image ends.
+    pub fn append_comment(&self, text: &str) -> FracResult<()> {
+        let io_err = |err| FracError::io(self.file_name, err);
+        let mut pic_file = OpenOptions::new().append(true).open(Path::new(self.file_name)).map_err(io_err)?;
+        pic_file.write_all(text.as_bytes()).map_err(io_err)
+    }
+
+    // The text written after the pixels with `append_comment`, if there is
+    // any
+    pub fn read_comment(file_name: &str) -> FracResult<Option<String>> {
+        let contents: Vec<u8> = fs::read(Path::new(file_name)).map_err(|err| FracError::io(file_name, err))?;
+        if contents.len() < BMP_HEADER_SIZE || &contents[0..2] != b"BM" {
+            return Err(FracError::Format(format!("{} is not a BMP file", file_name)));
+        }
+        let file_size: usize = u32::from_le_bytes(contents[2..6].try_into().unwrap()) as usize;
+        match contents.get(file_size..) {
+            Some(comment) if !comment.is_empty() => String::from_utf8(comment.to_vec()).map(Some)
+                .map_err(|_| FracError::Format(format!("The text at the end of {} isn't UTF-8", file_name))),
+            _ => Ok(None),
+        }
+    }
+
+    // Read the pixels of an uncompressed 24 or 32 bit BMP image, in the same
+    // order `write_colors` takes them (left to right, bottom to top).
+    // Returns the width, height and colors.
+    pub fn read_colors(file_name: &str) -> FracResult<(u32, u32, Vec<Color>)> {
+        let contents: Vec<u8> = fs::read(Path::new(file_name)).map_err(|err| FracError::io(file_name, err))?;
+        let format_err = || FracError::Format(format!("{} is not an uncompressed 24 or 32 bit BMP image", file_name));
+        if contents.len() < BMP_HEADER_SIZE + DIB_HEADER_SIZE || &contents[0..2] != b"BM" {
+            return Err(format_err());
+        }
+        let u32_at = |pos: usize| -> u32 { u32::from_le_bytes(contents[pos..pos + 4].try_into().unwrap()) };
+        let u16_at = |pos: usize| -> u16 { u16::from_le_bytes(contents[pos..pos + 2].try_into().unwrap()) };
+
+        // Every DIB header since the 40 byte one starts the same way
+        let (dib_size, offset): (usize, usize) = (u32_at(14) as usize, u32_at(10) as usize);
+        let (width, height): (i32, i32) = (u32_at(18) as i32, u32_at(22) as i32);
+        let (bits, compression): (u16, u32) = (u16_at(28), u32_at(30));
+        if dib_size < DIB_HEADER_SIZE || width <= 0 || height == 0 || !(bits == 24 || bits == 32) || compression != 0 {
+            return Err(format_err());
+        }
+
+        let (width, rows): (u32, u32) = (width as u32, height.unsigned_abs());
+        let pixel_size: usize = bits as usize / 8;
+        let row_size: usize = (width as usize * pixel_size).next_multiple_of(4);
+        if offset.saturating_add(row_size.saturating_mul(rows as usize)) > contents.len() {
+            return Err(FracError::Format(format!("{} ends before all of its pixels", file_name)));
+        }
+
+        let mut colors: Vec<Color> = Vec::with_capacity(width as usize * rows as usize);
+        for row in 0..rows {
+            // A negative height means the top row is stored first
+            let stored_row: u32 = if height > 0 { row } else { rows - 1 - row };
+            let row_start: usize = offset + stored_row as usize * row_size;
+            for pixel in contents[row_start..row_start + width as usize * pixel_size].chunks(pixel_size) {
+                colors.push(Color { r: pixel[2], g: pixel[1], b: pixel[0] });
             }
         }
+        Ok((width, rows, colors))
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Images with no pixels, or too many for the BMP header, are refused
+    #[test]
+    fn image_size_limits() {
+        assert!(matches!(BMPImg::new(0, 10, "unused.bmp"), Err(FracError::InvalidInput(_))));
+        assert!(matches!(BMPImg::new(40_000, 40_000, "unused.bmp"), Err(FracError::Format(_))));
+        assert!(BMPImg::new(16_384, 16_384, "unused.bmp").is_ok());
+    }
+
+    // Rows whose width isn't a multiple of 4 bytes get padded, so the file
+    // is as long as its header says and every row starts where readers
+    // expect it to
+    #[test]
+    fn rows_are_padded() {
+        let path = std::env::temp_dir().join("fractal_generator_padded_rows.bmp");
+        let file_name: &str = path.to_str().unwrap();
+        let bmp_img_obj = BMPImg::new(5, 3, file_name).unwrap();
+        bmp_img_obj.set_header().unwrap();
+        bmp_img_obj.color_pixels(&|pos: PixPos| Color{r: pos.col as u8, g: pos.row as u8, b: 7}).unwrap();
+        assert!(matches!(bmp_img_obj.write_colors(&[Color{r: 0, g: 0, b: 0}; 14]), Err(FracError::InvalidInput(_))));
+        let bytes: Vec<u8> = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // 5 pixels of 3 bytes and 1 byte of padding per row
+        assert_eq!(bytes.len(), 54 + 3 * 16);
+        assert_eq!(u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]) as usize, bytes.len());
+        assert_eq!(bytes[54 + 15], 0);
+        assert_eq!(&bytes[54 + 16..54 + 19], &[7, 1, 0]);
+        assert_eq!(&bytes[54 + 2 * 16 + 12..54 + 2 * 16 + 15], &[7, 2, 4]);
+    }
+
+    // The comment comes back out, and images without one don't have one
+    #[test]
+    fn comment_round_trip() {
+        let path = std::env::temp_dir().join("fractal_generator_bmp_comment.bmp");
+        let file_name: &str = path.to_str().unwrap();
+
+        let bmp_img_obj = BMPImg::new(3, 2, file_name).unwrap();
+        bmp_img_obj.set_header().unwrap();
+        bmp_img_obj.write_colors(&[Color{r: 1, g: 2, b: 3}; 6]).unwrap();
+        assert_eq!(BMPImg::read_comment(file_name).unwrap(), None);
+
+        bmp_img_obj.append_comment("max_iters = 20\n").unwrap();
+        let comment: Option<String> = BMPImg::read_comment(file_name).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(comment.as_deref(), Some("max_iters = 20\n"));
+    }
+
+    // The pixels come back out in the order they were written, padding and
+    // all
+    #[test]
+    fn read_back_colors() {
+        let path = std::env::temp_dir().join("fractal_generator_bmp_colors.bmp");
+        let file_name: &str = path.to_str().unwrap();
+
+        let colors: Vec<Color> = (0..15_u8).map(|val| Color{r: val, g: val * 2, b: val * 3}).collect();
+        let bmp_img_obj = BMPImg::new(5, 3, file_name).unwrap();
+        bmp_img_obj.set_header().unwrap();
+        bmp_img_obj.write_colors(&colors).unwrap();
+        bmp_img_obj.append_comment("max_iters = 20\n").unwrap();
+        let read_back = BMPImg::read_colors(file_name);
+        std::fs::write(&path, b"BM not really an image").unwrap();
+        let not_bmp = BMPImg::read_colors(file_name);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_back.unwrap(), (5, 3, colors));
+        assert!(matches!(not_bmp, Err(FracError::Format(_))));
     }
 }
@@ -1,9 +1,13 @@
 use super::super::fractals::FracOutput;
+use super::super::orbit_trap::OrbitTrap;
+use super::color_space::oklab_to_rgb;
+use super::palette::Palette;
 use super::Color;
 
+#[allow(non_snake_case)]
 pub fn FracOutput_to_Color(val: FracOutput) -> Color {
     match val {
-        FracOutput::MandelJulia{iters:m_j_val, max_iters:max} => {
+        FracOutput::MandelJulia{iters:m_j_val, max_iters:max, ..} => {
             Color {
                 // Each color will have 10 different levels
                 r: ((max - m_j_val) as u8 % 10) * 26,
@@ -11,14 +15,308 @@ pub fn FracOutput_to_Color(val: FracOutput) -> Color {
                 b: ((max - m_j_val) as u8 % 4 + 5) * 10
             }
         },
-        FracOutput::Newton{closest:n_val, roots:num_of_roots} => {
-            match n_val {
-                0 => Color { r: 255, g: 0, b: 0 },
-                1 => Color { r: 0, g: 255, b: 0 },
-                2 => Color { r: 0, g: 0, b: 255 },
-                3 => Color { r: 0, g: 255, b: 255 },
-                _ => Color { r: 30, g: 30, b: 30 }
+        FracOutput::Newton{closest:n_val, roots:num_of_roots} => root_color(n_val, num_of_roots)
+    }
+}
+
+// A distinct color for each of `num_roots` Newton roots. The hues are spaced
+// evenly around the Oklch hue circle (so every step looks like the same
+// amount of change), and with more than six roots every other root is made
+// darker so neighboring hues can still be told apart.
+pub fn root_color(idx: usize, num_roots: usize) -> Color {
+    let num_roots: usize = num_roots.max(1);
+    let hue: f32 = 0.5 + std::f32::consts::TAU * (idx % num_roots) as f32 / num_roots as f32;
+    let (lightness, chroma): (f32, f32) = if num_roots > 6 && idx % 2 == 1 {
+        (0.55, 0.11)
+    } else {
+        (0.75, 0.13)
+    };
+    oklab_to_rgb([lightness, chroma * hue.cos(), chroma * hue.sin()])
+}
+
+// A smooth grayscale coloring: escaping points fade from black to white over
+// the course of the smooth iteration count, points inside the set are black,
+// and Newton roots are given evenly spaced gray levels.
+#[allow(non_snake_case)]
+pub fn FracOutput_to_Grayscale(val: FracOutput) -> Color {
+    let level: u8 = match val {
+        FracOutput::MandelJulia{iters, max_iters, ..} => {
+            if iters >= max_iters {
+                0
+            } else {
+                let frac: f32 = (val.smooth_iters().max(0.0) / max_iters as f32).sqrt();
+                (frac.min(1.0) * 255.0) as u8
+            }
+        },
+        FracOutput::Newton{closest, roots} => {
+            (255 * (closest + 1) / roots.max(1)) as u8
+        }
+    };
+    Color { r: level, g: level, b: level }
+}
+
+// The names of the colorizers that can be picked from the command line
+pub const COLORIZER_NAMES: [&str; 2] = ["bands", "grayscale"];
+
+// Look up a colorizer by its command line name
+pub fn colorizer_from_name(name: &str) -> Option<fn(FracOutput) -> Color> {
+    match name {
+        "bands" => Some(FracOutput_to_Color),
+        "grayscale" => Some(FracOutput_to_Grayscale),
+        _ => None,
+    }
+}
+
+// The cumulative distribution of the iteration counts of an image, used for
+// histogram-equalized coloring. Only points that escaped are counted, so the
+// colors get spread evenly over the part of the image that's outside the set
+// no matter how deep the zoom or how high the iteration limit is.
+pub struct IterHistogram {
+    // cdf[i] is the fraction of escaped pixels that took `i` or fewer
+    // iterations
+    cdf: Vec<f32>,
+}
+
+impl IterHistogram {
+    pub fn new(pixels: &[FracOutput]) -> Self {
+        let mut counts: Vec<u64> = Vec::new();
+        let mut total: u64 = 0;
+        for val in pixels {
+            if let FracOutput::MandelJulia{iters, max_iters, ..} = *val {
+                if iters < max_iters {
+                    if counts.len() <= iters as usize {
+                        counts.resize(iters as usize + 1, 0);
+                    }
+                    counts[iters as usize] += 1;
+                    total += 1;
+                }
+            }
+        }
+
+        let mut running: u64 = 0;
+        let cdf: Vec<f32> = counts.iter().map(|count| {
+            running += count;
+            running as f32 / total.max(1) as f32
+        }).collect();
+        IterHistogram { cdf }
+    }
+
+    // Where a fractal output falls in the distribution (0 - 1). The smooth
+    // iteration count is used to blend between neighboring levels so that no
+    // banding is introduced. Points inside the set and Newton outputs give 1.
+    pub fn fraction(&self, val: FracOutput) -> f32 {
+        match val {
+            FracOutput::MandelJulia{iters, max_iters, ..} if iters < max_iters => {
+                let smooth: f32 = val.smooth_iters().max(0.0);
+                let level: usize = smooth as usize;
+                let lower: f32 = if level == 0 { 0.0 } else { self.cdf_at(level - 1) };
+                let upper: f32 = self.cdf_at(level);
+                lower + (upper - lower) * smooth.fract()
+            }
+            _ => 1.0,
+        }
+    }
+
+    fn cdf_at(&self, level: usize) -> f32 {
+        self.cdf.get(level).copied().unwrap_or(1.0)
+    }
+}
+
+// The names of the ways the inside of a Mandelbrot/Julia set can be
+// colored, the same as the values of --interior
+pub const INTERIOR_NAMES: [&str; 6] = ["flat", "final-mag", "angle", "period", "distance", "multiplier"];
+
+// How to color the points inside a Mandelbrot/Julia set. Everything but
+// `Flat` needs the fractal to record InteriorInfo (Fractal::set_interior).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InteriorColoring {
+    // The one inside color of the colorizer
+    Flat,
+    // |z| when the iteration stopped
+    FinalMag,
+    // The direction of the average orbit point
+    Angle,
+    // The length of the cycle the orbit fell into
+    Period,
+    // The estimated distance to the edge of the set
+    Distance,
+    // |multiplier| of the cycle the orbit fell into, 0 at its center and 1
+    // at the edge of its component
+    Multiplier,
+}
+
+impl InteriorColoring {
+    // Look up an interior coloring by its command line name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "flat" => Some(InteriorColoring::Flat),
+            "final-mag" => Some(InteriorColoring::FinalMag),
+            "angle" => Some(InteriorColoring::Angle),
+            "period" => Some(InteriorColoring::Period),
+            "distance" => Some(InteriorColoring::Distance),
+            "multiplier" => Some(InteriorColoring::Multiplier),
+            _ => None,
+        }
+    }
+
+    // Where a point inside the set falls on a palette (0 - 1), or None for
+    // points that escaped, Newton outputs, and points there's nothing
+    // recorded about (like ones that never settled into a cycle for the
+    // modes that need one). `pixel_size` is the size of a pixel in the
+    // complex plane, which the interior distance is measured in.
+    pub fn fraction(&self, val: FracOutput, pixel_size: f32) -> Option<f32> {
+        let (final_mag, period, interior) = match val {
+            FracOutput::MandelJulia{iters, max_iters, final_mag, period, interior, ..} if max_iters > 0 && iters >= max_iters => {
+                (final_mag, period, interior)
+            }
+            _ => return None,
+        };
+        match self {
+            InteriorColoring::Flat => None,
+            // Points inside stay within 2 of the origin
+            InteriorColoring::FinalMag => Some((final_mag / 2.0).min(1.0)),
+            InteriorColoring::Angle => {
+                Some((interior.angle + std::f32::consts::PI) / std::f32::consts::TAU)
             }
+            // Each period steps by the golden ratio so that neighboring
+            // periods get well separated colors
+            InteriorColoring::Period if period > 0 => Some(((period - 1) as f32 * 0.618034).fract()),
+            // A logarithmic scale, so the distance shading keeps going from
+            // the edge of the set down to the middle of big components
+            InteriorColoring::Distance if interior.distance > 0.0 => {
+                Some(((1.0 + interior.distance / pixel_size).log2() / 8.0).min(1.0))
+            }
+            InteriorColoring::Multiplier if period > 0 => Some(interior.multiplier.mag_sqr().sqrt().min(1.0)),
+            _ => None,
+        }
+    }
+}
+
+// 'trap' coloring: points are colored by how close their orbit came to an
+// orbit trap, from the start of the palette (or white) right on the trap to
+// its end (or black) `width` or more away. With an image trap, points are
+// colored with the pixel of the picture their orbit landed on instead.
+pub struct TrapColoring {
+    trap: OrbitTrap,
+    width: f32,
+}
+
+impl TrapColoring {
+    pub fn new(trap: OrbitTrap, width: f32) -> Self {
+        TrapColoring { trap, width }
+    }
+
+    // The color of a point, or None for points that weren't checked against
+    // a trap (like Newton outputs) and ones whose orbit never landed on the
+    // picture of an image trap
+    pub fn color(&self, val: FracOutput, palette: Option<&Palette>) -> Option<Color> {
+        let hit = match val {
+            FracOutput::MandelJulia{trap, ..} if trap.distance.is_finite() => trap,
+            _ => return None,
+        };
+        if let OrbitTrap::Image{..} = self.trap {
+            return self.trap.image_color(hit.pos);
         }
+        let frac: f32 = hit.distance / self.width;
+        Some(match palette {
+            Some(palette) => palette.color_at_fraction(frac),
+            None => {
+                let level: u8 = ((1.0 - frac.min(1.0)) * 255.0) as u8;
+                Color{r: level, g: level, b: level}
+            }
+        })
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::fractals::{InteriorInfo, NO_INTERIOR_INFO};
+    use super::super::super::my_complex::MyComplex;
+    use super::super::super::orbit_trap::{TrapHit, NO_TRAP_HIT};
+
+    // Four equally common iteration counts should be spread evenly over 0 - 1
+    #[test]
+    fn histogram_spreads_evenly() {
+        let escaped = |iters: u32| -> FracOutput {
+            // A final magnitude of e^2 makes the smooth count equal to `iters`
+            FracOutput::MandelJulia{iters, max_iters: 100, final_mag: std::f32::consts::E.powi(2), period: 0, distance: 0.0, trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO}
+        };
+        let pixels: Vec<FracOutput> = vec![escaped(3), escaped(5), escaped(7), escaped(50),
+            escaped(5), escaped(3), escaped(50), escaped(7),
+            FracOutput::MandelJulia{iters: 100, max_iters: 100, final_mag: 0.5, period: 0, distance: 0.0, trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO}];
+        let histogram = IterHistogram::new(&pixels);
+
+        assert!((histogram.fraction(escaped(3)) - 0.0).abs() < 1e-6);
+        assert!((histogram.fraction(escaped(5)) - 0.25).abs() < 1e-6);
+        assert!((histogram.fraction(escaped(7)) - 0.5).abs() < 1e-6);
+        assert!((histogram.fraction(escaped(50)) - 0.75).abs() < 1e-6);
+        assert_eq!(histogram.fraction(pixels[8]), 1.0);
+    }
+
+    // Every root of a 7 root polynomial should get a clearly different color
+    #[test]
+    fn root_colors_are_distinct() {
+        use super::super::color_space::rgb_to_oklab;
+
+        for num_roots in [1, 3, 4, 7, 12] {
+            let colors: Vec<[f32; 3]> = (0..num_roots).map(|idx| rgb_to_oklab(root_color(idx, num_roots))).collect();
+            for (i, a) in colors.iter().enumerate() {
+                for b in colors.iter().skip(i + 1) {
+                    let dist: f32 = ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt();
+                    assert!(dist > 0.05, "{} roots: colors too close ({})", num_roots, dist);
+                }
+            }
+        }
+    }
+
+    // Only points inside the set get an interior color, and only when what
+    // the mode needs was recorded
+    #[test]
+    fn interior_fractions() {
+        let inside = |period: u32, interior: InteriorInfo| -> FracOutput {
+            FracOutput::MandelJulia{iters: 100, max_iters: 100, final_mag: 0.5, period, distance: 0.0, trap: NO_TRAP_HIT, interior}
+        };
+        let info = InteriorInfo{angle: 0.0, multiplier: MyComplex::new(0.3, 0.4), distance: 0.01};
+        let escaped = FracOutput::MandelJulia{iters: 5, max_iters: 100, final_mag: 10.0, period: 0, distance: 0.0,
+                                               trap: NO_TRAP_HIT, interior: info};
+
+        for name in INTERIOR_NAMES {
+            let coloring: InteriorColoring = InteriorColoring::from_name(name).unwrap();
+            assert_eq!(coloring.fraction(escaped, 0.01), None);
+            assert_eq!(coloring.fraction(FracOutput::Newton{closest: 0, roots: 3}, 0.01), None);
+        }
+        assert_eq!(InteriorColoring::Flat.fraction(inside(2, info), 0.01), None);
+        assert_eq!(InteriorColoring::FinalMag.fraction(inside(2, info), 0.01), Some(0.25));
+        assert_eq!(InteriorColoring::Angle.fraction(inside(2, info), 0.01), Some(0.5));
+        assert_eq!(InteriorColoring::Period.fraction(inside(1, info), 0.01), Some(0.0));
+        assert_eq!(InteriorColoring::Period.fraction(inside(0, info), 0.01), None);
+        assert_eq!(InteriorColoring::Distance.fraction(inside(2, info), 0.01), Some(0.125));
+        assert_eq!(InteriorColoring::Distance.fraction(inside(2, NO_INTERIOR_INFO), 0.01), None);
+        assert!((InteriorColoring::Multiplier.fraction(inside(2, info), 0.01).unwrap() - 0.5).abs() < 1e-6);
+        assert_eq!(InteriorColoring::Multiplier.fraction(inside(0, info), 0.01), None);
+        assert_eq!(InteriorColoring::from_name("nope"), None);
+    }
+
+    // Trap coloring fades out with the distance to a shape trap, and only
+    // colors the points an orbit touched the picture of an image trap at
+    #[test]
+    fn trap_colors() {
+        let escaped = |trap: TrapHit| -> FracOutput {
+            FracOutput::MandelJulia{iters: 5, max_iters: 100, final_mag: 10.0, period: 0, distance: 0.0, trap, interior: NO_INTERIOR_INFO}
+        };
+        let hit = |distance: f32, pos: MyComplex<f32>| -> TrapHit { TrapHit{distance, iter: 2, pos} };
+        let point = TrapColoring::new(OrbitTrap::Point(MyComplex::new(0.0, 0.0)), 0.5);
+        assert_eq!(point.color(escaped(hit(0.0, MyComplex::new(0.0, 0.0))), None), Some(Color{r: 255, g: 255, b: 255}));
+        assert_eq!(point.color(escaped(hit(0.25, MyComplex::new(0.25, 0.0))), None), Some(Color{r: 127, g: 127, b: 127}));
+        assert_eq!(point.color(escaped(hit(2.0, MyComplex::new(2.0, 0.0))), None), Some(Color{r: 0, g: 0, b: 0}));
+        assert_eq!(point.color(escaped(NO_TRAP_HIT), None), None);
+        assert_eq!(point.color(FracOutput::Newton{closest: 0, roots: 3}, None), None);
+
+        let palette = Palette::from_colors(&[Color{r: 10, g: 20, b: 30}]).unwrap();
+        assert_eq!(point.color(escaped(hit(0.25, MyComplex::new(0.25, 0.0))), Some(&palette)), Some(Color{r: 10, g: 20, b: 30}));
     }
 }
@@ -0,0 +1,153 @@
+use super::Color;
+
+// Conversions between 8-bit sRGB colors and the color spaces used for
+// blending colors together. All of the non-RGB spaces are stored as three
+// f32 components.
+
+// Convert one 8-bit sRGB channel to linear light (0.0 - 1.0)
+pub fn srgb_to_linear(chan: u8) -> f32 {
+    let v: f32 = chan as f32 / 255.0;
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+// Convert one linear light channel back to 8-bit sRGB, clamping anything out
+// of gamut
+pub fn linear_to_srgb(v: f32) -> u8 {
+    let v: f32 = v.clamp(0.0, 1.0);
+    let s: f32 = if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+    (s * 255.0).round() as u8
+}
+
+pub fn rgb_to_linear(col: Color) -> [f32; 3] {
+    [srgb_to_linear(col.r), srgb_to_linear(col.g), srgb_to_linear(col.b)]
+}
+
+pub fn linear_to_rgb(lin: [f32; 3]) -> Color {
+    Color { r: linear_to_srgb(lin[0]), g: linear_to_srgb(lin[1]), b: linear_to_srgb(lin[2]) }
+}
+
+// Hue in degrees (0 - 360), saturation and value (0 - 1)
+pub fn rgb_to_hsv(col: Color) -> [f32; 3] {
+    let (r, g, b) = (col.r as f32 / 255.0, col.g as f32 / 255.0, col.b as f32 / 255.0);
+    let max: f32 = r.max(g).max(b);
+    let min: f32 = r.min(g).min(b);
+    let delta: f32 = max - min;
+
+    let hue: f32 = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let sat: f32 = if max == 0.0 { 0.0 } else { delta / max };
+    [hue, sat, max]
+}
+
+pub fn hsv_to_rgb(hsv: [f32; 3]) -> Color {
+    let hue: f32 = hsv[0].rem_euclid(360.0);
+    let chroma: f32 = hsv[2] * hsv[1];
+    let x: f32 = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let m: f32 = hsv[2] - chroma;
+
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let to_u8 = |v: f32| -> u8 { ((v + m).clamp(0.0, 1.0) * 255.0).round() as u8 };
+    Color { r: to_u8(r), g: to_u8(g), b: to_u8(b) }
+}
+
+// CIE L*a*b* with a D65 white point
+const D65_WHITE: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+
+pub fn rgb_to_lab(col: Color) -> [f32; 3] {
+    let [r, g, b] = rgb_to_linear(col);
+    let xyz: [f32; 3] = [
+        0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b,
+        0.212_672_9 * r + 0.715_152_2 * g + 0.072_175_0 * b,
+        0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b,
+    ];
+
+    let f = |t: f32| -> f32 {
+        if t > 216.0 / 24_389.0 { t.cbrt() } else { (24_389.0 / 27.0 * t + 16.0) / 116.0 }
+    };
+    let fx: f32 = f(xyz[0] / D65_WHITE[0]);
+    let fy: f32 = f(xyz[1] / D65_WHITE[1]);
+    let fz: f32 = f(xyz[2] / D65_WHITE[2]);
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+pub fn lab_to_rgb(lab: [f32; 3]) -> Color {
+    let fy: f32 = (lab[0] + 16.0) / 116.0;
+    let fx: f32 = fy + lab[1] / 500.0;
+    let fz: f32 = fy - lab[2] / 200.0;
+
+    let f_inv = |t: f32| -> f32 {
+        if t.powi(3) > 216.0 / 24_389.0 { t.powi(3) } else { (116.0 * t - 16.0) * 27.0 / 24_389.0 }
+    };
+    let x: f32 = f_inv(fx) * D65_WHITE[0];
+    let y: f32 = f_inv(fy) * D65_WHITE[1];
+    let z: f32 = f_inv(fz) * D65_WHITE[2];
+
+    linear_to_rgb([
+         3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z,
+         0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+    ])
+}
+
+// Oklab (https://bottosson.github.io/posts/oklab/)
+pub fn rgb_to_oklab(col: Color) -> [f32; 3] {
+    let [r, g, b] = rgb_to_linear(col);
+    let l: f32 = (0.412_221_47 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m: f32 = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s: f32 = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+pub fn oklab_to_rgb(lab: [f32; 3]) -> Color {
+    let l: f32 = (lab[0] + 0.396_337_78 * lab[1] + 0.215_803_76 * lab[2]).powi(3);
+    let m: f32 = (lab[0] - 0.105_561_346 * lab[1] - 0.063_854_17 * lab[2]).powi(3);
+    let s: f32 = (lab[0] - 0.089_484_18 * lab[1] - 1.291_485_5 * lab[2]).powi(3);
+    linear_to_rgb([
+         4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ])
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_COLORS: [Color; 5] = [
+        Color { r: 0, g: 0, b: 0 }, Color { r: 255, g: 255, b: 255 },
+        Color { r: 255, g: 0, b: 0 }, Color { r: 12, g: 200, b: 97 },
+        Color { r: 30, g: 60, b: 250 },
+    ];
+
+    // Converting to each color space and back should give the same color
+    #[test]
+    fn color_space_round_trips() {
+        for col in TEST_COLORS {
+            assert_eq!(linear_to_rgb(rgb_to_linear(col)), col);
+            assert_eq!(hsv_to_rgb(rgb_to_hsv(col)), col);
+            assert_eq!(lab_to_rgb(rgb_to_lab(col)), col);
+            assert_eq!(oklab_to_rgb(rgb_to_oklab(col)), col);
+        }
+    }
+}
@@ -1,5 +1,26 @@
 pub mod color_mapping;
 pub mod bmp_img_maker;
+pub mod raw_img_maker;
+pub mod color_space;
+pub mod palette;
+pub mod antialias;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color { pub r:u8, pub g:u8, pub b:u8 }
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct PixPos { pub row:u32, pub col:u32 }
+
+impl Color {
+    // Parse a "#rrggbb" (or "rrggbb") hex color
+    pub fn from_hex(text: &str) -> Result<Self, String> {
+        let hex: &str = text.trim().trim_start_matches('#');
+        let chan = |idx: usize| -> Result<u8, String> {
+            hex.get(idx..idx + 2).and_then(|chan_str| u8::from_str_radix(chan_str, 16).ok())
+                .ok_or(format!("Invalid hex color: {}", text))
+        };
+        if hex.len() != 6 {
+            return Err(format!("Invalid hex color: {}", text));
+        }
+        Ok(Color { r: chan(0)?, g: chan(2)?, b: chan(4)? })
+    }
+}
@@ -0,0 +1,500 @@
+use std::{fs, path::Path};
+use super::super::error::{FracError, FracResult};
+use super::super::fractals::FracOutput;
+use super::color_space::{rgb_to_hsv, hsv_to_rgb, rgb_to_lab, lab_to_rgb, rgb_to_oklab, oklab_to_rgb};
+use super::Color;
+
+// The color space that the colors between two control points are blended in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Rgb,
+    Hsv,
+    Lab,
+    Oklab,
+}
+
+// What happens to palette positions outside of 0 - 1: either the palette
+// repeats, or the end colors are used
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaletteMapping {
+    Cyclic,
+    Clamped,
+}
+
+// A control point of a palette. `pos` runs from 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaletteStop {
+    pub pos: f32,
+    pub color: Color,
+}
+
+// A color gradient made up of any number of control points. Fractal outputs
+// are turned into a palette position with
+//     pos = offset + scale * (smooth iteration count)
+// so `scale` is the fraction of the palette covered per iteration.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    stops: Vec<PaletteStop>,
+    // The stop colors converted to the interpolation color space
+    coords: Vec<[f32; 3]>,
+    interpolation: Interpolation,
+    mapping: PaletteMapping,
+    offset: f32,
+    scale: f32,
+}
+
+// The names of the palettes that are built into the program
+pub const BUILTIN_PALETTE_NAMES: [&str; 5] = ["classic", "fire", "ocean", "grayscale", "rainbow"];
+
+impl Interpolation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(Interpolation::Rgb),
+            "hsv" => Some(Interpolation::Hsv),
+            "lab" => Some(Interpolation::Lab),
+            "oklab" => Some(Interpolation::Oklab),
+            _ => None,
+        }
+    }
+
+    fn color_to_coords(self, col: Color) -> [f32; 3] {
+        match self {
+            Interpolation::Rgb => [col.r as f32, col.g as f32, col.b as f32],
+            Interpolation::Hsv => rgb_to_hsv(col),
+            Interpolation::Lab => rgb_to_lab(col),
+            Interpolation::Oklab => rgb_to_oklab(col),
+        }
+    }
+
+    fn coords_to_color(self, coords: [f32; 3]) -> Color {
+        match self {
+            Interpolation::Rgb => Color {
+                r: coords[0].round().clamp(0.0, 255.0) as u8,
+                g: coords[1].round().clamp(0.0, 255.0) as u8,
+                b: coords[2].round().clamp(0.0, 255.0) as u8,
+            },
+            Interpolation::Hsv => hsv_to_rgb(coords),
+            Interpolation::Lab => lab_to_rgb(coords),
+            Interpolation::Oklab => oklab_to_rgb(coords),
+        }
+    }
+
+    // Blend two sets of coordinates, `frac` of the way from `a` to `b`
+    fn blend(self, a: [f32; 3], b: [f32; 3], frac: f32) -> [f32; 3] {
+        let lerp = |x: f32, y: f32| -> f32 { x + (y - x) * frac };
+        match self {
+            // Take the short way around the hue circle
+            Interpolation::Hsv => {
+                let mut hue_diff: f32 = b[0] - a[0];
+                if hue_diff > 180.0 { hue_diff -= 360.0; }
+                if hue_diff < -180.0 { hue_diff += 360.0; }
+                [a[0] + hue_diff * frac, lerp(a[1], b[1]), lerp(a[2], b[2])]
+            }
+            _ => [lerp(a[0], b[0]), lerp(a[1], b[1]), lerp(a[2], b[2])],
+        }
+    }
+}
+
+impl PaletteMapping {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cyclic" => Some(PaletteMapping::Cyclic),
+            "clamped" => Some(PaletteMapping::Clamped),
+            _ => None,
+        }
+    }
+}
+
+impl Palette {
+    // Make a palette from its control points. The points are sorted by
+    // position; there must be at least one of them, and their positions
+    // have to be finite.
+    pub fn new(mut stops: Vec<PaletteStop>) -> FracResult<Self> {
+        if stops.is_empty() {
+            return Err(FracError::InvalidInput(String::from("A palette needs at least one color")));
+        }
+        if let Some(stop) = stops.iter().find(|stop| !stop.pos.is_finite()) {
+            return Err(FracError::InvalidInput(format!("A palette color can't be at position {}", stop.pos)));
+        }
+        stops.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+
+        let mut palette = Palette {
+            stops,
+            coords: Vec::new(),
+            interpolation: Interpolation::Oklab,
+            mapping: PaletteMapping::Cyclic,
+            offset: 0.0,
+            scale: 1.0 / 32.0,
+        };
+        palette.set_interpolation(Interpolation::Oklab);
+        Ok(palette)
+    }
+
+    // Make a palette with the colors evenly spaced from 0 to 1
+    pub fn from_colors(colors: &[Color]) -> FracResult<Self> {
+        let last: f32 = (colors.len().max(2) - 1) as f32;
+        Palette::new(colors.iter().enumerate()
+            .map(|(idx, color)| PaletteStop { pos: idx as f32 / last, color: *color })
+            .collect())
+    }
+
+    pub fn set_interpolation(&mut self, val: Interpolation) {
+        self.interpolation = val;
+        self.coords = self.stops.iter().map(|stop| val.color_to_coords(stop.color)).collect();
+    }
+
+    pub fn set_mapping(&mut self, val: PaletteMapping) {
+        self.mapping = val;
+    }
+
+    pub fn set_offset(&mut self, val: f32) {
+        self.offset = val;
+    }
+
+    pub fn set_scale(&mut self, val: f32) {
+        self.scale = val;
+    }
+
+    pub fn stops(&self) -> &[PaletteStop] {
+        &self.stops
+    }
+
+    // Look up one of the palettes built into the program
+    pub fn builtin(name: &str) -> Option<Self> {
+        let rgb = |r: u8, g: u8, b: u8| -> Color { Color { r, g, b } };
+        let stop = |pos: f32, color: Color| -> PaletteStop { PaletteStop { pos, color } };
+
+        let palette: FracResult<Palette> = match name {
+            "classic" => Palette::new(vec![
+                stop(0.0, rgb(0, 7, 100)), stop(0.16, rgb(32, 107, 203)),
+                stop(0.42, rgb(237, 255, 255)), stop(0.6425, rgb(255, 170, 0)),
+                stop(0.8575, rgb(0, 2, 0)),
+            ]),
+            "fire" => Palette::from_colors(&[
+                rgb(0, 0, 0), rgb(128, 0, 0), rgb(255, 64, 0), rgb(255, 200, 0),
+                rgb(255, 255, 220), rgb(255, 200, 0), rgb(128, 0, 0), rgb(0, 0, 0),
+            ]),
+            "ocean" => Palette::from_colors(&[
+                rgb(0, 5, 40), rgb(0, 60, 120), rgb(0, 160, 170), rgb(200, 255, 250),
+                rgb(0, 160, 170), rgb(0, 60, 120), rgb(0, 5, 40),
+            ]),
+            "grayscale" => Palette::from_colors(&[rgb(0, 0, 0), rgb(255, 255, 255), rgb(0, 0, 0)]),
+            "rainbow" => Palette::from_colors(&[
+                rgb(255, 0, 0), rgb(255, 255, 0), rgb(0, 255, 0), rgb(0, 255, 255),
+                rgb(0, 0, 255), rgb(255, 0, 255), rgb(255, 0, 0),
+            ]).map(|mut palette| {
+                palette.set_interpolation(Interpolation::Hsv);
+                palette
+            }),
+            _ => return None,
+        };
+        palette.ok()
+    }
+
+    // Load a palette from a gradient file. The format is picked from the
+    // extension: GIMP gradients (.ggr), Fractint maps (.map), CSV (.csv) or
+    // JSON (.json).
+    pub fn from_file(file_name: &str) -> FracResult<Self> {
+        let parse: fn(&str) -> Result<Palette, String> = match Path::new(file_name).extension().and_then(|ext| ext.to_str()) {
+            Some("ggr") => parse_ggr,
+            Some("map") => parse_map,
+            Some("csv") => parse_csv,
+            Some("json") => parse_json,
+            _ => return Err(FracError::InvalidInput(format!("Unknown palette: {} (not a built in palette or a \
+                .ggr, .map, .csv or .json file)", file_name))),
+        };
+        let contents: String = fs::read_to_string(Path::new(file_name)).map_err(|err| FracError::io(file_name, err))?;
+        parse(&contents).map_err(|err| FracError::Format(format!("{}: {}", file_name, err)))
+    }
+
+    // Either a built in palette name or the name of a palette file
+    pub fn from_name_or_file(name: &str) -> FracResult<Self> {
+        match Palette::builtin(name) {
+            Some(palette) => Ok(palette),
+            None => Palette::from_file(name),
+        }
+    }
+
+    // The color at a palette position, after the cyclic / clamped mapping
+    pub fn color_at(&self, pos: f32) -> Color {
+        let first: &PaletteStop = &self.stops[0];
+        let last: &PaletteStop = &self.stops[self.stops.len() - 1];
+
+        let pos: f32 = match self.mapping {
+            PaletteMapping::Cyclic => pos.rem_euclid(1.0),
+            PaletteMapping::Clamped => pos.clamp(0.0, 1.0),
+        };
+
+        // Positions before the first or after the last stop either wrap
+        // around between the last and first colors, or use the end colors.
+        if pos <= first.pos || pos >= last.pos {
+            if self.mapping == PaletteMapping::Clamped || self.stops.len() == 1 {
+                return if pos <= first.pos { first.color } else { last.color };
+            }
+            let gap: f32 = first.pos + 1.0 - last.pos;
+            let dist: f32 = if pos >= last.pos { pos - last.pos } else { pos + 1.0 - last.pos };
+            let frac: f32 = if gap > 0.0 { dist / gap } else { 0.0 };
+            return self.blend_stops(self.stops.len() - 1, 0, frac);
+        }
+
+        // Find the pair of stops that the position falls between
+        let upper: usize = self.stops.partition_point(|stop| stop.pos <= pos);
+        let lower: usize = upper - 1;
+        let gap: f32 = self.stops[upper].pos - self.stops[lower].pos;
+        let frac: f32 = if gap > 0.0 { (pos - self.stops[lower].pos) / gap } else { 0.0 };
+        self.blend_stops(lower, upper, frac)
+    }
+
+    // The color for a fraction (0 - 1) of the way through the palette, with
+    // the palette offset applied. Used for colorings that already normalize
+    // their values, like histogram coloring.
+    pub fn color_at_fraction(&self, frac: f32) -> Color {
+        self.color_at(self.offset + frac)
+    }
+
+    fn blend_stops(&self, a: usize, b: usize, frac: f32) -> Color {
+        self.interpolation.coords_to_color(self.interpolation.blend(self.coords[a], self.coords[b], frac))
+    }
+
+    // Color a fractal output. Escaping points are colored by their smooth
+    // iteration count, points inside the set are black, and Newton roots are
+    // spread evenly over the palette.
+    #[allow(non_snake_case)]
+    pub fn FracOutput_to_Color(&self, val: FracOutput) -> Color {
+        match val {
+            FracOutput::MandelJulia{iters, max_iters, ..} => {
+                if iters >= max_iters {
+                    Color { r: 0, g: 0, b: 0 }
+                } else {
+                    self.color_at(self.offset + self.scale * val.smooth_iters())
+                }
+            }
+            FracOutput::Newton{closest, roots} => {
+                self.color_at(self.offset + (closest as f32 + 0.5) / roots.max(1) as f32)
+            }
+        }
+    }
+}
+
+/*------------------------------------------------------------------------
+                            PALETTE FILE FORMATS
+------------------------------------------------------------------------*/
+fn parse_num<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.trim().parse::<T>().map_err(|_| format!("Invalid number in palette file: {}", text))
+}
+
+// A GIMP gradient is a list of segments, each with a left, middle and right
+// position and a left and right RGBA color (as 0 - 1 floats). The blending
+// and coloring types of each segment are ignored; every segment is turned
+// into three control points.
+fn parse_ggr(contents: &str) -> Result<Palette, String> {
+    let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some("GIMP Gradient") {
+        return Err(String::from("Not a GIMP gradient file"));
+    }
+
+    let mut stops: Vec<PaletteStop> = Vec::new();
+    let to_u8 = |v: f32| -> u8 { (v.clamp(0.0, 1.0) * 255.0).round() as u8 };
+    for line in lines.filter(|line| !line.starts_with("Name:")).skip(1) {
+        let vals: Vec<f32> = line.split_whitespace().take(11).map(parse_num).collect::<Result<_, _>>()?;
+        if vals.len() < 11 {
+            return Err(format!("Invalid GIMP gradient segment: {}", line));
+        }
+
+        let left: Color = Color { r: to_u8(vals[3]), g: to_u8(vals[4]), b: to_u8(vals[5]) };
+        let right: Color = Color { r: to_u8(vals[7]), g: to_u8(vals[8]), b: to_u8(vals[9]) };
+        let middle: Color = Color {
+            r: to_u8((vals[3] + vals[7]) / 2.0),
+            g: to_u8((vals[4] + vals[8]) / 2.0),
+            b: to_u8((vals[5] + vals[9]) / 2.0),
+        };
+        stops.push(PaletteStop { pos: vals[0], color: left });
+        stops.push(PaletteStop { pos: vals[1], color: middle });
+        stops.push(PaletteStop { pos: vals[2], color: right });
+    }
+
+    if stops.is_empty() {
+        return Err(String::from("The GIMP gradient has no segments"));
+    }
+    Palette::new(stops).map_err(|err| err.to_string())
+}
+
+// A Fractint map is a list of "r g b" lines (usually 256 of them), with
+// anything after the third number treated as a comment.
+fn parse_map(contents: &str) -> Result<Palette, String> {
+    let mut colors: Vec<Color> = Vec::new();
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let vals: Vec<u8> = line.split_whitespace().take(3).map(parse_num).collect::<Result<_, _>>()?;
+        if vals.len() < 3 {
+            return Err(format!("Invalid Fractint map line: {}", line));
+        }
+        colors.push(Color { r: vals[0], g: vals[1], b: vals[2] });
+    }
+
+    if colors.is_empty() {
+        return Err(String::from("The Fractint map has no colors"));
+    }
+    let mut palette = Palette::from_colors(&colors).map_err(|err| err.to_string())?;
+    palette.set_interpolation(Interpolation::Rgb);
+    Ok(palette)
+}
+
+// Each CSV line is one of "#rrggbb", "pos,#rrggbb", "r,g,b" or "pos,r,g,b".
+// Lines starting with '#' followed by a space are comments. Colors without a
+// position are spread evenly over the palette.
+fn parse_csv(contents: &str) -> Result<Palette, String> {
+    let mut entries: Vec<(Option<f32>, Color)> = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("# ") || line == "#" {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let entry: (Option<f32>, Color) = match fields.len() {
+            1 => (None, Color::from_hex(fields[0])?),
+            2 => (Some(parse_num(fields[0])?), Color::from_hex(fields[1])?),
+            3 => (None, Color { r: parse_num(fields[0])?, g: parse_num(fields[1])?, b: parse_num(fields[2])? }),
+            4 => (Some(parse_num(fields[0])?),
+                  Color { r: parse_num(fields[1])?, g: parse_num(fields[2])?, b: parse_num(fields[3])? }),
+            _ => return Err(format!("Invalid palette CSV line: {}", line)),
+        };
+        entries.push(entry);
+    }
+
+    palette_from_entries(entries)
+}
+
+// A JSON palette is either a list of colors, or an object with a "stops"
+// list and optional "interpolation" and "mapping" names. Colors are
+// "#rrggbb" strings or [r, g, b] lists, and stops are either plain colors or
+// {"pos": 0.5, "color": ...} objects.
+fn parse_json(contents: &str) -> Result<Palette, String> {
+    use serde_json::Value;
+
+    let json_color = |val: &Value| -> Result<Color, String> {
+        match val {
+            Value::String(hex) => Color::from_hex(hex),
+            Value::Array(chans) if chans.len() == 3 => {
+                let chan = |idx: usize| -> Result<u8, String> {
+                    chans[idx].as_u64().filter(|v| *v <= 255).map(|v| v as u8)
+                        .ok_or(format!("Invalid color in palette JSON: {}", val))
+                };
+                Ok(Color { r: chan(0)?, g: chan(1)?, b: chan(2)? })
+            }
+            _ => Err(format!("Invalid color in palette JSON: {}", val)),
+        }
+    };
+
+    let root: Value = serde_json::from_str(contents).map_err(|err| format!("Invalid palette JSON: {}", err))?;
+    let stops: &Vec<Value> = match &root {
+        Value::Array(stops) => stops,
+        Value::Object(obj) => obj.get("stops").and_then(Value::as_array)
+            .ok_or(String::from("The palette JSON has no \"stops\" list"))?,
+        _ => return Err(String::from("The palette JSON must be a list or an object")),
+    };
+
+    let mut entries: Vec<(Option<f32>, Color)> = Vec::new();
+    for stop in stops {
+        entries.push(match stop.get("color") {
+            Some(color) => (stop.get("pos").and_then(Value::as_f64).map(|pos| pos as f32), json_color(color)?),
+            None => (None, json_color(stop)?),
+        });
+    }
+    let mut palette: Palette = palette_from_entries(entries)?;
+
+    if let Some(name) = root.get("interpolation").and_then(Value::as_str) {
+        palette.set_interpolation(Interpolation::from_name(name)
+            .ok_or(format!("Unknown interpolation in palette JSON: {}", name))?);
+    }
+    if let Some(name) = root.get("mapping").and_then(Value::as_str) {
+        palette.set_mapping(PaletteMapping::from_name(name)
+            .ok_or(format!("Unknown mapping in palette JSON: {}", name))?);
+    }
+    Ok(palette)
+}
+
+// Build a palette from colors that may or may not have explicit positions.
+// Colors without a position are spaced evenly by their index.
+fn palette_from_entries(entries: Vec<(Option<f32>, Color)>) -> Result<Palette, String> {
+    if entries.is_empty() {
+        return Err(String::from("The palette has no colors"));
+    }
+
+    let last: f32 = (entries.len().max(2) - 1) as f32;
+    Palette::new(entries.into_iter().enumerate()
+        .map(|(idx, (pos, color))| PaletteStop { pos: pos.unwrap_or(idx as f32 / last), color })
+        .collect()).map_err(|err| err.to_string())
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+    const WHITE: Color = Color { r: 255, g: 255, b: 255 };
+
+    #[test]
+    fn palette_endpoints_and_midpoint() {
+        let mut palette = Palette::from_colors(&[BLACK, WHITE]).unwrap();
+        palette.set_interpolation(Interpolation::Rgb);
+        palette.set_mapping(PaletteMapping::Clamped);
+
+        assert_eq!(palette.color_at(0.0), BLACK);
+        assert_eq!(palette.color_at(1.0), WHITE);
+        assert_eq!(palette.color_at(-3.0), BLACK);
+        assert_eq!(palette.color_at(7.0), WHITE);
+        assert_eq!(palette.color_at(0.5), Color { r: 128, g: 128, b: 128 });
+    }
+
+    // A cyclic palette repeats, and blends from the last stop back into the
+    // first one
+    #[test]
+    fn palette_cyclic_wrap() {
+        let mut palette = Palette::new(vec![
+            PaletteStop { pos: 0.25, color: BLACK }, PaletteStop { pos: 0.75, color: WHITE },
+        ]).unwrap();
+        palette.set_interpolation(Interpolation::Rgb);
+
+        assert_eq!(palette.color_at(0.25), palette.color_at(1.25));
+        assert_eq!(palette.color_at(0.0), Color { r: 128, g: 128, b: 128 });
+        assert_eq!(palette.color_at(0.5), palette.color_at(-0.5));
+    }
+
+    #[test]
+    fn builtin_palettes_exist() {
+        for name in BUILTIN_PALETTE_NAMES {
+            assert!(Palette::builtin(name).is_some());
+        }
+        assert!(Palette::builtin("no-such-palette").is_none());
+        assert!(Palette::new(Vec::new()).is_err());
+        assert!(Palette::from_colors(&[]).is_err());
+    }
+
+    #[test]
+    fn parse_palette_files() {
+        let ggr: &str = "GIMP Gradient\nName: Test\n1\n\
+                         0.0 0.5 1.0 0 0 0 1 1 1 1 1 0 0\n";
+        let palette: Palette = parse_ggr(ggr).unwrap();
+        assert_eq!(palette.stops().len(), 3);
+        assert_eq!(palette.stops()[2].color, WHITE);
+
+        let map: &str = "0 0 0 black\n255 255 255 white\n";
+        assert_eq!(parse_map(map).unwrap().stops()[1], PaletteStop { pos: 1.0, color: WHITE });
+
+        let csv: &str = "# a comment\n0.0,#000000\n0.3,255,255,255\n";
+        assert_eq!(parse_csv(csv).unwrap().stops()[1], PaletteStop { pos: 0.3, color: WHITE });
+
+        let json: &str = r##"{"interpolation": "rgb", "stops": ["#000000", {"pos": 0.8, "color": [255, 255, 255]}]}"##;
+        let palette: Palette = parse_json(json).unwrap();
+        assert_eq!(palette.stops()[1], PaletteStop { pos: 0.8, color: WHITE });
+        assert_eq!(palette.interpolation, Interpolation::Rgb);
+
+        assert!(parse_csv("1,2\n").is_err());
+        assert!(parse_json("{}").is_err());
+        assert!(parse_csv("-nan,#ff0000\n0.5,#00ff00\n1,#0000ff\n").is_err());
+        assert!(parse_csv("0,#ff0000\ninf,#00ff00\n").is_err());
+    }
+}
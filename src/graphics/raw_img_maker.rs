@@ -0,0 +1,424 @@
+use std::{fs::File, io::{BufReader, BufWriter, Read, Write}, path::Path};
+use super::super::error::{FracError, FracResult};
+use super::super::fractals::{FracOutput, InteriorInfo, NO_INTERIOR_INFO};
+use super::super::my_complex::MyComplex;
+use super::super::orbit_trap::{TrapHit, NO_TRAP_HIT};
+use super::super::render::FracImage;
+use super::PixPos;
+
+// The raw output is written as a NumPy `.npy` file (format version 1.0) so
+// it can be loaded directly with `numpy.load`. The array has the shape
+// (height, width), with row 0 being the bottom row of the image (the same
+// order the BMP pixels are written in), and every element is a record with
+// the following little-endian fields:
+//
+//   iters              u32  Iterations before escaping (0 for Newton fractals)
+//   max_iters          u32  The iteration limit the fractal was rendered with
+//   smooth             f32  Continuous iteration count (see
+//                           FracOutput::smooth_iters)
+//   final_mag          f32  |z| when the iteration stopped (0 for Newton
+//                           fractals)
+//   root               i32  Index of the closest root (-1 for Mandelbrot/Julia)
+//   num_roots          u32  Number of roots of the Newton polynomial (0
+//                           otherwise)
+//   period             u32  Length of the cycle an interior point fell into
+//                           (0 if it escaped, wasn't found in a cycle, or for
+//                           Newton)
+//   distance           f32  Estimated distance to the edge of the set, in the
+//                           units of the complex plane (0 if it wasn't
+//                           estimated, for interior points, or for Newton)
+//   trap_distance      f32  The closest the orbit came to the orbit trap (inf
+//                           without a trap, or for Newton)
+//   trap_iter          u32  The iteration it came closest on
+//   trap_real          f32  Where the orbit was then
+//   trap_imag          f32
+//   angle              f32  The direction of the average orbit point, in
+//                           radians (see InteriorInfo; it and the rest are 0
+//                           if they weren't recorded, or for Newton)
+//   multiplier_real    f32  The multiplier of the cycle an interior point
+//   multiplier_imag    f32  fell into
+//   interior_distance  f32  Estimated distance from an interior point to the
+//                           edge of the set
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+const NPY_FIELDS: [(&str, &str); 16] = [
+    ("iters", "<u4"), ("max_iters", "<u4"), ("smooth", "<f4"), ("final_mag", "<f4"), ("root", "<i4"),
+    ("num_roots", "<u4"), ("period", "<u4"), ("distance", "<f4"), ("trap_distance", "<f4"),
+    ("trap_iter", "<u4"), ("trap_real", "<f4"), ("trap_imag", "<f4"), ("angle", "<f4"),
+    ("multiplier_real", "<f4"), ("multiplier_imag", "<f4"), ("interior_distance", "<f4"),
+];
+// Files written by older versions of the program only have some of the
+// fields. Every version wrote at least this many of the first ones.
+const NPY_REQUIRED_FIELDS: usize = 6;
+// Size of a single pixel record in bytes
+const NPY_RECORD_SIZE: usize = 4 * NPY_FIELDS.len();
+
+pub struct RawImg<'a> {
+    width: u32,
+    height: u32,
+    file_name: &'a str,
+}
+
+impl<'a> RawImg<'a> {
+    pub fn new(width: u32, height: u32, file_name: &'a str) -> Self {
+        Self { width, height, file_name }
+    }
+}
+
+// The NumPy type of the records, e.g. [('iters', '<u4'), ...]
+fn npy_descr() -> String {
+    let fields: Vec<String> = NPY_FIELDS.iter().map(|(name, kind)| format!("('{}', '{}')", name, kind)).collect();
+    format!("[{}]", fields.join(", "))
+}
+
+// Build the full `.npy` header (magic string, version, header length and the
+// header dictionary), padded so the pixel data starts on a 64 byte boundary.
+fn npy_header(descr: &str, width: u32, height: u32) -> Vec<u8> {
+    let mut dict: String = format!(
+        "{{'descr': {}, 'fortran_order': False, 'shape': ({}, {}), }}",
+        descr, height, width
+    );
+
+    // magic (6by) + version (2by) + header length (2by) + dict + '\n'
+    let unpadded_len: usize = NPY_MAGIC.len() + 4 + dict.len() + 1;
+    let padding: usize = (64 - unpadded_len % 64) % 64;
+    dict.push_str(&" ".repeat(padding));
+    dict.push('\n');
+
+    let mut header: Vec<u8> = Vec::with_capacity(unpadded_len + padding);
+    header.extend_from_slice(NPY_MAGIC);
+    header.extend_from_slice(&[1_u8, 0_u8]);
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
+
+// A function to turn a fractal output into the bytes of one `.npy` record
+#[allow(non_snake_case)]
+fn FracOutput_to_bytes(val: FracOutput) -> [u8; NPY_RECORD_SIZE] {
+    let (iters, max_iters, final_mag, root, num_roots, period, distance, trap, interior):
+        (u32, u32, f32, i32, u32, u32, f32, TrapHit, InteriorInfo) = match val {
+        FracOutput::MandelJulia{iters, max_iters, final_mag, period, distance, trap, interior} => {
+            (iters, max_iters, final_mag, -1, 0, period, distance, trap, interior)
+        }
+        FracOutput::Newton{closest, roots} => (0, 0, 0.0, closest as i32, roots as u32, 0, 0.0, NO_TRAP_HIT, NO_INTERIOR_INFO),
+    };
+
+    let mut bytes: [u8; NPY_RECORD_SIZE] = [0_u8; NPY_RECORD_SIZE];
+    bytes[0..4].copy_from_slice(&iters.to_le_bytes());
+    bytes[4..8].copy_from_slice(&max_iters.to_le_bytes());
+    bytes[8..12].copy_from_slice(&val.smooth_iters().to_le_bytes());
+    bytes[12..16].copy_from_slice(&final_mag.to_le_bytes());
+    bytes[16..20].copy_from_slice(&root.to_le_bytes());
+    bytes[20..24].copy_from_slice(&num_roots.to_le_bytes());
+    bytes[24..28].copy_from_slice(&period.to_le_bytes());
+    bytes[28..32].copy_from_slice(&distance.to_le_bytes());
+    bytes[32..36].copy_from_slice(&trap.distance.to_le_bytes());
+    bytes[36..40].copy_from_slice(&trap.iter.to_le_bytes());
+    bytes[40..44].copy_from_slice(&trap.pos.r().to_le_bytes());
+    bytes[44..48].copy_from_slice(&trap.pos.i().to_le_bytes());
+    bytes[48..52].copy_from_slice(&interior.angle.to_le_bytes());
+    bytes[52..56].copy_from_slice(&interior.multiplier.r().to_le_bytes());
+    bytes[56..60].copy_from_slice(&interior.multiplier.i().to_le_bytes());
+    bytes[60..64].copy_from_slice(&interior.distance.to_le_bytes());
+    bytes
+}
+
+// The inverse of FracOutput_to_bytes. Fails on a Newton record whose root
+// isn't one of the polynomial's roots.
+#[allow(non_snake_case)]
+fn bytes_to_FracOutput(bytes: &[u8]) -> Result<FracOutput, String> {
+    let field = |start: usize| -> [u8; 4] { bytes[start..start + 4].try_into().unwrap() };
+
+    let num_roots: u32 = u32::from_le_bytes(field(20));
+    if num_roots > 0 {
+        let root: i32 = i32::from_le_bytes(field(16));
+        if root < 0 || root as u32 >= num_roots {
+            return Err(format!("its root {} isn't one of its {} roots", root, num_roots));
+        }
+        Ok(FracOutput::Newton {
+            closest: root as usize,
+            roots: num_roots as usize,
+        })
+    } else {
+        Ok(FracOutput::MandelJulia {
+            iters: u32::from_le_bytes(field(0)),
+            max_iters: u32::from_le_bytes(field(4)),
+            final_mag: f32::from_le_bytes(field(12)),
+            period: u32::from_le_bytes(field(24)),
+            distance: f32::from_le_bytes(field(28)),
+            trap: TrapHit {
+                distance: f32::from_le_bytes(field(32)),
+                iter: u32::from_le_bytes(field(36)),
+                pos: MyComplex::new(f32::from_le_bytes(field(40)), f32::from_le_bytes(field(44))),
+            },
+            interior: InteriorInfo {
+                angle: f32::from_le_bytes(field(48)),
+                multiplier: MyComplex::new(f32::from_le_bytes(field(52)), f32::from_le_bytes(field(56))),
+                distance: f32::from_le_bytes(field(60)),
+            },
+        })
+    }
+}
+
+// Where the fields of NPY_FIELDS are in the records of a file being read
+// (None for the ones it doesn't have), and how long its records are
+struct NpyLayout {
+    offsets: Vec<Option<usize>>,
+    record_size: usize,
+}
+
+impl NpyLayout {
+    // Copy the fields of a record read from the file into `current`, a
+    // record laid out the way this version writes them. The fields the file
+    // doesn't have are left as they were.
+    fn to_current(&self, record: &[u8], current: &mut [u8; NPY_RECORD_SIZE]) {
+        for (idx, offset) in self.offsets.iter().enumerate() {
+            if let Some(start) = *offset {
+                current[idx * 4..idx * 4 + 4].copy_from_slice(&record[start..start + 4]);
+            }
+        }
+    }
+}
+
+// The text in `dict` between `start` and the next `end`
+fn npy_entry<'d>(dict: &'d str, start: &str, end: char) -> Option<&'d str> {
+    let entry_start: usize = dict.find(start)? + start.len();
+    let entry_end: usize = entry_start + dict[entry_start..].find(end)?;
+    Some(&dict[entry_start..entry_end])
+}
+
+// Pull the (height, width) shape and the layout of the records out of a
+// `.npy` header dictionary. The fields are found by name, so files written
+// by older versions of the program can be read too, and fields that aren't
+// known are skipped.
+fn parse_npy_header(dict: &str) -> Result<(u32, u32, NpyLayout), String> {
+    if !dict.contains("'fortran_order': False") {
+        return Err(String::from("its pixels aren't stored a row at a time"));
+    }
+
+    let shape: &str = npy_entry(dict, "'shape': (", ')').ok_or("it doesn't have a shape")?;
+    let dims: Vec<u32> = shape.split(',').map(str::trim).filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<u32>()).collect::<Result<_, _>>().map_err(|_| format!("its shape ({}) isn't a size", shape))?;
+    let (height, width): (u32, u32) = match dims[..] {
+        [height, width] => (height, width),
+        _ => return Err(format!("its shape ({}) isn't two dimensional", shape)),
+    };
+
+    let descr: &str = npy_entry(dict, "'descr': [", ']').ok_or("its records don't have named fields")?;
+    let mut layout = NpyLayout { offsets: vec![None; NPY_FIELDS.len()], record_size: 0 };
+    for field in descr.split(')').map(|field| field.trim_start_matches([',', ' ', '('])).filter(|field| !field.is_empty()) {
+        let parts: Vec<&str> = field.split(',').map(|part| part.trim().trim_matches('\'')).collect();
+        let (name, kind): (&str, &str) = match parts[..] {
+            [name, kind] => (name, kind),
+            _ => return Err(format!("its field ({}) can't be read", field)),
+        };
+        let size: usize = kind.get(2..).and_then(|size| size.parse().ok())
+            .ok_or_else(|| format!("the type of its '{}' field ({}) can't be read", name, kind))?;
+        match NPY_FIELDS.iter().position(|(known, _)| *known == name) {
+            Some(idx) if NPY_FIELDS[idx].1 == kind => layout.offsets[idx] = Some(layout.record_size),
+            Some(idx) => return Err(format!("its '{}' field is '{}' instead of '{}'", name, kind, NPY_FIELDS[idx].1)),
+            None => (),
+        }
+        layout.record_size += size;
+    }
+    if let Some(idx) = layout.offsets[..NPY_REQUIRED_FIELDS].iter().position(Option::is_none) {
+        return Err(format!("it doesn't have an '{}' field", NPY_FIELDS[idx].0));
+    }
+    Ok((width, height, layout))
+}
+
+impl<'a> RawImg<'a> {
+    // Read a file written by `write_pixels` back in as an image of fractal
+    // outputs
+    pub fn read_image(file_name: &str) -> FracResult<FracImage> {
+        let io_err = |err| FracError::io(file_name, err);
+        let pic_file = File::open(Path::new(file_name)).map_err(io_err)?;
+        let file_len: u64 = pic_file.metadata().map_err(io_err)?.len();
+        let mut reader = BufReader::new(pic_file);
+
+        let mut preamble: [u8; 10] = [0_u8; 10];
+        reader.read_exact(&mut preamble).map_err(io_err)?;
+        if &preamble[0..6] != NPY_MAGIC || preamble[6] != 1 {
+            return Err(FracError::Format(format!("{} is not a version 1 NPY file", file_name)));
+        }
+
+        let dict_len: usize = u16::from_le_bytes([preamble[8], preamble[9]]) as usize;
+        let mut dict: Vec<u8> = vec![0_u8; dict_len];
+        reader.read_exact(&mut dict).map_err(io_err)?;
+        let format_err = |err: String| FracError::Format(format!("{} can't be read as fractal data: {}", file_name, err));
+        let (width, height, layout): (u32, u32, NpyLayout) = parse_npy_header(&String::from_utf8_lossy(&dict)).map_err(format_err)?;
+
+        // Check the header's shape against the size of the file before
+        // making room for the pixels
+        let data_len: Option<u64> = (width as u64).checked_mul(height as u64)
+            .and_then(|num_pixels| num_pixels.checked_mul(layout.record_size as u64));
+        if data_len.is_none_or(|data_len| data_len > file_len.saturating_sub((preamble.len() + dict_len) as u64)) {
+            return Err(FracError::Format(format!("{} ends before all of its pixels", file_name)));
+        }
+
+        // The fields missing from older files are read as if the fractal
+        // didn't record them
+        let mut current: [u8; NPY_RECORD_SIZE] = FracOutput_to_bytes(FracOutput::MandelJulia {
+            iters: 0, max_iters: 0, final_mag: 0.0, period: 0, distance: 0.0, trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO,
+        });
+        let mut pixels: Vec<FracOutput> = Vec::with_capacity((width as usize) * (height as usize));
+        let mut record: Vec<u8> = vec![0_u8; layout.record_size];
+        for _ in 0..(width as usize) * (height as usize) {
+            reader.read_exact(&mut record).map_err(io_err)?;
+            layout.to_current(&record, &mut current);
+            pixels.push(bytes_to_FracOutput(&current).map_err(format_err)?);
+        }
+
+        FracImage::new(width, height, pixels)
+    }
+
+    // Write the header, then run the pixel-position-to-fractal-output closure
+    // over all pixels and write each record out in order.
+    pub fn write_pixels(&self, pos_to_output: &dyn Fn(PixPos) -> FracOutput) -> FracResult<()> {
+        let io_err = |err| FracError::io(self.file_name, err);
+        let pic_file = File::create(Path::new(self.file_name)).map_err(io_err)?;
+        let mut writer = BufWriter::new(pic_file);
+
+        writer.write_all(&npy_header(&npy_descr(), self.width, self.height)).map_err(io_err)?;
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let val: FracOutput = pos_to_output(PixPos{row, col});
+                writer.write_all(&FracOutput_to_bytes(val)).map_err(io_err)?;
+            }
+        }
+        writer.flush().map_err(io_err)
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The pixel data of a `.npy` file has to start on an aligned boundary
+    #[test]
+    fn npy_header_alignment() {
+        for (width, height) in [(1, 1), (640, 480), (16_384, 16_384)] {
+            let header: Vec<u8> = npy_header(&npy_descr(), width, height);
+            assert_eq!(header.len() % 64, 0);
+            assert_eq!(&header[0..6], NPY_MAGIC);
+            assert_eq!(*header.last().unwrap(), b'\n');
+        }
+    }
+
+    #[test]
+    fn npy_record_layout() {
+        let bytes = FracOutput_to_bytes(FracOutput::Newton{closest: 3, roots: 7});
+        assert_eq!(&bytes[0..4], &0_u32.to_le_bytes());
+        assert_eq!(&bytes[16..20], &3_i32.to_le_bytes());
+        assert_eq!(&bytes[20..24], &7_u32.to_le_bytes());
+
+        let bytes = FracOutput_to_bytes(FracOutput::MandelJulia{iters: 100, max_iters: 100, final_mag: 0.1, period: 3, distance: 0.25,
+                                                                 trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO});
+        assert_eq!(&bytes[0..4], &100_u32.to_le_bytes());
+        assert_eq!(&bytes[4..8], &100_u32.to_le_bytes());
+        assert_eq!(&bytes[12..16], &0.1_f32.to_le_bytes());
+        assert_eq!(&bytes[16..20], &(-1_i32).to_le_bytes());
+        assert_eq!(&bytes[24..28], &3_u32.to_le_bytes());
+        assert_eq!(&bytes[28..32], &0.25_f32.to_le_bytes());
+        assert_eq!(&bytes[32..36], &f32::INFINITY.to_le_bytes());
+    }
+
+    // Writing a file and reading it back should give the same outputs
+    #[test]
+    fn npy_round_trip() {
+        let path = std::env::temp_dir().join("fractal_generator_npy_round_trip.npy");
+        let file_name: &str = path.to_str().unwrap();
+
+        let pos_to_output = |pix_pos: PixPos| -> FracOutput {
+            if pix_pos.col.is_multiple_of(2) {
+                FracOutput::MandelJulia{iters: pix_pos.row * 10 + pix_pos.col, max_iters: 1000, final_mag: 2.5, period: pix_pos.row, distance: 0.5 / (pix_pos.col + 1) as f32,
+                    trap: TrapHit{distance: 0.1, iter: pix_pos.col, pos: MyComplex::new(0.5, pix_pos.row as f32)},
+                    interior: InteriorInfo{angle: -1.5, multiplier: MyComplex::new(0.25, pix_pos.col as f32), distance: 0.01}}
+            } else {
+                FracOutput::Newton{closest: pix_pos.row as usize, roots: 5}
+            }
+        };
+        RawImg::new(5, 3, file_name).write_pixels(&pos_to_output).unwrap();
+        let image: FracImage = RawImg::read_image(file_name).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((image.width(), image.height()), (5, 3));
+        for row in 0..image.height() {
+            for col in 0..image.width() {
+                assert_eq!(image.pixel(PixPos{row, col}), pos_to_output(PixPos{row, col}));
+            }
+        }
+    }
+
+    // Files from before the distance, trap and interior fields were added
+    // are read with those fields left at their defaults
+    #[test]
+    fn npy_older_layout() {
+        let path = std::env::temp_dir().join("fractal_generator_npy_older_layout.npy");
+        let file_name: &str = path.to_str().unwrap();
+
+        let descr: &str = "[('iters', '<u4'), ('max_iters', '<u4'), ('smooth', '<f4'), ('final_mag', '<f4'), \
+                           ('root', '<i4'), ('num_roots', '<u4'), ('period', '<u4')]";
+        let mut bytes: Vec<u8> = npy_header(descr, 2, 1);
+        for field in [5, 100, 0, 2.5_f32.to_bits(), -1_i32 as u32, 0, 3, 0, 0, 0, 0, 2, 3, 0] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        std::fs::write(&path, &bytes).unwrap();
+        let image: FracResult<FracImage> = RawImg::read_image(file_name);
+        std::fs::remove_file(&path).unwrap();
+
+        let image: FracImage = image.unwrap();
+        assert_eq!(image.pixel(PixPos{row: 0, col: 0}), FracOutput::MandelJulia{iters: 5, max_iters: 100, final_mag: 2.5, period: 3,
+            distance: 0.0, trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO});
+        assert_eq!(image.pixel(PixPos{row: 0, col: 1}), FracOutput::Newton{closest: 2, roots: 3});
+    }
+
+    // A shape bigger than the file and a root outside the polynomial are
+    // format errors rather than panics
+    #[test]
+    fn npy_bad_records() {
+        let path = std::env::temp_dir().join("fractal_generator_npy_bad_records.npy");
+        let file_name: &str = path.to_str().unwrap();
+        let read_bytes = |bytes: &[u8]| -> FracResult<FracImage> {
+            std::fs::write(&path, bytes).unwrap();
+            let image: FracResult<FracImage> = RawImg::read_image(file_name);
+            std::fs::remove_file(&path).unwrap();
+            image
+        };
+
+        let newton_record = |root: i32| FracOutput_to_bytes(FracOutput::Newton{closest: root as usize, roots: 3});
+        for (width, height) in [(2, 1), (4_000_000_000, 4_000_000_000)] {
+            let mut bytes: Vec<u8> = npy_header(&npy_descr(), width, height);
+            bytes.extend_from_slice(&newton_record(0));
+            assert!(matches!(read_bytes(&bytes), Err(FracError::Format(_))));
+        }
+        for root in [-1, 3] {
+            let mut bytes: Vec<u8> = npy_header(&npy_descr(), 1, 1);
+            bytes.extend_from_slice(&newton_record(root));
+            assert!(matches!(read_bytes(&bytes), Err(FracError::Format(_))));
+        }
+    }
+
+    // Fields are found by name, and unknown ones are skipped
+    #[test]
+    fn npy_header_fields() {
+        let dict = |descr: &str| format!("{{'descr': {}, 'fortran_order': False, 'shape': (3, 4), }}", descr);
+        let (width, height, layout): (u32, u32, NpyLayout) = parse_npy_header(&dict(&npy_descr())).unwrap();
+        assert_eq!((width, height, layout.record_size), (4, 3, NPY_RECORD_SIZE));
+        assert!(layout.offsets.iter().enumerate().all(|(idx, offset)| *offset == Some(idx * 4)));
+
+        let (_, _, layout): (u32, u32, NpyLayout) = parse_npy_header(&dict("[('extra', '<f8'), ('num_roots', '<u4'), ('iters', '<u4'), \
+            ('max_iters', '<u4'), ('smooth', '<f4'), ('final_mag', '<f4'), ('root', '<i4')]")).unwrap();
+        assert_eq!(layout.record_size, 32);
+        assert_eq!(layout.offsets[..7], [Some(12), Some(16), Some(20), Some(24), Some(28), Some(8), None]);
+
+        assert!(parse_npy_header(&dict("[('iters', '<u4'), ('max_iters', '<u4')]")).is_err());
+        assert!(parse_npy_header(&dict(&npy_descr().replace("('iters', '<u4')", "('iters', '<f8')"))).is_err());
+        assert!(parse_npy_header(&dict("'<f4'")).is_err());
+        assert!(parse_npy_header(&dict(&npy_descr()).replace("False", "True")).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+pub mod error;
+pub mod my_complex;
+pub mod fractals;
+pub mod graphics;
+pub mod render;
+pub mod scene;
+pub mod viewport;
+pub mod animation;
+pub mod atlas;
+pub mod buddhabrot;
+pub mod orbit_trap;
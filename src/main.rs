@@ -1,148 +1,1182 @@
-mod my_complex;
-mod fractals;
-mod graphics;
-
-use graphics::{color_mapping::FracOutput_to_Color, bmp_img_maker::BMPImg};
-use my_complex::MyComplex;
-use fractals::{Fractal, FractalType};
+use fractal_generator::graphics::{self, antialias, color_mapping, bmp_img_maker::BMPImg, raw_img_maker::RawImg,
+    palette::{Palette, Interpolation, PaletteMapping, BUILTIN_PALETTE_NAMES}};
+use fractal_generator::error::{FracError, FracResult};
+use fractal_generator::my_complex::MyComplex;
+use fractal_generator::fractals::{Fractal, FracOutput, NO_INTERIOR_INFO};
+use fractal_generator::scene::{Scene, FractalSpec, ColorSpec};
+use fractal_generator::viewport::Viewport;
+use fractal_generator::atlas::AtlasSpec;
+use fractal_generator::buddhabrot::{DensityMap, DensitySpec, OrbitTracer};
+use fractal_generator::animation::{AnimationSpec, Easing, ZoomAnimation, EASING_NAMES};
+use fractal_generator::orbit_trap::{OrbitTrap, TrapSpec, NO_TRAP_HIT, TRAP_SHAPES};
+use fractal_generator::render::{self, FracImage, FracSampler, PixelSource, RenderMode, RowMirror, SamplePattern,
+    TileScheduler, SAMPLE_PATTERN_NAMES};
 
 extern crate clap;
-use clap::{Command, Arg, Values};
+use clap::{Command, Arg, ArgMatches, ErrorKind, ValueSource};
+
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+fn str_to_val<T: std::str::FromStr>(inp_str: &str, name: &str) -> FracResult<T> {
+    inp_str.parse::<T>().map_err(|_| invalid_value(inp_str, name))
+}
+
+fn invalid_value(inp_str: &str, name: &str) -> FracError {
+    FracError::InvalidInput(format!("Invalid value '{}' for '{}'", inp_str, name))
+}
 
-fn str_to_val<T: std::str::FromStr>(inpStr: &str) -> T
-where
-    T: std::str::FromStr, <T as std::str::FromStr>::Err: std::fmt::Debug,
-{
-    let new_inp_str: String = inpStr.replace("~", "-");
-    new_inp_str.parse::<T>().unwrap()
+// The text of an argument that always has a value (it's required or has a
+// default)
+fn arg_str<'a>(matches: &'a ArgMatches, name: &str) -> FracResult<&'a str> {
+    matches.value_of(name).ok_or_else(|| FracError::InvalidInput(format!("Missing a value for '{}'", name)))
+}
+
+fn arg_val<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> FracResult<T> {
+    str_to_val(arg_str(matches, name)?, name)
+}
+
+// The two values of an argument like --real-range, if it was given
+fn arg_pair<T: std::str::FromStr + Copy>(matches: &ArgMatches, name: &str) -> FracResult<Option<(T, T)>> {
+    let inputs = match matches.values_of(name) {
+        Some(inputs) => inputs,
+        None => return Ok(None),
+    };
+    let vals: Vec<T> = inputs.map(|inp_str| str_to_val(inp_str, name)).collect::<FracResult<_>>()?;
+    match vals[..] {
+        [first, second] => Ok(Some((first, second))),
+        _ => Err(FracError::InvalidInput(format!("'{}' takes two values", name))),
+    }
+}
+
+// A complex number given either as a complex literal ("-0.8+0.156i") or as
+// its real and imaginary parts
+fn complex_from_strs(inp_strs: &[&str], name: &str) -> FracResult<MyComplex<f32>> {
+    match inp_strs {
+        [literal] => literal.parse::<MyComplex<f32>>()
+            .map_err(|err| FracError::InvalidInput(format!("{} (for '{}')", err, name))),
+        [real, imag] => Ok(MyComplex::new(str_to_val(real, name)?, str_to_val(imag, name)?)),
+        _ => Err(FracError::InvalidInput(format!("'{}' takes a complex number, or its real and imaginary parts", name))),
+    }
+}
+
+// The complex numbers given with an argument that can be used more than
+// once, like --root. The values that came with the same use of the argument
+// are next to each other on the command line.
+fn complex_args(matches: &ArgMatches, name: &str) -> FracResult<Vec<MyComplex<f32>>> {
+    let (inputs, indices) = match (matches.values_of(name), matches.indices_of(name)) {
+        (Some(inputs), Some(indices)) => (inputs, indices),
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut groups: Vec<Vec<&str>> = Vec::new();
+    let mut last_idx: Option<usize> = None;
+    for (inp_str, idx) in inputs.zip(indices) {
+        match (last_idx, groups.last_mut()) {
+            (Some(last_idx), Some(group)) if last_idx + 1 == idx => group.push(inp_str),
+            _ => groups.push(vec![inp_str]),
+        }
+        last_idx = Some(idx);
+    }
+    groups.iter().map(|group| complex_from_strs(group, name)).collect()
 }
 
 fn main() {
     // Parse the command line arguments
-    let matches = Command::new(env!("CARGO_PKG_NAME"))
+    let palette_help: String = palette_help();
+    let mut cli = Command::new(env!("CARGO_PKG_NAME"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .version(env!("CARGO_PKG_VERSION"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
-        .arg(Arg::new("width").takes_value(true).required(true)
+        .after_help("Exit codes: 0 on success, 2 for invalid arguments, 3 for files in the wrong \
+            format or images too big for their format, 4 when a file can't be read or written, \
+            and 130 when the render is cancelled with Ctrl-C.")
+        // The width and height are only needed when rendering a fractal, not
+        // when working with data that has already been rendered
+        .subcommand_negates_reqs(true)
+        .allow_negative_numbers(true)
+        .arg(Arg::new("width").takes_value(true).required_unless_present("scene")
             .help("The width of the image in pixels")
         )
-        .arg(Arg::new("height").takes_value(true).required(true)
+        .arg(Arg::new("height").takes_value(true).required_unless_present("scene")
             .help("The height of the image in pixels")
         )
-        .arg(Arg::new("file-name").short('f').long("file-name")
+        .arg(Arg::new("scene").long("scene").global(true).takes_value(true).value_name("file")
+            .help("Load the fractal, view, coloring and output options from a TOML or JSON scene \
+            file. Options given on the command line (including a fractal type) override the ones \
+            in the file.")
+        )
+        .arg(Arg::new("dump-scene").long("dump-scene").takes_value(true).value_name("file")
+            .help("Write the scene the other options describe to a TOML file (or JSON if the name \
+            ends in .json, or TOML to stdout for '-') instead of rendering it.")
+        )
+        .arg(Arg::new("file-name").short('f').long("file-name").global(true)
             .default_value("fractal.bmp")
             .help("The name of the image to output.")
         )
+        .arg(Arg::new("colorizer").short('c').long("colorizer").global(true)
+            .possible_values(color_mapping::COLORIZER_NAMES).default_value("bands")
+            .help("The color mapping used to turn the fractal data into pixel colors.")
+        )
+        .arg(Arg::new("palette").short('p').long("palette").global(true).takes_value(true)
+            .help(palette_help.as_str())
+        )
+        .arg(Arg::new("palette-interp").long("palette-interp").global(true).takes_value(true)
+            .possible_values(["rgb", "hsv", "lab", "oklab"])
+            .help("The color space the palette colors are blended in. Defaults to the \
+            palette's own setting.")
+        )
+        .arg(Arg::new("palette-mapping").long("palette-mapping").global(true)
+            .possible_values(["cyclic", "clamped"])
+            .help("Whether the palette repeats or sticks to its end colors. Defaults to the \
+            palette's own setting, which is 'cyclic' unless a JSON palette says otherwise.")
+        )
+        .arg(Arg::new("root-color").long("root-color").global(true).takes_value(true)
+            .multiple_occurrences(true).value_name("#rrggbb")
+            .help("The color of a Newton fractal root, given in the same order as the roots. \
+            Roots without a color of their own get evenly spaced hues.")
+        )
+        .arg(Arg::new("coloring").long("coloring").global(true)
+            .possible_values(["iteration", "histogram", "distance", "trap"]).default_value("iteration")
+            .help("How escaping points are turned into palette positions. 'iteration' uses \
+            the smooth iteration count directly, 'histogram' spreads the palette evenly over \
+            the iteration counts in the image (and ignores --palette-scale). 'distance' draws \
+            the edge of a Mandelbrot or Julia set as lines from its estimated distance, over \
+            the palette colors (or white without a palette), so thin filaments show up at \
+            any resolution. 'trap' colors every point by how close its orbit came to the \
+            --trap, or by the pixel of an image trap its orbit landed on.")
+        )
+        .arg(Arg::new("interior").long("interior").global(true)
+            .possible_values(color_mapping::INTERIOR_NAMES).default_value("flat")
+            .help("How the inside of a Mandelbrot or Julia set is colored, over the palette \
+            (or in grays without one). 'flat' gives it one color, 'final-mag' uses |z| when \
+            the iteration stopped, 'angle' the direction of the average orbit point, 'period' \
+            the length of the cycle the orbit fell into, 'distance' the estimated distance to \
+            the edge of a Mandelbrot set, and 'multiplier' how strongly the cycle attracts, \
+            which shades each component of the set from its center out. Anything but 'flat' \
+            follows every orbit, so renders are slower.")
+        )
+        .arg(Arg::new("boundary-width").long("boundary-width").global(true).default_value("1")
+            .help("How many pixels wide the lines of 'distance' coloring are.")
+        )
+        .arg(Arg::new("trap").long("trap").global(true).takes_value(true)
+            .possible_values(TRAP_SHAPES)
+            .help("Follow every orbit into an orbit trap of this shape, placed with the other \
+            --trap options, for 'trap' coloring.")
+        )
+        .arg(Arg::new("trap-center").long("trap-center").global(true).takes_value(true)
+            .allow_hyphen_values(true).value_name("complex")
+            .help("The middle of the orbit trap. [default: 0]")
+        )
+        .arg(Arg::new("trap-angle").long("trap-angle").global(true).takes_value(true)
+            .allow_hyphen_values(true).value_name("degrees")
+            .help("How far a line or cross trap is turned from the real axis. [default: 0]")
+        )
+        .arg(Arg::new("trap-radius").long("trap-radius").global(true).takes_value(true)
+            .help("The radius of a circle trap. [default: 1]")
+        )
+        .arg(Arg::new("trap-image").long("trap-image").global(true).takes_value(true).value_name("file")
+            .help("The BMP picture of an image trap.")
+        )
+        .arg(Arg::new("trap-size").long("trap-size").global(true).takes_value(true)
+            .help("How wide an image trap's picture is, in complex units. [default: 1]")
+        )
+        .arg(Arg::new("trap-width").long("trap-width").global(true).default_value("0.25")
+            .help("How far from the orbit trap 'trap' coloring goes through the palette over \
+            (or fades from white to black without one).")
+        )
+        .arg(Arg::new("distance-antialias").long("distance-antialias").global(true)
+            .help("Smooth the edge of a Mandelbrot or Julia set without supersampling, by \
+            blending the pixels it passes through into the inside color by how close their \
+            estimated distance says they are.")
+        )
+        .arg(Arg::new("palette-offset").long("palette-offset").global(true).default_value("0")
+            .help("Shifts the palette position of every pixel.")
+        )
+        .arg(Arg::new("palette-scale").long("palette-scale").global(true).default_value("0.03125")
+            .help("The fraction of the palette covered per iteration.")
+        )
+        .arg(Arg::new("format").long("format").takes_value(true)
+            .possible_values(["bmp", "npy"])
+            .help("The output format. 'bmp' writes a colored image, 'npy' writes the raw \
+            per-pixel fractal data (iteration count, smooth value, final |z|, interior cycle \
+            period, distance estimate, orbit trap hit, interior angle, cycle multiplier and \
+            distance, and Newton root index) as a NumPy array. Defaults to the extension of the file name.")
+        )
+        .arg(Arg::new("npy-interior").long("npy-interior")
+            .help("Record the interior angle, cycle multiplier and interior distance in .npy \
+            output even if --interior doesn't need them, so the data can be recolored with \
+            them later. Follows every orbit, so renders are slower. Without it, they're 0.")
+        )
         .arg(Arg::new("max-iters").default_value("1000")
             .help("The maximum number of iterations to run the fractal algorithms \
             before moving on.")
         )
+        .arg(Arg::new("threads").short('t').long("threads").takes_value(true)
+            .help("The number of threads to render with. Defaults to the number of CPU cores.")
+        )
+        .arg(Arg::new("tile-size").long("tile-size").default_value("32")
+            .help("The width and height (in pixels) of the tiles handed out to the render threads.")
+        )
+        .arg(Arg::new("render-mode").long("render-mode")
+            .possible_values(["full", "subdivide"]).default_value("full")
+            .help("How the pixels are worked out. 'full' calculates every pixel, 'subdivide' \
+            only calculates the borders of rectangles and fills in the ones whose border is \
+            all the same, which is much faster for Newton fractals with large basins (larger \
+            tiles help) but can miss tiny details. Mandelbrot and Julia sets are only filled \
+            in inside the two biggest bulbs of the Mandelbrot set, so they gain little from it.")
+        )
+        .arg(Arg::new("no-mirror").long("no-mirror")
+            .help("Render every row, even when the fractal is symmetric across the real axis. \
+            By default, when the view straddles the real axis of a Mandelbrot set, a Julia set \
+            with a real seed or a Newton fractal whose roots come in conjugate pairs, only one \
+            side is rendered and it's mirrored onto the other.")
+        )
+        .arg(Arg::new("progressive").long("progressive")
+            .help("Render in passes of increasing detail (every 16th pixel, then every 8th, \
+            and so on), writing a blocky preview of the image to the output file after each \
+            pass.")
+        )
+        .arg(Arg::new("samples").long("samples").takes_value(true).conflicts_with("progressive")
+            .help("Supersample (anti-alias) colored images with this many samples in every \
+            pixel, averaging their colors. Has to be a square number (4, 9, 16, ...). Can't \
+            be used with .npy output.")
+        )
+        .arg(Arg::new("sample-pattern").long("sample-pattern")
+            .possible_values(SAMPLE_PATTERN_NAMES).default_value("rotated")
+            .help("Where the samples go in each pixel: the middle of each cell of a grid, a \
+            random spot in each cell, or the grid turned so no two samples line up.")
+        )
+        .arg(Arg::new("adaptive").long("adaptive").requires("samples")
+            .help("Only supersample the pixels whose color stands out from their neighbors.")
+        )
+        .arg(Arg::new("quiet").short('q').long("quiet")
+            .help("Don't print the render progress to stderr.")
+        )
         .arg(Arg::new("real-range").short('r').long("real-range")
-            .number_of_values(2).value_names(&["start", "end"]).default_values(&["-2", "2"])
-            .help("The start and end coordinates of the real axis of the image.")
+            .number_of_values(2).value_names(&["start", "end"])
+            .help("The start and end coordinates of the real axis of the image. Defaults to -2 2 if only --imag-range is given.")
         )
         .arg(Arg::new("imag-range").short('i').long("imag-range")
-            .number_of_values(2).value_names(&["start", "end"]).default_values(&["-2", "2"])
-            .help("The start and end coordinates of the imaginary axis of the image.")
+            .number_of_values(2).value_names(&["start", "end"])
+            .help("The start and end coordinates of the imaginary axis of the image. Defaults to -2 2 if only --real-range is given.")
+        )
+        .arg(Arg::new("center").long("center")
+            .takes_value(true).allow_hyphen_values(true).value_name("complex")
+            .conflicts_with_all(&["real-range", "imag-range"])
+            .help("The complex number at the center of the image, like -0.5, 1.5-0.3i, 2e-3+i, or \
+            2∠45 (or 2@45) in polar form with the angle in degrees (add 'rad' for radians). [default: 0]")
+        )
+        .arg(Arg::new("zoom").long("zoom").takes_value(true).conflicts_with_all(&["real-range", "imag-range"])
+            .help("How many times to magnify the view. At a zoom of 1 the shorter side of the image spans 4 units. \
+                The pixels are always square. [default: 1]")
+        )
+        .arg(Arg::new("rotate").long("rotate").takes_value(true).default_value("0")
+            .help("Turn the view counter-clockwise by this many degrees.")
         )
         .subcommand(Command::new("Mandelbrot")
             .about("Generates a Mandelbrot set fractal image")
         )
         .subcommand(Command::new("Julia")
-            .arg(Arg::new("real").required(true)
-                .help("The real component of the complex number seed of the Julia fractal.")
+            .allow_negative_numbers(true)
+            .arg(Arg::new("real").required(true).allow_hyphen_values(true)
+                .help("The complex number seed of the Julia fractal (written like --center), or \
+                its real component if the imaginary component is given separately.")
             )
-            .arg(Arg::new("imaginary").required(true)
+            .arg(Arg::new("imaginary")
                 .help("The imaginary component of the complex number seed of the Julia fractal.")
             )
             .about("Generates a Julia set fractal image")
         )
         .subcommand(Command::new("Newton")
+            .allow_negative_numbers(true)
             .arg(Arg::new("root").short('r').long("root").required(true)
-                .multiple_occurrences(true).number_of_values(2).value_names(&["real", "imag"])
-                .help("A complex number representing a polynomial root (the 'a' in the 'x-a' linear term). \
-                       List as many roots as are needed for the fractal.")
+                .multiple_occurrences(true).multiple_values(true).value_name("complex")
+                .help("A complex number representing a polynomial root (the 'a' in the 'x-a' linear term), \
+                       written like --center or as its real and imaginary parts. Write roots that start \
+                       with '-' and aren't plain numbers as --root=-0.5+0.866i. List as many roots as \
+                       are needed for the fractal.")
             )
             .about("Generates a Newton fractal image")
         )
-        .get_matches();
+        .subcommand(Command::new("atlas")
+            .arg(Arg::new("cells").long("cells").takes_value(true).number_of_values(2)
+                .value_names(&["columns", "rows"])
+                .help("The number of Julia sets across and up the image [default: 8 6]")
+            )
+            .arg(Arg::new("gap").long("gap").default_value("2")
+                .help("Pixels left between the Julia sets")
+            )
+            .arg(Arg::new("cell-zoom").long("cell-zoom").default_value("1")
+                .help("How far each Julia set is magnified inside its cell")
+            )
+            .arg(Arg::new("background").long("background")
+                .help("Show the Mandelbrot set in the gaps between the Julia sets (make them wider \
+                with --gap) instead of leaving them empty")
+            )
+            .about("Generates a grid of Julia sets, each seeded with the point of the Mandelbrot \
+            set view under the middle of its cell")
+        )
+        .subcommand(Command::new("buddhabrot")
+            .arg(Arg::new("orbits").long("orbits").default_value("10000000")
+                .help("The number of random points whose orbits are traced. More points make a \
+                smoother image.")
+            )
+            .arg(Arg::new("anti").long("anti")
+                .help("Trace the points that never escape (the Anti-Buddhabrot) instead of the ones \
+                that do")
+            )
+            .arg(Arg::new("seed").long("seed").default_value("1")
+                .help("The seed of the random points. The same seed always gives the same image.")
+            )
+            .arg(Arg::new("gamma").long("gamma").default_value("0.5")
+                .help("The density is raised to this power, to bring out the faint orbits")
+            )
+            .arg(Arg::new("nebula").long("nebula").takes_value(true).number_of_values(3)
+                .value_names(&["red", "green", "blue"])
+                .help("Make a Nebulabrot: trace the red, green and blue channels with these \
+                iteration limits (like 5000 500 50) instead of the max-iters")
+            )
+            .about("Generates a Buddhabrot: the density of the orbits of random points that escape \
+            the Mandelbrot set")
+        )
+        .subcommand(Command::new("reproduce")
+            .arg(Arg::new("image").required(true)
+                .help("An image written by this program. The parameters it was rendered with are \
+                stored at the end of the file.")
+            )
+            .arg(Arg::new("width").requires("height")
+                .help("Render at a different width, in pixels")
+            )
+            .arg(Arg::new("height")
+                .help("Render at a different height, in pixels")
+            )
+            .about("Renders an image again from the parameters stored in it. Options given on the \
+            command line (like --file-name or --samples) change those parameters.")
+        )
+        .subcommand(Command::new("animate")
+            .allow_negative_numbers(true)
+            .arg(Arg::new("out-dir").required(true)
+                .help("The directory to write the frames to, named frame_00000.bmp, frame_00001.bmp, \
+                ... (ffmpeg -i frame_%05d.bmp turns them into a video)")
+            )
+            .arg(Arg::new("frames").long("frames").takes_value(true)
+                .help("The number of frames, including the first and last views. Defaults to the \
+                frames in the scene's animation section.")
+            )
+            .arg(Arg::new("to-center").long("to-center").takes_value(true).value_name("complex")
+                .allow_hyphen_values(true)
+                .help("The center of the last frame")
+            )
+            .arg(Arg::new("to-zoom").long("to-zoom").takes_value(true)
+                .help("The zoom of the last frame. The zoom changes by the same factor every frame.")
+            )
+            .arg(Arg::new("to-rotate").long("to-rotate").takes_value(true).value_name("degrees")
+                .help("The rotation of the last frame")
+            )
+            .arg(Arg::new("to-max-iters").long("to-max-iters").takes_value(true)
+                .help("The iteration limit of the last frame. Deeper zooms usually need more.")
+            )
+            .arg(Arg::new("easing").long("easing").default_value("linear").possible_values(EASING_NAMES)
+                .help("Start and/or end the zoom slowly instead of at a constant speed")
+            )
+            .arg(Arg::new("resume").long("resume")
+                .help("Skip the frames that were already rendered with the same parameters")
+            )
+            .about("Renders the frames of an animation: a zoom from the view in the --scene file to \
+            another one, and/or the Julia seed or Newton roots moving along the paths in the scene's \
+            [animation] section (a circle, line, Bézier curve or spline through keyframes)")
+        )
+        .subcommand(Command::new("recolor")
+            .arg(Arg::new("raw-file").required(true)
+                .help("A raw data file previously written with '--format npy'.")
+            )
+            .about("Colors previously rendered raw fractal data without re-rendering it")
+        );
+    let matches: ArgMatches = cli.get_matches_mut();
 
-    /**************************************************************************
-     * Pull the information needed from the command line arguments
-     *************************************************************************/
-    // Create the object that will handle making the BMP image
-    let width:     u32  = str_to_val(matches.value_of("width").unwrap());
-    let height:    u32  = str_to_val(matches.value_of("height").unwrap());
-    let file_name: &str = matches.value_of("file-name").unwrap();
-    let bmp_img_obj = BMPImg::new(width, height, file_name);
+    let result: FracResult<()> = match matches.subcommand() {
+        Some(("recolor", recolor_matches)) => recolor_raw_data(recolor_matches),
+        Some(("reproduce", reproduce_matches)) => reproduce_image(&matches, reproduce_matches),
+        Some(("animate", animate_matches)) => animate(&matches, animate_matches),
+        subcommand => {
+            if !matches.is_present("scene") {
+                if subcommand.is_none() {
+                    cli.error(ErrorKind::MissingSubcommand,
+                        "A fractal type, a --scene file or the 'recolor' subcommand is required").exit();
+                }
+                if !matches.is_present("width") || !matches.is_present("height") {
+                    cli.error(ErrorKind::MissingRequiredArgument,
+                        "The width and height of the image are required to render a fractal").exit();
+                }
+            }
+            scene_from_matches(&matches, None).and_then(|scene| match matches.value_of("dump-scene") {
+                Some(file_name) => dump_scene(&scene, file_name),
+                None => render_scene(&scene, &matches, &ctrl_c_flag()),
+            })
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(err.exit_code());
+    }
+}
 
-    /**************************************************************************
-     * Make the Fractal object
-     *************************************************************************/
-    let frac_kind: FractalType = 
-        if let (frac_name, frac_matches) = matches.subcommand().unwrap() {
-            if frac_name == "Mandelbrot" {
-                FractalType::Mandelbrot ()
-            } else if frac_name == "Julia" {
-                let real_seed: f32 = str_to_val(frac_matches.values_of("real").unwrap().next().unwrap());
-                let imag_seed: f32 = str_to_val(frac_matches.values_of("imaginary").unwrap().next().unwrap());
-                FractalType::Julia (MyComplex::new(real_seed, imag_seed))
-            } else { // Must be a Newton fractal
-                // If a Newton fractal, get the values of each of the roots.
-                let mut roots: Vec<MyComplex<f32>> = Vec::new();
-
-                'root: loop {
-                    let real: f32;
-                    let imag: f32;
-                    match frac_matches.values_of("root").unwrap().next() {
-                        Some(real_str) => real = str_to_val(real_str),
-                        None => break 'root,
-                    }
-                    match frac_matches.values_of("root").unwrap().next() {
-                        Some(imag_str) => imag = str_to_val(imag_str),
-                        None => break 'root,
-                    }
-                    roots.push(MyComplex::new(real, imag));
+fn palette_help() -> String {
+    format!("Color the image with a gradient palette instead of a colorizer. Either a \
+        GIMP (.ggr), Fractint (.map), CSV or JSON palette file, or one of the built \
+        in palettes: {}.", BUILTIN_PALETTE_NAMES.join(", "))
+}
+
+// Build the palette picked in the coloring options. Histogram coloring
+// always needs a palette, so it falls back to the classic one.
+fn palette_from_spec(color: &ColorSpec) -> FracResult<Option<Palette>> {
+    let palette_name: &str = match (&color.palette, color.coloring.as_str()) {
+        (Some(name), _) => name,
+        (None, "histogram") => "classic",
+        (None, _) => return Ok(None),
+    };
+
+    let mut palette: Palette = Palette::from_name_or_file(palette_name)?;
+    if let Some(interp) = &color.palette_interp {
+        palette.set_interpolation(Interpolation::from_name(interp).ok_or_else(|| invalid_value(interp, "palette-interp"))?);
+    }
+    if let Some(mapping) = &color.palette_mapping {
+        palette.set_mapping(PaletteMapping::from_name(mapping).ok_or_else(|| invalid_value(mapping, "palette-mapping"))?);
+    }
+    palette.set_offset(color.palette_offset as f32);
+    palette.set_scale(color.palette_scale as f32);
+    Ok(Some(palette))
+}
+
+// The size of a pixel (in complex units) that distance estimates are
+// measured against
+fn distance_pixel_size(viewport: &Viewport) -> f32 {
+    let (pixel_width, pixel_height): (f32, f32) = viewport.pixel_size();
+    (pixel_width * pixel_height).sqrt()
+}
+
+// Build the function that colors each pixel with the coloring options.
+// Histogram coloring spreads the palette over the iteration counts of
+// `histogram_pixels`, and the distance colorings measure the distance
+// estimates in pixels `pixel_size` across.
+fn pixel_colorer(color: &ColorSpec, histogram_pixels: &[FracOutput], pixel_size: f32) -> FracResult<Box<dyn Fn(FracOutput) -> graphics::Color>> {
+    if !(color.boundary_width > 0.0 && color.boundary_width.is_finite()) {
+        return Err(FracError::InvalidInput(format!("The boundary width has to be a positive number, not {}", color.boundary_width)));
+    }
+    // The same palette is shared by the base, trap and interior colorings
+    let palette: Option<Arc<Palette>> = palette_from_spec(color)?.map(Arc::new);
+    let interior: color_mapping::InteriorColoring = color_mapping::InteriorColoring::from_name(&color.interior)
+        .ok_or_else(|| invalid_value(&color.interior, "interior"))?;
+
+    let base_colorer: Box<dyn Fn(FracOutput) -> graphics::Color> = match (palette.clone(), color.coloring.as_str()) {
+        (Some(palette), "histogram") => {
+            // The second pass of histogram coloring: place every pixel by
+            // where it falls in the distribution of the whole image
+            let histogram = color_mapping::IterHistogram::new(histogram_pixels);
+            Box::new(move |val: FracOutput| match val {
+                FracOutput::MandelJulia{iters, max_iters, ..} if iters < max_iters => {
+                    palette.color_at_fraction(histogram.fraction(val))
                 }
-                FractalType::Newton (roots)
+                _ => palette.FracOutput_to_Color(val),
+            })
+        }
+        (Some(palette), _) => Box::new(move |val: FracOutput| palette.FracOutput_to_Color(val)),
+        (None, "distance") => {
+            // Black lines on white
+            let colorizer = color_mapping::colorizer_from_name(&color.colorizer)
+                .ok_or_else(|| invalid_value(&color.colorizer, "colorizer"))?;
+            Box::new(move |val: FracOutput| match val {
+                FracOutput::MandelJulia{iters, max_iters, ..} if iters < max_iters => graphics::Color{r: 255, g: 255, b: 255},
+                FracOutput::MandelJulia{..} => graphics::Color{r: 0, g: 0, b: 0},
+                FracOutput::Newton{..} => colorizer(val),
+            })
+        }
+        (None, _) => Box::new(color_mapping::colorizer_from_name(&color.colorizer)
+            .ok_or_else(|| invalid_value(&color.colorizer, "colorizer"))?),
+    };
+
+    // Escaping points closer to the edge than `width` (in complex units)
+    // fade into the flat inside color
+    let inside: FracOutput = FracOutput::MandelJulia{iters: 1, max_iters: 1, final_mag: 0.0, period: 0, distance: 0.0,
+                                              trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO};
+    let inside_color: graphics::Color = base_colorer(inside);
+    let fade_to_inside = |base_colorer: Box<dyn Fn(FracOutput) -> graphics::Color>, width: f32| -> Box<dyn Fn(FracOutput) -> graphics::Color> {
+        Box::new(move |val: FracOutput| match val {
+            FracOutput::MandelJulia{iters, max_iters, distance, ..} if iters < max_iters && distance < width => {
+                antialias::blend_colors(inside_color, base_colorer(val), distance / width)
             }
-        } else {
-            panic!();
+            _ => base_colorer(val),
+        })
+    };
+    let mut base_colorer: Box<dyn Fn(FracOutput) -> graphics::Color> = base_colorer;
+    if color.coloring == "trap" {
+        // Points the trap coloring doesn't cover keep the color they had
+        let trap_coloring: color_mapping::TrapColoring = trap_coloring(color)?;
+        let palette: Option<Arc<Palette>> = palette.clone();
+        let fallback: Box<dyn Fn(FracOutput) -> graphics::Color> = base_colorer;
+        base_colorer = Box::new(move |val: FracOutput| {
+            trap_coloring.color(val, palette.as_deref()).unwrap_or_else(|| fallback(val))
+        });
+    }
+    if interior != color_mapping::InteriorColoring::Flat {
+        // Points inside the set without what the interior coloring needs
+        // keep the color they had
+        let fallback: Box<dyn Fn(FracOutput) -> graphics::Color> = base_colorer;
+        base_colorer = Box::new(move |val: FracOutput| match (interior.fraction(val, pixel_size), &palette) {
+            (Some(frac), Some(palette)) => palette.color_at_fraction(frac),
+            (Some(frac), None) => {
+                let level: u8 = (frac.clamp(0.0, 1.0) * 255.0) as u8;
+                graphics::Color{r: level, g: level, b: level}
+            }
+            (None, _) => fallback(val),
+        });
+    }
+    if color.coloring == "distance" {
+        base_colorer = fade_to_inside(base_colorer, color.boundary_width as f32 * pixel_size);
+    }
+    if color.distance_antialias {
+        base_colorer = fade_to_inside(base_colorer, pixel_size);
+    }
+
+    // Colors picked for Newton roots take priority
+    let root_colors: Vec<graphics::Color> = color.root_colors.iter()
+        .map(|hex| graphics::Color::from_hex(hex).map_err(FracError::InvalidInput))
+        .collect::<FracResult<_>>()?;
+    Ok(Box::new(move |val: FracOutput| match val {
+        FracOutput::Newton{closest, ..} if closest < root_colors.len() => root_colors[closest],
+        _ => base_colorer(val),
+    }))
+}
+
+// The trap and trap width picked for 'trap' coloring
+fn trap_coloring(color: &ColorSpec) -> FracResult<color_mapping::TrapColoring> {
+    let trap: OrbitTrap = match &color.trap {
+        Some(spec) => spec.to_trap()?,
+        None => return Err(FracError::InvalidInput(String::from("Trap coloring needs an orbit trap (--trap)"))),
+    };
+    if !(color.trap_width > 0.0 && color.trap_width.is_finite()) {
+        return Err(FracError::InvalidInput(format!("The trap width has to be a positive number, not {}", color.trap_width)));
+    }
+    Ok(color_mapping::TrapColoring::new(trap, color.trap_width as f32))
+}
+
+// Color a rendered image with the coloring options and write it out as a
+// BMP image
+fn write_colored_image(color: &ColorSpec, image: &FracImage, pixel_size: f32, file_name: &str, comment: Option<&str>) -> FracResult<()> {
+    let colorer = pixel_colorer(color, image.pixels(), pixel_size)?;
+    let colors: Vec<graphics::Color> = image.pixels().iter().map(|val| colorer(*val)).collect();
+    write_colors(image.width(), image.height(), &colors, file_name, comment)
+}
+
+// Write colored pixels out as a BMP image, with `comment` (the parameters
+// the image was rendered with) after them
+fn write_colors(width: u32, height: u32, colors: &[graphics::Color], file_name: &str, comment: Option<&str>) -> FracResult<()> {
+    let bmp_img_obj = BMPImg::new(width, height, file_name)?;
+    bmp_img_obj.set_header()?;
+    bmp_img_obj.write_colors(colors)?;
+    match comment {
+        Some(text) => bmp_img_obj.append_comment(text),
+        None => Ok(()),
+    }
+}
+
+// Render the fractal with `samples` samples spread over every pixel and
+// average their colors. With --adaptive, one sample is taken in the middle
+// of every pixel first, and only the pixels whose color stands out from
+// their neighbors are supersampled.
+fn render_supersampled(scene: &Scene, scheduler: &TileScheduler, frac_obj: &Fractal,
+                       point_to_complex: &(dyn Fn(f32, f32) -> MyComplex<f32> + Sync)) -> FracResult<Vec<graphics::Color>> {
+    let (width, height, samples): (u32, u32, u32) = (scene.output.width, scene.output.height, scene.output.samples);
+    let pattern_name: &str = &scene.output.sample_pattern;
+    let pattern: SamplePattern = SamplePattern::from_name(pattern_name).ok_or_else(|| invalid_value(pattern_name, "sample-pattern"))?;
+    let sample_to_complex = |pixel: graphics::PixPos, sample: u32| -> MyComplex<f32> {
+        let (offset_x, offset_y): (f32, f32) = pattern.offset(samples, sample, pixel);
+        point_to_complex(pixel.col as f32 + offset_x, pixel.row as f32 + offset_y)
+    };
+
+    // The single sample image is needed to find the edges, and to base
+    // histogram coloring on
+    let adaptive: bool = scene.output.adaptive;
+    let center_image: Option<FracImage> = if adaptive || scene.color.coloring == "histogram" {
+        let center_to_complex = |pixel: graphics::PixPos| -> MyComplex<f32> {
+            point_to_complex(pixel.col as f32 + 0.5, pixel.row as f32 + 0.5)
+        };
+        Some(scheduler.render(width, height, &FracSampler::new(frac_obj, &center_to_complex)))
+    } else {
+        None
+    };
+    let colorer = pixel_colorer(&scene.color, center_image.as_ref().map_or(&[], |image| image.pixels()),
+                                distance_pixel_size(&scene.viewport()?))?;
+
+    let (mut colors, pixels): (Vec<graphics::Color>, Vec<graphics::PixPos>) = match &center_image {
+        Some(image) if adaptive => {
+            let colors: Vec<graphics::Color> = image.pixels().iter().map(|val| colorer(*val)).collect();
+            let edges: Vec<graphics::PixPos> = antialias::edge_pixels(&colors, width, height, antialias::EDGE_THRESHOLD);
+            (colors, edges)
+        }
+        _ => {
+            let all_pixels: Vec<graphics::PixPos> = (0..height)
+                .flat_map(|row| (0..width).map(move |col| graphics::PixPos{row, col}))
+                .collect();
+            (vec![graphics::Color{r: 0, g: 0, b: 0}; (width as usize) * (height as usize)], all_pixels)
+        }
+    };
+
+    scheduler.render_samples(frac_obj, &pixels, samples, &sample_to_complex,
+        &mut |chunk: &[graphics::PixPos], chunk_samples: &[FracOutput]| {
+            for (pixel, pixel_samples) in chunk.iter().zip(chunk_samples.chunks(samples as usize)) {
+                let sample_colors: Vec<graphics::Color> = pixel_samples.iter().map(|val| colorer(*val)).collect();
+                colors[pixel.row as usize * width as usize + pixel.col as usize] = antialias::average_colors(&sample_colors);
+            }
+        });
+    Ok(colors)
+}
+
+// Color the data in a raw data file and write it out as a BMP image
+fn recolor_raw_data(matches: &ArgMatches) -> FracResult<()> {
+    let scene: Option<Scene> = matches.value_of("scene").map(Scene::from_file).transpose()?;
+    let mut color: ColorSpec = scene.as_ref().map_or_else(ColorSpec::default, |scene| scene.color.clone());
+    color_from_matches(matches, &mut color)?;
+
+    // The raw data doesn't say how big its pixels were
+    let pixel_size: f32 = match &scene {
+        Some(scene) => distance_pixel_size(&scene.viewport()?),
+        None if color.uses_distance() || color.interior == "distance" => return Err(FracError::InvalidInput(String::from(
+            "Distance coloring needs the --scene the raw data was rendered from, for the size of its pixels"))),
+        None => 1.0,
+    };
+    let image: FracImage = RawImg::read_image(arg_str(matches, "raw-file")?)?;
+    write_colored_image(&color, &image, pixel_size, arg_str(matches, "file-name")?, None)
+}
+
+// The value of an option only if it was given on the command line (not
+// filled in with its default), so it doesn't override a scene file
+fn given_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    match matches.value_source(name) {
+        Some(ValueSource::CommandLine) => matches.value_of(name),
+        _ => None,
+    }
+}
+
+// The fractal picked with a subcommand, if there was one
+fn fractal_from_matches(matches: &ArgMatches) -> FracResult<Option<FractalSpec>> {
+    let fractal: FractalSpec = match matches.subcommand() {
+        Some(("Mandelbrot", _)) => FractalSpec::Mandelbrot,
+        Some(("Julia", frac_matches)) => {
+            let seed_strs: Vec<&str> = ["real", "imaginary"].iter()
+                .filter_map(|name| frac_matches.value_of(name))
+                .collect();
+            FractalSpec::Julia{seed: complex_from_strs(&seed_strs, "seed")?}
+        }
+        Some(("Newton", frac_matches)) => FractalSpec::Newton{roots: complex_args(frac_matches, "root")?},
+        // The Julia sets of an atlas are laid out over the Mandelbrot set
+        Some(("atlas", _)) => FractalSpec::Mandelbrot,
+        Some(("buddhabrot", _)) => FractalSpec::Mandelbrot,
+        _ => return Ok(None),
+    };
+    Ok(Some(fractal))
+}
+
+// Put the coloring options given on the command line into `color`
+fn color_from_matches(matches: &ArgMatches, color: &mut ColorSpec) -> FracResult<()> {
+    if let Some(colorizer) = given_value(matches, "colorizer") {
+        color.colorizer = String::from(colorizer);
+    }
+    if let Some(palette) = given_value(matches, "palette") {
+        color.palette = Some(String::from(palette));
+    }
+    if let Some(interp) = given_value(matches, "palette-interp") {
+        color.palette_interp = Some(String::from(interp));
+    }
+    if let Some(mapping) = given_value(matches, "palette-mapping") {
+        color.palette_mapping = Some(String::from(mapping));
+    }
+    if let Some(offset) = given_value(matches, "palette-offset") {
+        color.palette_offset = str_to_val(offset, "palette-offset")?;
+    }
+    if let Some(scale) = given_value(matches, "palette-scale") {
+        color.palette_scale = str_to_val(scale, "palette-scale")?;
+    }
+    if let Some(coloring) = given_value(matches, "coloring") {
+        color.coloring = String::from(coloring);
+    }
+    if let Some(interior) = given_value(matches, "interior") {
+        color.interior = String::from(interior);
+    }
+    if let Some(width) = given_value(matches, "boundary-width") {
+        color.boundary_width = str_to_val(width, "boundary-width")?;
+    }
+    if matches.is_present("distance-antialias") {
+        color.distance_antialias = true;
+    }
+    if let Some(width) = given_value(matches, "trap-width") {
+        color.trap_width = str_to_val(width, "trap-width")?;
+    }
+    match given_value(matches, "trap") {
+        Some(shape) => color.trap = Some(trap_from_matches(matches, shape)?),
+        None => {
+            let trap_options: [&str; 5] = ["trap-center", "trap-angle", "trap-radius", "trap-image", "trap-size"];
+            if let Some(name) = trap_options.iter().find(|name| matches.is_present(name)) {
+                return Err(FracError::InvalidInput(format!("--{} needs a --trap to go with it", name)));
+            }
+        }
+    }
+    if let Some(hex_colors) = matches.values_of("root-color") {
+        color.root_colors = hex_colors.map(String::from).collect();
+    }
+    Ok(())
+}
+
+// The orbit trap of shape `shape` the --trap options describe
+fn trap_from_matches(matches: &ArgMatches, shape: &str) -> FracResult<TrapSpec> {
+    let center: MyComplex<f32> = match given_value(matches, "trap-center") {
+        Some(center) => complex_from_strs(&[center], "trap-center")?,
+        None => MyComplex::new(0.0, 0.0),
+    };
+    let number = |name: &str, default: f64| -> FracResult<f64> {
+        given_value(matches, name).map_or(Ok(default), |val| str_to_val(val, name))
+    };
+    Ok(match shape {
+        "point" => TrapSpec::Point{center},
+        "line" => TrapSpec::Line{center, angle: number("trap-angle", 0.0)?},
+        "cross" => TrapSpec::Cross{center, angle: number("trap-angle", 0.0)?},
+        "circle" => TrapSpec::Circle{center, radius: number("trap-radius", 1.0)?},
+        "image" => match given_value(matches, "trap-image") {
+            Some(file) => TrapSpec::Image{file: String::from(file), center, size: number("trap-size", 1.0)?},
+            None => return Err(FracError::InvalidInput(String::from("An image trap needs a picture (--trap-image)"))),
+        },
+        _ => return Err(invalid_value(shape, "trap")),
+    })
+}
+
+// The scene to render: `base` or the --scene file with the options given on
+// the command line on top of it, or just the command line options
+fn scene_from_matches(matches: &ArgMatches, base: Option<Scene>) -> FracResult<Scene> {
+    let fractal: Option<FractalSpec> = fractal_from_matches(matches)?;
+    let mut scene: Scene = match (base, matches.value_of("scene"), &fractal) {
+        (Some(scene), _, _) => scene,
+        (None, Some(scene_file), _) => Scene::from_file(scene_file)?,
+        (None, None, Some(fractal)) => Scene::new(fractal.clone(), arg_val(matches, "width")?, arg_val(matches, "height")?),
+        (None, None, None) => return Err(FracError::InvalidInput(String::from("No fractal type was given"))),
+    };
+    if let Some(fractal) = fractal {
+        scene.fractal = fractal;
+    }
+    if let Some(max_iters) = given_value(matches, "max-iters") {
+        scene.max_iters = str_to_val(max_iters, "max-iters")?;
+    }
+
+    // A center or zoom on the command line replaces ranges from the file,
+    // and the other way around
+    if let Some(center) = given_value(matches, "center") {
+        scene.view.center = complex_from_strs(&[center], "center")?;
+    }
+    if let Some(zoom) = given_value(matches, "zoom") {
+        scene.view.zoom = str_to_val(zoom, "zoom")?;
+    }
+    if matches.is_present("center") || matches.is_present("zoom") {
+        scene.view.real_range = None;
+        scene.view.imag_range = None;
+    }
+    if matches.is_present("real-range") || matches.is_present("imag-range") {
+        scene.view.real_range = arg_pair(matches, "real-range")?;
+        scene.view.imag_range = arg_pair(matches, "imag-range")?;
+    }
+    if let Some(rotate) = given_value(matches, "rotate") {
+        scene.view.rotate = str_to_val(rotate, "rotate")?;
+    }
+
+    if let Some(("atlas", atlas_matches)) = matches.subcommand() {
+        let (columns, rows): (u32, u32) = arg_pair(atlas_matches, "cells")?.unwrap_or((8, 6));
+        let mut atlas = AtlasSpec::new(columns, rows);
+        atlas.gap = arg_val(atlas_matches, "gap")?;
+        atlas.cell_zoom = arg_val(atlas_matches, "cell-zoom")?;
+        atlas.background = atlas_matches.is_present("background");
+        scene.atlas = Some(atlas);
+    }
+    if let Some(("buddhabrot", buddha_matches)) = matches.subcommand() {
+        let mut density = DensitySpec::new(arg_val(buddha_matches, "orbits")?);
+        density.anti = buddha_matches.is_present("anti");
+        density.seed = arg_val(buddha_matches, "seed")?;
+        density.gamma = arg_val(buddha_matches, "gamma")?;
+        if let Some(limits) = buddha_matches.values_of("nebula") {
+            density.nebula = limits.map(|limit| str_to_val(limit, "nebula")).collect::<FracResult<_>>()?;
+        }
+        scene.density = Some(density);
+    }
+
+    color_from_matches(matches, &mut scene.color)?;
+
+    let output = &mut scene.output;
+    if let Some(width) = given_value(matches, "width") {
+        output.width = str_to_val(width, "width")?;
+    }
+    if let Some(height) = given_value(matches, "height") {
+        output.height = str_to_val(height, "height")?;
+    }
+    if let Some(file_name) = given_value(matches, "file-name") {
+        output.file_name = String::from(file_name);
+        // Let the new file name pick the format
+        output.format = None;
+    }
+    if let Some(format) = given_value(matches, "format") {
+        output.format = Some(String::from(format));
+    }
+    if let Some(samples) = given_value(matches, "samples") {
+        output.samples = str_to_val(samples, "samples")?;
+    }
+    if let Some(pattern) = given_value(matches, "sample-pattern") {
+        output.sample_pattern = String::from(pattern);
+    }
+    if matches.is_present("adaptive") {
+        output.adaptive = true;
+    }
+    if matches.is_present("npy-interior") {
+        output.npy_interior = true;
+    }
+    Ok(scene)
+}
+
+// Render an image again from the scene stored in it, at a new size if one
+// was given
+fn reproduce_image(matches: &ArgMatches, reproduce_matches: &ArgMatches) -> FracResult<()> {
+    let image_name: &str = arg_str(reproduce_matches, "image")?;
+    let mut scene: Scene = scene_from_matches(matches, Some(Scene::from_image(image_name)?))?;
+    if reproduce_matches.is_present("width") {
+        scene.output.width = arg_val(reproduce_matches, "width")?;
+        scene.output.height = arg_val(reproduce_matches, "height")?;
+    }
+
+    // Don't write over the image the scene came from
+    let same_file = |other: &str| -> bool {
+        match (std::fs::canonicalize(image_name), std::fs::canonicalize(other)) {
+            (Ok(image_path), Ok(other_path)) => image_path == other_path,
+            _ => false,
+        }
+    };
+    if same_file(&scene.output.file_name) {
+        return Err(FracError::InvalidInput(format!(
+            "Reproducing {} would write over it, pick another name with --file-name", image_name)));
+    }
+
+    match matches.value_of("dump-scene") {
+        Some(file_name) => dump_scene(&scene, file_name),
+        None => render_scene(&scene, matches, &ctrl_c_flag()),
+    }
+}
+
+// A flag Ctrl-C sets to stop rendering. What's been rendered so far still
+// gets written out.
+fn ctrl_c_flag() -> Arc<AtomicBool> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let handler_cancel = Arc::clone(&cancel);
+    if let Err(err) = ctrlc::set_handler(move || handler_cancel.store(true, Ordering::Relaxed)) {
+        eprintln!("warning: Ctrl-C will not save the unfinished image ({})", err);
+    }
+    cancel
+}
+
+// Render the frames of a zoom from the --scene view to the one given with
+// the animate options, moving the Julia seed or Newton roots along the paths
+// in the scene's animation section. The frames are numbered in the order
+// they're shown.
+fn animate(matches: &ArgMatches, animate_matches: &ArgMatches) -> FracResult<()> {
+    if !matches.is_present("scene") {
+        return Err(FracError::InvalidInput(String::from("An animation starts from the view in a --scene file")));
+    }
+    // Progressive previews would look like finished frames to --resume
+    if matches.is_present("progressive") {
+        return Err(FracError::InvalidInput(String::from("Animation frames can't be rendered progressively")));
+    }
+    let mut start: Scene = scene_from_matches(matches, None)?;
+    // The frames are stills; the fractal of each one comes from the scene's
+    // animation section
+    let params: Option<AnimationSpec> = start.animation.take();
+    if let Some(params) = &params {
+        params.check(&start.fractal)?;
+    }
+
+    // The end of the zoom is the start with the animate options on top
+    let mut end: Scene = start.clone();
+    if let Some(center) = animate_matches.value_of("to-center") {
+        end.view.center = complex_from_strs(&[center], "to-center")?;
+        end.view.real_range = None;
+        end.view.imag_range = None;
+    }
+    if let Some(zoom) = animate_matches.value_of("to-zoom") {
+        end.view.zoom = str_to_val(zoom, "to-zoom")?;
+        end.view.real_range = None;
+        end.view.imag_range = None;
+    }
+    if let Some(rotate) = animate_matches.value_of("to-rotate") {
+        end.view.rotate = str_to_val(rotate, "to-rotate")?;
+    }
+    if let Some(max_iters) = animate_matches.value_of("to-max-iters") {
+        end.max_iters = str_to_val(max_iters, "to-max-iters")?;
+    }
+
+    let frames: u32 = match (animate_matches.value_of("frames"), &params) {
+        (Some(frames), _) => str_to_val(frames, "frames")?,
+        (None, Some(params)) => params.frames,
+        (None, None) => return Err(FracError::InvalidInput(String::from(
+            "The number of frames has to be given with --frames or in the scene's animation section"))),
+    };
+    let mut anim = ZoomAnimation::new(start, &end, frames)?;
+    let easing_name: &str = arg_str(animate_matches, "easing")?;
+    anim.set_easing(Easing::from_name(easing_name).ok_or_else(|| invalid_value(easing_name, "easing"))?);
+
+    let out_dir: &str = arg_str(animate_matches, "out-dir")?;
+    std::fs::create_dir_all(out_dir).map_err(|err| FracError::io(out_dir, err))?;
+    let resume: bool = animate_matches.is_present("resume");
+    let quiet: bool = matches.is_present("quiet");
+
+    let cancel: Arc<AtomicBool> = ctrl_c_flag();
+    for frame in 0..anim.frames() {
+        let mut scene: Scene = anim.frame(frame);
+        if let Some(params) = &params {
+            let time: f64 = if frames > 1 { frame as f64 / (frames - 1) as f64 } else { 0.0 };
+            scene.fractal = params.fractal_at(&scene.fractal, time)?;
+        }
+        let extension: String = String::from(scene.output.format_name());
+        let file_name: String = Path::new(out_dir).join(format!("frame_{:05}.{}", frame, extension))
+            .to_string_lossy().into_owned();
+        scene.output.file_name = file_name.clone();
+
+        // A frame is finished if the scene stored at its end (which is
+        // written last) is the frame's scene. Raw data frames are finished
+        // if all of their pixels are there.
+        if resume && Path::new(&file_name).exists() {
+            let finished: bool = match extension.as_str() {
+                "npy" => RawImg::read_image(&file_name).is_ok(),
+                _ => Scene::from_image(&file_name).is_ok_and(|frame_scene| frame_scene == scene),
+            };
+            if finished {
+                continue;
+            }
+        }
+
+        if !quiet {
+            eprintln!("Frame {}/{}: {}", frame + 1, anim.frames(), file_name);
+        }
+        if let Err(err) = render_scene(&scene, matches, &cancel) {
+            // Don't leave a cut off frame that looks finished
+            if let FracError::Cancelled = err {
+                let _ = std::fs::remove_file(&file_name);
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+fn dump_scene(scene: &Scene, file_name: &str) -> FracResult<()> {
+    if file_name == "-" {
+        print!("{}", scene.to_toml()?);
+        Ok(())
+    } else {
+        scene.write_file(file_name)
+    }
+}
+
+fn thread_count(matches: &ArgMatches) -> FracResult<usize> {
+    match matches.value_of("threads") {
+        Some(threads) => str_to_val(threads, "threads"),
+        None => Ok(render::default_thread_count()),
+    }
+}
+
+// Render the orbit density of a Mandelbrot scene (a Buddhabrot) into an
+// image. A Nebulabrot traces each color channel separately.
+fn render_density(scene: &Scene, density: &DensitySpec, matches: &ArgMatches, cancel: &Arc<AtomicBool>) -> FracResult<()> {
+    let (width, height): (u32, u32) = (scene.output.width, scene.output.height);
+    let file_name: &str = &scene.output.file_name;
+    if scene.output.format_name() != "bmp" {
+        return Err(FracError::InvalidInput(String::from("A Buddhabrot can only be written as a BMP image")));
+    }
+    if scene.fractal != FractalSpec::Mandelbrot {
+        return Err(FracError::InvalidInput(String::from("A Buddhabrot is made from the orbits of the Mandelbrot set")));
+    }
+    let limits: Vec<u32> = match density.nebula.len() {
+        0 => vec![scene.max_iters],
+        3 => density.nebula.clone(),
+        _ => return Err(FracError::InvalidInput(String::from("A Nebulabrot needs an iteration limit for each of red, green and blue"))),
     };
+    let palette: Option<Palette> = palette_from_spec(&scene.color)?;
+    BMPImg::new(width, height, file_name)?;
+
+    let mut channels: Vec<Vec<f32>> = Vec::with_capacity(limits.len());
+    let mut cancelled: bool = false;
+    for limit in limits.iter().copied() {
+        let mut tracer = OrbitTracer::new(scene.viewport()?, limit)?;
+        tracer.set_anti(density.anti);
+        tracer.set_seed(density.seed);
+        tracer.set_threads(thread_count(matches)?);
+        tracer.set_cancel_flag(Arc::clone(cancel));
+        let map: DensityMap = tracer.trace(density.orbits);
+        channels.push(map.brightness(density.gamma as f32));
+        if tracer.was_cancelled() {
+            cancelled = true;
+            break;
+        }
+    }
+    // The channels that weren't traced are left dark
+    channels.resize(limits.len(), vec![0.0; (width as usize) * (height as usize)]);
+
+    let to_byte = |val: f32| -> u8 { (val * 255.0).round() as u8 };
+    let colors: Vec<graphics::Color> = (0..(width as usize) * (height as usize)).map(|idx| match &channels[..] {
+        [red, green, blue] => graphics::Color{r: to_byte(red[idx]), g: to_byte(green[idx]), b: to_byte(blue[idx])},
+        [gray, ..] => match &palette {
+            Some(palette) => palette.color_at_fraction(gray[idx]),
+            None => graphics::Color{r: to_byte(gray[idx]), g: to_byte(gray[idx]), b: to_byte(gray[idx])},
+        },
+        [] => unreachable!("At least one channel is always traced"),
+    }).collect();
+
+    if cancelled {
+        eprintln!("Render cancelled, writing the orbits traced so far to {}", file_name);
+    }
+    write_colors(width, height, &colors, file_name, Some(&scene.to_comment()?))?;
+    if cancelled {
+        return Err(FracError::Cancelled);
+    }
+    Ok(())
+}
 
-    // Put together the fractal object
-    let frac_obj: &Fractal = &Fractal::new(
-        str_to_val(matches.value_of("max-iters").unwrap()),
-        frac_kind,
-    );
+// Render a scene into an image or raw data file. How it's rendered (threads,
+// tiles, mirroring, progress) is picked on the command line.
+fn render_scene(scene: &Scene, matches: &ArgMatches, cancel: &Arc<AtomicBool>) -> FracResult<()> {
+    if let Some(density) = &scene.density {
+        return render_density(scene, density, matches, cancel);
+    }
+
+    /**************************************************************************
+     * Pull the information needed from the scene
+     *************************************************************************/
+    let width:     u32  = scene.output.width;
+    let height:    u32  = scene.output.height;
+    let file_name: &str = &scene.output.file_name;
+    let format:    &str = scene.output.format_name();
+    if format != "bmp" && format != "npy" {
+        return Err(invalid_value(format, "format"));
+    }
+
+    // Put together the fractal object and find the part of the complex
+    // plane the image shows
+    let frac_obj: &Fractal = &scene.fractal()?;
+    let viewport: Viewport = scene.viewport()?;
 
     /**************************************************************************
      * Create the fractal
      *************************************************************************/
-    // Get the values that will be used in the pixel-to-complex coordinate
-    // calculation.
-    let width_as_f32:  f32 = width as f32;
-    let height_as_f32: f32 = height as f32;
+    // Create the closure that finds the complex number at a point in the
+    // image, given in pixels across and up from the bottom left corner
+    let point_to_complex = |x: f32, y: f32| -> MyComplex<f32> {
+        viewport.point_to_complex(x, y)
+    };
+    // Without supersampling, each pixel is sampled in its middle
+    let pix_pos_to_complex = |pix_pos: graphics::PixPos| -> MyComplex<f32> {
+        viewport.pixel_to_complex(pix_pos)
+    };
+    let sampler = FracSampler::new(frac_obj, &pix_pos_to_complex);
+    let atlas = scene.julia_atlas(frac_obj)?;
+    let source: &dyn PixelSource = match &atlas {
+        Some(atlas) => atlas,
+        None => &sampler,
+    };
+
+    // Render the whole image first so colorings that look at every pixel
+    // (like histogram coloring) can be used
+    let mut scheduler = TileScheduler::new(thread_count(matches)?);
+    scheduler.set_tile_size(arg_val(matches, "tile-size")?);
+    if matches.value_of("render-mode") == Some("subdivide") {
+        scheduler.set_mode(RenderMode::Subdivide);
+    }
+
+    // Supersampled images are only written as colors
+    let samples: u32 = scene.output.samples;
+    let side: u32 = (samples as f32).sqrt().round() as u32;
+    if samples == 0 || side * side != samples {
+        return Err(FracError::InvalidInput(String::from("The number of samples has to be a square number (1, 4, 9, 16, ...)")));
+    }
+    if samples > 1 && format == "npy" {
+        return Err(FracError::InvalidInput(String::from("Raw .npy data can't be supersampled, only colored images can")));
+    }
+    if samples > 1 && atlas.is_some() {
+        return Err(FracError::InvalidInput(String::from("A Julia set atlas can't be supersampled")));
+    }
 
-    let mut r_inputs: Values = matches.values_of("real-range").unwrap();
-    let r_start: f32 = str_to_val(r_inputs.next().unwrap());
-    let r_range: f32 = str_to_val::<f32>(r_inputs.next().unwrap()) - r_start;
+    // Row `row` is the mirror image of row `axis_sum - row` when the rows
+    // line up on either side of the real axis
+    if let Some(axis_sum) = viewport.mirror_axis_sum() {
+        if !matches.is_present("no-mirror") && samples == 1 && atlas.is_none() {
+            if let Some(root_map) = frac_obj.conjugate_root_map() {
+                scheduler.set_mirror(Some(RowMirror::new(axis_sum, root_map)));
+            }
+        }
+    }
+    scheduler.set_progress(!matches.is_present("quiet") && std::io::stderr().is_terminal());
 
-    let mut i_inputs: Values = matches.values_of("imag-range").unwrap();
-    let i_start: f32 = str_to_val(i_inputs.next().unwrap());
-    let i_range: f32 = str_to_val::<f32>(i_inputs.next().unwrap()) - i_start;
+    scheduler.set_cancel_flag(Arc::clone(cancel));
 
-    // Create the closure that colors each pixel
-    let pix_pos_to_color = |pix_pos: graphics::PixPos| -> graphics::Color {
-        // Pixels are colored left to right, bottom to top
-        let real: f32 = r_start + (pix_pos.col as f32) / width_as_f32  * r_range;
-        let imag: f32 = i_start + (pix_pos.row as f32) / height_as_f32 * i_range;
-        let cmplx_num: MyComplex<f32> = MyComplex::new(real, imag);
+    // Make sure the image can be colored and written before spending time
+    // rendering it
+    if format != "npy" {
+        BMPImg::new(width, height, file_name)?;
+        let _ = pixel_colorer(&scene.color, &[], distance_pixel_size(&viewport))?;
+    }
 
-        FracOutput_to_Color(frac_obj.complex_to_frac_output(cmplx_num))
+    // Store the scene in the image so it can be rendered again later
+    let comment: String = scene.to_comment()?;
+    let write_image = |image: &FracImage| -> FracResult<()> {
+        if format == "npy" {
+            // Write the raw fractal data, leaving the coloring for later
+            let raw_img_obj = RawImg::new(width, height, file_name);
+            raw_img_obj.write_pixels(&|pix_pos: graphics::PixPos| image.pixel(pix_pos))
+        } else {
+            write_colored_image(&scene.color, image, distance_pixel_size(&viewport), file_name, Some(&comment))
+        }
     };
 
-    // Create the image
-    bmp_img_obj.set_header();
-    bmp_img_obj.color_pixels(&pix_pos_to_color);
+    if samples > 1 {
+        let colors: Vec<graphics::Color> = render_supersampled(scene, &scheduler, frac_obj, &point_to_complex)?;
+        if scheduler.was_cancelled() {
+            eprintln!("Render cancelled, writing the finished part of the image to {}", file_name);
+        }
+        write_colors(width, height, &colors, file_name, Some(&comment))?;
+        if scheduler.was_cancelled() {
+            return Err(FracError::Cancelled);
+        }
+        return Ok(());
+    }
+
+    let image: FracImage = if matches.is_present("progressive") {
+        // The last pass is written below, like any other render. If a
+        // preview can't be written, there's no point in finishing.
+        let mut preview_result: FracResult<()> = Ok(());
+        let image: FracImage = scheduler.render_progressive(width, height, source, &mut |step: u32, preview: &FracImage| {
+            if step > 1 && preview_result.is_ok() {
+                preview_result = write_image(preview);
+                if preview_result.is_err() {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+        preview_result?;
+        image
+    } else {
+        scheduler.render(width, height, source)
+    };
+    if scheduler.was_cancelled() {
+        eprintln!("Render cancelled, writing the finished part of the image to {}", file_name);
+    }
+
+    write_image(&image)?;
+
+    // Let scripts know the image isn't complete
+    if scheduler.was_cancelled() {
+        return Err(FracError::Cancelled);
+    }
+    Ok(())
 }
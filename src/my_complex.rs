@@ -1,4 +1,6 @@
 use std::ops::{Add, Sub, Mul, Div, AddAssign, SubAssign, MulAssign, DivAssign};
+use std::{fmt, str::FromStr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 // A trait for things that when the basic operations (+ - * /) are done on them,
 // they return something of the same type. Also myst be copiable (aka a stack
@@ -18,7 +20,7 @@ pub struct MyComplex<T: BasicOps> {
 
 impl<T: BasicOps> MyComplex<T> {
     // Constructor for MyComplex
-    pub fn new(r: T, i: T) -> Self {
+    pub const fn new(r: T, i: T) -> Self {
         Self { r, i }
     }
     // Getter functions for MyComplex
@@ -100,6 +102,160 @@ impl<T: BasicOps> DivAssign for MyComplex<T> {
         *self = *self / rhs;
     }
 }
+// A group of `N` complex numbers stored as structure-of-arrays "lanes" (all
+// of the real parts together, then all of the imaginary parts), so the same
+// operation on every lane compiles down to SIMD instructions. The operations
+// are written the same way as the MyComplex ones, so each lane gives exactly
+// the same result as doing the operation on a single MyComplex.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct MyComplexLanes<const N: usize> {
+    pub r: [f32; N],
+    pub i: [f32; N],
+}
+
+impl<const N: usize> MyComplexLanes<N> {
+    pub fn from_complex(vals: [MyComplex<f32>; N]) -> Self {
+        Self { r: vals.map(|val| val.r), i: vals.map(|val| val.i) }
+    }
+
+    // Every lane set to the same value
+    pub fn splat(val: MyComplex<f32>) -> Self {
+        Self { r: [val.r; N], i: [val.i; N] }
+    }
+
+    pub fn lane(&self, idx: usize) -> MyComplex<f32> {
+        MyComplex::new(self.r[idx], self.i[idx])
+    }
+
+    pub fn mag_sqr(&self) -> [f32; N] {
+        let mut mag: [f32; N] = [0.0; N];
+        for (lane, mag) in mag.iter_mut().enumerate() {
+            *mag = self.r[lane] * self.r[lane] + self.i[lane] * self.i[lane];
+        }
+        mag
+    }
+}
+
+impl<const N: usize> Add for MyComplexLanes<N> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let mut out: Self = self;
+        for lane in 0..N {
+            out.r[lane] = self.r[lane] + rhs.r[lane];
+            out.i[lane] = self.i[lane] + rhs.i[lane];
+        }
+        out
+    }
+}
+
+impl<const N: usize> Mul for MyComplexLanes<N> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let mut out: Self = self;
+        for lane in 0..N {
+            out.r[lane] = self.r[lane] * rhs.r[lane] - self.i[lane] * rhs.i[lane];
+            out.i[lane] = self.r[lane] * rhs.i[lane] + self.i[lane] * rhs.r[lane];
+        }
+        out
+    }
+}
+
+// Read a complex number written the usual ways: "1.5-0.3i", "2e-3+i",
+// "-i", "0.75" (just the real part) or "2.5i" (just the imaginary part), or
+// in polar form as "r∠θ" (or "r@θ", which is easier to type) with θ in
+// degrees, or in radians if it ends in "rad". Spaces are ignored.
+impl FromStr for MyComplex<f32> {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let compact: String = text.chars().filter(|chr| !chr.is_whitespace()).collect();
+        let invalid = || format!("Invalid complex number: {}", text);
+        let parse_num = |num_str: &str| -> Result<f32, String> { num_str.parse::<f32>().map_err(|_| invalid()) };
+
+        // Polar form
+        if let Some((mag_str, angle_str)) = compact.split_once('∠').or_else(|| compact.split_once('@')) {
+            let mag: f32 = parse_num(mag_str)?;
+            let angle: f32 = match angle_str.strip_suffix("rad") {
+                Some(radians) => parse_num(radians)?,
+                None => parse_num(angle_str.trim_end_matches('°'))?.to_radians(),
+            };
+            return Ok(MyComplex::new(mag * angle.cos(), mag * angle.sin()));
+        }
+
+        let imag_str: &str = match compact.strip_suffix('i') {
+            Some(imag_str) => imag_str,
+            None => return Ok(MyComplex::new(parse_num(&compact)?, 0.0)),
+        };
+        // The imaginary part starts at the last sign that isn't at the start
+        // or part of an exponent
+        let bytes: &[u8] = imag_str.as_bytes();
+        let split: usize = (1..bytes.len()).rev()
+            .find(|&idx| (bytes[idx] == b'+' || bytes[idx] == b'-') && !matches!(bytes[idx - 1], b'e' | b'E'))
+            .unwrap_or(0);
+        // A bare "i" (with or without a sign) means a coefficient of 1
+        let imag: f32 = match &imag_str[split..] {
+            "" | "+" => 1.0,
+            "-" => -1.0,
+            coef => parse_num(coef)?,
+        };
+        let real: f32 = if split == 0 { 0.0 } else { parse_num(&imag_str[..split])? };
+        Ok(MyComplex::new(real, imag))
+    }
+}
+
+// Written so that FromStr reads back exactly the same number
+impl fmt::Display for MyComplex<f32> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{:+}i", self.r, self.i)
+    }
+}
+
+// In scene files, complex numbers are written as text ("-0.8+0.156i"), but
+// a plain number or a [real, imag] pair is read too
+impl Serialize for MyComplex<f32> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MyComplex<f32> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ComplexVisitor;
+
+        impl<'de> de::Visitor<'de> for ComplexVisitor {
+            type Value = MyComplex<f32>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a complex number like \"1.5-0.3i\", a number, or a [real, imag] pair")
+            }
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<Self::Value, E> {
+                text.parse::<MyComplex<f32>>().map_err(E::custom)
+            }
+            fn visit_f64<E: de::Error>(self, val: f64) -> Result<Self::Value, E> {
+                Ok(MyComplex::new(val as f32, 0.0))
+            }
+            fn visit_i64<E: de::Error>(self, val: i64) -> Result<Self::Value, E> {
+                Ok(MyComplex::new(val as f32, 0.0))
+            }
+            fn visit_u64<E: de::Error>(self, val: u64) -> Result<Self::Value, E> {
+                Ok(MyComplex::new(val as f32, 0.0))
+            }
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let real: f32 = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let imag: f32 = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                if seq.next_element::<f32>()?.is_some() {
+                    return Err(de::Error::invalid_length(3, &self));
+                }
+                Ok(MyComplex::new(real, imag))
+            }
+        }
+
+        deserializer.deserialize_any(ComplexVisitor)
+    }
+}
+
 /*===================================================================
 UNIT TESTS
 ===================================================================*/
@@ -131,6 +287,52 @@ mod tests {
             MyComplex {r:-13_284.0, i:-618.4});
     }
 
+    #[test]
+    fn complex_lanes_match_scalar() {
+        let a: [MyComplex<f32>; 4] = [MyComplex {r:0.3, i:-1.2}, MyComplex {r:-2.0, i:0.5},
+            MyComplex {r:120.4, i:10.0}, MyComplex {r:0.0, i:0.0}];
+        let b: [MyComplex<f32>; 4] = [MyComplex {r:-0.7, i:0.1}, MyComplex {r:1.5, i:1.5},
+            MyComplex {r:-110.0, i:4.0}, MyComplex {r:3.0, i:-3.0}];
+        let lanes_a = MyComplexLanes::from_complex(a);
+        let lanes_b = MyComplexLanes::from_complex(b);
+
+        for idx in 0..4 {
+            assert_eq!((lanes_a * lanes_b + lanes_a).lane(idx), a[idx] * b[idx] + a[idx]);
+            assert_eq!(lanes_a.mag_sqr()[idx], a[idx].mag_sqr());
+        }
+    }
+
+    #[test]
+    fn parse_complex() {
+        let parse = |text: &str| -> MyComplex<f32> { text.parse::<MyComplex<f32>>().unwrap() };
+        assert_eq!(parse("1.5-0.3i"), MyComplex {r:1.5, i:-0.3});
+        assert_eq!(parse("2e-3+i"), MyComplex {r:0.002, i:1.0});
+        assert_eq!(parse("-1E+2-2.5e-1i"), MyComplex {r:-100.0, i:-0.25});
+        assert_eq!(parse("-0.75"), MyComplex {r:-0.75, i:0.0});
+        assert_eq!(parse("-i"), MyComplex {r:0.0, i:-1.0});
+        assert_eq!(parse("3.5i"), MyComplex {r:0.0, i:3.5});
+        assert_eq!(parse(" 1 + 2i "), MyComplex {r:1.0, i:2.0});
+
+        let polar: MyComplex<f32> = parse("2∠90");
+        assert!((polar - MyComplex {r:0.0, i:2.0}).mag_sqr() < 1e-10);
+        let polar: MyComplex<f32> = parse("1@3.14159265rad");
+        assert!((polar - MyComplex {r:-1.0, i:0.0}).mag_sqr() < 1e-10);
+        assert_eq!(parse("1∠0°"), MyComplex {r:1.0, i:0.0});
+
+        for bad in ["", "i1", "1+2", "1+2j", "1++2i", "2∠", "abc"] {
+            assert!(bad.parse::<MyComplex<f32>>().is_err(), "{}", bad);
+        }
+    }
+
+    // Writing a complex number out and reading it back gives the same number
+    #[test]
+    fn display_round_trip() {
+        for val in [MyComplex {r:-0.8, i:0.156}, MyComplex {r:1e-7, i:-3.0e12}, MyComplex {r:0.1, i:-0.0}] {
+            assert_eq!(val.to_string().parse::<MyComplex<f32>>(), Ok(val));
+        }
+        assert_eq!(MyComplex {r:-0.5_f32, i:0.25}.to_string(), "-0.5+0.25i");
+    }
+
     /*
     #[test]
     fn complex_division() {
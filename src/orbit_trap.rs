@@ -0,0 +1,249 @@
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use super::error::{FracError, FracResult};
+use super::graphics::{Color, bmp_img_maker::BMPImg};
+use super::my_complex::MyComplex;
+
+// The names of the trap shapes, the same as the values of --trap
+pub const TRAP_SHAPES: [&str; 5] = ["point", "line", "cross", "circle", "image"];
+
+// An orbit trap in a scene file. The trap sits in the plane the orbits move
+// in, so for a Julia set it lines up with the view, and for a Mandelbrot set
+// it's the same everywhere. In a scene file:
+//
+//   [color.trap]
+//   shape = "circle"
+//   center = "0.25"
+//   radius = 0.5
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TrapSpec {
+    Point {
+        #[serde(default = "origin")]
+        center: MyComplex<f32>,
+    },
+    // The line through `center`, `angle` degrees counter-clockwise from the
+    // real axis
+    Line {
+        #[serde(default = "origin")]
+        center: MyComplex<f32>,
+        #[serde(default)]
+        angle: f64,
+    },
+    // A line like `Line` and the line at right angles to it
+    Cross {
+        #[serde(default = "origin")]
+        center: MyComplex<f32>,
+        #[serde(default)]
+        angle: f64,
+    },
+    Circle {
+        #[serde(default = "origin")]
+        center: MyComplex<f32>,
+        #[serde(default = "default_size")]
+        radius: f64,
+    },
+    // A BMP picture `size` wide (in complex units) laid over the plane.
+    // Orbits that land on it are colored with the pixel they land on.
+    Image {
+        file: String,
+        #[serde(default = "origin")]
+        center: MyComplex<f32>,
+        #[serde(default = "default_size")]
+        size: f64,
+    },
+}
+
+fn origin() -> MyComplex<f32> { MyComplex::new(0.0, 0.0) }
+fn default_size() -> f64 { 1.0 }
+
+// The closest an orbit came to a trap: how close, the iteration it happened
+// on, and where the orbit was then
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrapHit {
+    pub distance: f32,
+    pub iter: u32,
+    pub pos: MyComplex<f32>,
+}
+
+// The hit of an orbit that wasn't checked against a trap (or never got any
+// closer than infinitely far away)
+pub const NO_TRAP_HIT: TrapHit = TrapHit { distance: f32::INFINITY, iter: 0, pos: MyComplex::new(0.0, 0.0) };
+
+// The pixels of an image trap, a row at a time from the bottom left
+pub struct TrapImage {
+    width: u32,
+    height: u32,
+    colors: Vec<Color>,
+}
+
+// An orbit trap ready to measure orbit points against. Directions are unit
+// vectors.
+#[derive(Clone)]
+pub enum OrbitTrap {
+    Point(MyComplex<f32>),
+    Line {center: MyComplex<f32>, dir: MyComplex<f32>},
+    Cross {center: MyComplex<f32>, dir: MyComplex<f32>},
+    Circle {center: MyComplex<f32>, radius: f32},
+    // The picture covers the rectangle `half_width` and `half_height` out
+    // from `center`. It's shared between all of the fractals using it.
+    Image {center: MyComplex<f32>, half_width: f32, half_height: f32, image: Arc<TrapImage>},
+}
+
+impl TrapSpec {
+    // The trap the spec describes, reading the picture of an image trap
+    pub fn to_trap(&self) -> FracResult<OrbitTrap> {
+        let direction = |angle: f64| -> MyComplex<f32> {
+            MyComplex::new(angle.to_radians().cos() as f32, angle.to_radians().sin() as f32)
+        };
+        let check_size = |val: f64, name: &str| -> FracResult<f32> {
+            if val > 0.0 && val.is_finite() {
+                Ok(val as f32)
+            } else {
+                Err(FracError::InvalidInput(format!("The {} of an orbit trap has to be a positive number, not {}", name, val)))
+            }
+        };
+
+        match self {
+            TrapSpec::Point{center} => Ok(OrbitTrap::Point(*center)),
+            TrapSpec::Line{center, angle} => Ok(OrbitTrap::Line{center: *center, dir: direction(*angle)}),
+            TrapSpec::Cross{center, angle} => Ok(OrbitTrap::Cross{center: *center, dir: direction(*angle)}),
+            TrapSpec::Circle{center, radius} => Ok(OrbitTrap::Circle{center: *center, radius: check_size(*radius, "radius")?}),
+            TrapSpec::Image{file, center, size} => {
+                let half_width: f32 = check_size(*size, "size")? / 2.0;
+                let (width, height, colors): (u32, u32, Vec<Color>) = BMPImg::read_colors(file)?;
+                Ok(OrbitTrap::Image {
+                    center: *center,
+                    half_width,
+                    half_height: half_width * height as f32 / width as f32,
+                    image: Arc::new(TrapImage { width, height, colors }),
+                })
+            }
+        }
+    }
+}
+
+impl OrbitTrap {
+    // How far an orbit point is from the trap. Points on the picture of an
+    // image trap are 0 away.
+    pub fn distance(&self, z: MyComplex<f32>) -> f32 {
+        match self {
+            OrbitTrap::Point(center) => (z - *center).mag_sqr().sqrt(),
+            OrbitTrap::Line{center, dir} => {
+                let offset: MyComplex<f32> = z - *center;
+                (offset.r() * dir.i() - offset.i() * dir.r()).abs()
+            }
+            OrbitTrap::Cross{center, dir} => {
+                let offset: MyComplex<f32> = z - *center;
+                let across: f32 = (offset.r() * dir.i() - offset.i() * dir.r()).abs();
+                let along: f32 = (offset.r() * dir.r() + offset.i() * dir.i()).abs();
+                across.min(along)
+            }
+            OrbitTrap::Circle{center, radius} => ((z - *center).mag_sqr().sqrt() - radius).abs(),
+            OrbitTrap::Image{center, half_width, half_height, ..} => {
+                let offset: MyComplex<f32> = z - *center;
+                let outside_r: f32 = (offset.r().abs() - half_width).max(0.0);
+                let outside_i: f32 = (offset.i().abs() - half_height).max(0.0);
+                (outside_r * outside_r + outside_i * outside_i).sqrt()
+            }
+        }
+    }
+
+    // Whether the trap is its own mirror image across the real axis, so
+    // orbits of conjugate points come just as close to it. Traps centered
+    // on the axis are, as long as their lines are level with it, at right
+    // angles to it, or (for a cross) 45 degrees off it. An image trap's
+    // picture is never counted as symmetric.
+    pub fn is_conjugate_symmetric(&self) -> bool {
+        // Directions are worked out from angles in degrees, so they're only
+        // close to the exact ones
+        let level = |val: f32| -> bool { val.abs() < 1e-6 };
+        match self {
+            OrbitTrap::Point(center) | OrbitTrap::Circle{center, ..} => center.i() == 0.0,
+            OrbitTrap::Line{center, dir} => center.i() == 0.0 && (level(dir.r()) || level(dir.i())),
+            OrbitTrap::Cross{center, dir} => {
+                center.i() == 0.0 && (level(dir.r()) || level(dir.i()) || level(dir.r().abs() - dir.i().abs()))
+            }
+            OrbitTrap::Image{..} => false,
+        }
+    }
+
+    // The pixel of an image trap an orbit point landed on, if it's an image
+    // trap and the point is on the picture
+    pub fn image_color(&self, z: MyComplex<f32>) -> Option<Color> {
+        let (center, half_width, half_height, image) = match self {
+            OrbitTrap::Image{center, half_width, half_height, image} => (center, half_width, half_height, image),
+            _ => return None,
+        };
+        let offset: MyComplex<f32> = z - *center;
+        let x: f32 = (offset.r() + half_width) / (2.0 * half_width);
+        let y: f32 = (offset.i() + half_height) / (2.0 * half_height);
+        if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+            return None;
+        }
+        let col: u32 = ((x * image.width as f32) as u32).min(image.width - 1);
+        let row: u32 = ((y * image.height as f32) as u32).min(image.height - 1);
+        Some(image.colors[(row * image.width + col) as usize])
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trap_distances() {
+        let trap_distance = |spec: TrapSpec, z: MyComplex<f32>| -> f32 { spec.to_trap().unwrap().distance(z) };
+        let center = MyComplex::new(1.0, 1.0);
+        let z = MyComplex::new(4.0, 5.0);
+        assert_eq!(trap_distance(TrapSpec::Point{center}, z), 5.0);
+        assert_eq!(trap_distance(TrapSpec::Line{center, angle: 0.0}, z), 4.0);
+        assert!((trap_distance(TrapSpec::Line{center, angle: 90.0}, z) - 3.0).abs() < 1e-5);
+        assert!((trap_distance(TrapSpec::Cross{center, angle: 0.0}, z) - 3.0).abs() < 1e-5);
+        assert_eq!(trap_distance(TrapSpec::Circle{center, radius: 2.0}, z), 3.0);
+        assert_eq!(trap_distance(TrapSpec::Circle{center, radius: 7.0}, z), 2.0);
+        assert!(TrapSpec::Circle{center, radius: 0.0}.to_trap().is_err());
+    }
+
+    // An image trap is a rectangle as tall as the picture's shape makes it,
+    // and orbit points on it pick out its pixels
+    #[test]
+    fn image_trap() {
+        let path = std::env::temp_dir().join("fractal_generator_image_trap.bmp");
+        let file_name: &str = path.to_str().unwrap();
+        let colors: Vec<Color> = (0..8_u8).map(|val| Color{r: val, g: 0, b: 0}).collect();
+        let bmp_img_obj = BMPImg::new(4, 2, file_name).unwrap();
+        bmp_img_obj.set_header().unwrap();
+        bmp_img_obj.write_colors(&colors).unwrap();
+        let trap: OrbitTrap = TrapSpec::Image{file: String::from(file_name), center: origin(), size: 2.0}.to_trap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(trap.distance(MyComplex::new(0.9, 0.4)), 0.0);
+        assert_eq!(trap.distance(MyComplex::new(1.0, 1.5)), 1.0);
+        assert_eq!(trap.image_color(MyComplex::new(-0.9, -0.4)), Some(colors[0]));
+        assert_eq!(trap.image_color(MyComplex::new(0.9, 0.4)), Some(colors[7]));
+        assert_eq!(trap.image_color(MyComplex::new(0.0, 0.6)), None);
+        assert_eq!(OrbitTrap::Point(origin()).image_color(origin()), None);
+        assert!(!trap.is_conjugate_symmetric());
+    }
+
+    #[test]
+    fn conjugate_symmetric_traps() {
+        let symmetric = |spec: TrapSpec| -> bool { spec.to_trap().unwrap().is_conjugate_symmetric() };
+        let (on_axis, off_axis) = (MyComplex::new(0.5, 0.0), MyComplex::new(0.5, 0.1));
+        assert!(symmetric(TrapSpec::Point{center: on_axis}));
+        assert!(!symmetric(TrapSpec::Point{center: off_axis}));
+        assert!(symmetric(TrapSpec::Circle{center: on_axis, radius: 0.5}));
+        assert!(!symmetric(TrapSpec::Circle{center: off_axis, radius: 0.5}));
+        for angle in [0.0, 90.0, 180.0, 270.0] {
+            assert!(symmetric(TrapSpec::Line{center: on_axis, angle}));
+        }
+        assert!(!symmetric(TrapSpec::Line{center: on_axis, angle: 45.0}));
+        assert!(!symmetric(TrapSpec::Line{center: off_axis, angle: 0.0}));
+        assert!(symmetric(TrapSpec::Cross{center: on_axis, angle: 45.0}));
+        assert!(!symmetric(TrapSpec::Cross{center: on_axis, angle: 30.0}));
+    }
+}
@@ -0,0 +1,1058 @@
+use std::{ops::Range, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::{self, RecvTimeoutError}, Arc}, thread,
+    time::{Duration, Instant}};
+use super::error::{FracError, FracResult};
+use super::fractals::{Fractal, FracOutput, NO_INTERIOR_INFO};
+use super::graphics::PixPos;
+use super::my_complex::MyComplex;
+use super::orbit_trap::NO_TRAP_HIT;
+
+// A rendered image, kept as fractal outputs so that it can be colored (or
+// analyzed) after the whole image is known. Pixels are stored row by row,
+// starting with the bottom row, which is the order BMP pixels are written in.
+pub struct FracImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<FracOutput>,
+}
+
+impl FracImage {
+    pub fn new(width: u32, height: u32, pixels: Vec<FracOutput>) -> FracResult<Self> {
+        if pixels.len() != (width as usize) * (height as usize) {
+            return Err(FracError::InvalidInput(format!("{} pixels don't make up a {}x{} image", pixels.len(), width, height)));
+        }
+        Ok(Self { width, height, pixels })
+    }
+
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn pixels(&self) -> &[FracOutput] { &self.pixels }
+
+    pub fn pixel(&self, pos: PixPos) -> FracOutput {
+        self.pixels[pos.row as usize * self.width as usize + pos.col as usize]
+    }
+}
+
+// Run the pixel-position-to-fractal-output closure over every pixel and keep
+// the results
+pub fn render(width: u32, height: u32, pos_to_output: &dyn Fn(PixPos) -> FracOutput) -> FracImage {
+    let mut pixels: Vec<FracOutput> = Vec::with_capacity((width as usize) * (height as usize));
+    for row in 0..height {
+        for col in 0..width {
+            pixels.push(pos_to_output(PixPos{row, col}));
+        }
+    }
+    FracImage { width, height, pixels }
+}
+
+// Anything that can work out the fractal output of a pixel. The renderers
+// ask for whole runs of pixels in a row at a time through `row_span`, so
+// sources that can work on many pixels at once (like FracSampler) can do so.
+// Any `Fn(PixPos) -> FracOutput` closure is a pixel source.
+pub trait PixelSource: Sync {
+    fn pixel(&self, pos: PixPos) -> FracOutput;
+
+    // Fill `out` with the pixels of `row`, starting at column `col_start`
+    fn row_span(&self, row: u32, col_start: u32, out: &mut [FracOutput]) {
+        for (offset, out_val) in out.iter_mut().enumerate() {
+            *out_val = self.pixel(PixPos{row, col: col_start + offset as u32});
+        }
+    }
+
+    // Fill `out` with the pixels at each of `positions`, which can be
+    // anywhere in the image
+    fn pixel_list(&self, positions: &[PixPos], out: &mut [FracOutput]) {
+        for (pos, out_val) in positions.iter().zip(out.iter_mut()) {
+            *out_val = self.pixel(*pos);
+        }
+    }
+}
+
+impl<F: Fn(PixPos) -> FracOutput + Sync> PixelSource for F {
+    fn pixel(&self, pos: PixPos) -> FracOutput {
+        self(pos)
+    }
+}
+
+// Samples a fractal at the complex coordinate of each pixel. Runs of pixels
+// go through the batched (SIMD) Mandelbrot/Julia kernels.
+pub struct FracSampler<'a> {
+    fractal: &'a Fractal,
+    pix_to_complex: &'a (dyn Fn(PixPos) -> MyComplex<f32> + Sync),
+}
+
+impl<'a> FracSampler<'a> {
+    pub fn new(fractal: &'a Fractal, pix_to_complex: &'a (dyn Fn(PixPos) -> MyComplex<f32> + Sync)) -> Self {
+        Self { fractal, pix_to_complex }
+    }
+}
+
+impl<'a> PixelSource for FracSampler<'a> {
+    fn pixel(&self, pos: PixPos) -> FracOutput {
+        self.fractal.complex_to_frac_output((self.pix_to_complex)(pos))
+    }
+
+    fn row_span(&self, row: u32, col_start: u32, out: &mut [FracOutput]) {
+        let points: Vec<MyComplex<f32>> = (0..out.len() as u32)
+            .map(|offset| (self.pix_to_complex)(PixPos{row, col: col_start + offset}))
+            .collect();
+        self.fractal.complex_slice_to_frac_output(&points, out);
+    }
+
+    fn pixel_list(&self, positions: &[PixPos], out: &mut [FracOutput]) {
+        let points: Vec<MyComplex<f32>> = positions.iter().map(|pos| (self.pix_to_complex)(*pos)).collect();
+        self.fractal.complex_slice_to_frac_output(&points, out);
+    }
+}
+
+// Where the samples go inside each pixel when supersampling. Every pattern
+// splits the pixel into a square grid with one sample in each cell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplePattern {
+    // The center of each cell
+    Grid,
+    // A random spot in each cell, different in every pixel
+    Jittered,
+    // The grid turned by atan(1 / cells per side) and stretched back out
+    // to the edges of the pixel, so no two samples share a row or column,
+    // which smooths nearly horizontal and vertical edges better
+    RotatedGrid,
+}
+
+pub const SAMPLE_PATTERN_NAMES: [&str; 3] = ["grid", "jittered", "rotated"];
+
+impl SamplePattern {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "grid" => Some(SamplePattern::Grid),
+            "jittered" => Some(SamplePattern::Jittered),
+            "rotated" => Some(SamplePattern::RotatedGrid),
+            _ => None,
+        }
+    }
+
+    // Where sample number `sample` (out of `samples`, which has to be a
+    // square number) of `pixel` goes, as the distance across and up from
+    // the pixel's bottom left corner (0 - 1, in pixels)
+    pub fn offset(&self, samples: u32, sample: u32, pixel: PixPos) -> (f32, f32) {
+        let side: u32 = (samples as f32).sqrt().round() as u32;
+        let side_f32: f32 = side as f32;
+        let (cell_x, cell_y): (f32, f32) = ((sample % side) as f32, (sample / side) as f32);
+
+        match self {
+            SamplePattern::Grid => ((cell_x + 0.5) / side_f32, (cell_y + 0.5) / side_f32),
+            SamplePattern::Jittered => {
+                let hash: u32 = jitter_hash(pixel, sample);
+                let jitter_x: f32 = (hash & 0xffff) as f32 / 65_536.0;
+                let jitter_y: f32 = (hash >> 16) as f32 / 65_536.0;
+                ((cell_x + jitter_x) / side_f32, (cell_y + jitter_y) / side_f32)
+            }
+            SamplePattern::RotatedGrid => {
+                // Relative to the middle of the pixel
+                let u: f32 = (cell_x + 0.5) / side_f32 - 0.5;
+                let v: f32 = (cell_y + 0.5) / side_f32 - 0.5;
+                (0.5 + u - v / side_f32, 0.5 + v + u / side_f32)
+            }
+        }
+    }
+}
+
+// Mix the position of a pixel and a sample number into a random looking
+// number, so jittered samples land in the same spots every render
+fn jitter_hash(pixel: PixPos, sample: u32) -> u32 {
+    let mut hash: u64 = (((pixel.row as u64) << 32) | pixel.col as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ sample as u64;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (hash ^ (hash >> 31)) as u32
+}
+
+// The number of threads to render with when none is given: one per CPU core
+pub fn default_thread_count() -> usize {
+    thread::available_parallelism().map(|count| count.get()).unwrap_or(1)
+}
+
+// The value left in pixels that never got rendered because the render was
+// cancelled. It's colored the same as the inside of the set.
+pub const UNRENDERED: FracOutput = FracOutput::MandelJulia{iters: 0, max_iters: 0, final_mag: 0.0, period: 0, distance: 0.0, trap: NO_TRAP_HIT,
+                                                               interior: NO_INTERIOR_INFO};
+
+// How the pixels of each tile are worked out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    // Every pixel is calculated
+    Full,
+    // Mariani-Silver subdivision: only the border of a rectangle is
+    // calculated, and if the whole border is in the same region (the same
+    // Newton root, or the same bulb of the Mandelbrot set) the inside is
+    // filled in with the border's value. Otherwise the rectangle is split in
+    // two and each half is tried again. This is much faster for Newton
+    // fractals with large basins, but it can miss details that are small
+    // enough to fit inside a rectangle without touching its border.
+    Subdivide,
+}
+
+// Lets the renderer skip rows that are the mirror image of other rows, for
+// views of a fractal that's symmetric across the real axis. Row `row` is the
+// mirror image of row `axis_sum - row`, so for an image whose imaginary axis
+// runs from -2 to 2 over 600 rows, `axis_sum` is 600 (row 300 is on the real
+// axis). The mirrored pixels are worked out with FracOutput::conjugate.
+pub struct RowMirror {
+    axis_sum: u32,
+    root_map: Vec<usize>,
+}
+
+impl RowMirror {
+    // `root_map` is from Fractal::conjugate_root_map
+    pub fn new(axis_sum: u32, root_map: Vec<usize>) -> Self {
+        Self { axis_sum, root_map }
+    }
+
+    // The rows that are copied from their mirror image instead of being
+    // rendered (the ones whose mirror image is below them)
+    fn mirrored_rows(&self, height: u32) -> Range<u32> {
+        let start: u32 = (self.axis_sum / 2 + 1).min(height);
+        start..(self.axis_sum + 1).min(height).max(start)
+    }
+}
+
+// A rectangle of pixels handed to one thread at a time (and the rectangles
+// a tile is split into by the subdivision renderer)
+#[derive(Clone, Copy, Debug)]
+struct Tile {
+    row: u32,
+    col: u32,
+    width: u32,
+    height: u32,
+}
+
+// Splits an image into small tiles and hands them out to a pool of threads
+// as the threads finish their previous tile, so the threads stay busy no
+// matter how uneven the cost of the pixels is. The finished tiles are copied
+// into the image on the calling thread, which also reports progress and
+// watches for the render being cancelled.
+pub struct TileScheduler {
+    threads: usize,
+    tile_size: u32,
+    mode: RenderMode,
+    mirror: Option<RowMirror>,
+    progress: bool,
+    cancel: Arc<AtomicBool>,
+}
+
+impl TileScheduler {
+    pub fn new(threads: usize) -> Self {
+        TileScheduler {
+            threads: threads.max(1),
+            tile_size: 32,
+            mode: RenderMode::Full,
+            mirror: None,
+            progress: false,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn set_tile_size(&mut self, val: u32) {
+        self.tile_size = val.max(1);
+    }
+
+    pub fn set_mode(&mut self, val: RenderMode) {
+        self.mode = val;
+    }
+
+    // Only render one side of the real axis, and mirror it onto the other.
+    // Only use this for fractals that are symmetric across the real axis.
+    pub fn set_mirror(&mut self, val: Option<RowMirror>) {
+        self.mirror = val;
+    }
+
+    // Whether to print the percent complete, speed and time left to stderr
+    pub fn set_progress(&mut self, val: bool) {
+        self.progress = val;
+    }
+
+    // Setting this flag (from a Ctrl-C handler, for example) stops the
+    // render after the tiles currently being worked on. The pixels that
+    // were finished are still returned.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
+    // Watch a flag shared with other renders, so one Ctrl-C handler can
+    // stop a whole series of them
+    pub fn set_cancel_flag(&mut self, val: Arc<AtomicBool>) {
+        self.cancel = val;
+    }
+
+    pub fn was_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    // Split a range of rows into tiles
+    fn tiles_in_rows(&self, width: u32, rows: Range<u32>) -> Vec<Tile> {
+        let mut tiles: Vec<Tile> = Vec::new();
+        for row in rows.clone().step_by(self.tile_size as usize) {
+            for col in (0..width).step_by(self.tile_size as usize) {
+                tiles.push(Tile {
+                    row, col,
+                    width: self.tile_size.min(width - col),
+                    height: self.tile_size.min(rows.end - row),
+                });
+            }
+        }
+        tiles
+    }
+
+    // Split the rows that have to be rendered into tiles
+    fn tiles(&self, width: u32, height: u32) -> Vec<Tile> {
+        let mirrored: Range<u32> = match &self.mirror {
+            Some(mirror) => mirror.mirrored_rows(height),
+            None => height..height,
+        };
+
+        let mut tiles: Vec<Tile> = self.tiles_in_rows(width, 0..mirrored.start);
+        tiles.extend(self.tiles_in_rows(width, mirrored.end..height));
+        tiles
+    }
+
+    pub fn render(&self, width: u32, height: u32, source: &dyn PixelSource) -> FracImage {
+        let tiles: Vec<Tile> = self.tiles(width, height);
+        let total: u64 = tiles.iter().map(|tile| (tile.width as u64) * (tile.height as u64)).sum();
+        let mut progress = ProgressReport::new(total, self.progress);
+        let mut pixels: Vec<FracOutput> = self.render_tiles(width, height, &tiles, source, &mut progress);
+        progress.print(true);
+
+        if let Some(mirror) = &self.mirror {
+            let row_len: usize = width as usize;
+            for row in mirror.mirrored_rows(height) {
+                let src_start: usize = (mirror.axis_sum - row) as usize * row_len;
+                let (before, after) = pixels.split_at_mut(row as usize * row_len);
+                for (pixel, src_pixel) in after[..row_len].iter_mut().zip(&before[src_start..src_start + row_len]) {
+                    *pixel = src_pixel.conjugate(&mirror.root_map);
+                }
+            }
+        }
+
+        FracImage { width, height, pixels }
+    }
+
+    // Render the given tiles of an image on the worker threads, returning
+    // the pixels of the whole image (with the pixels that weren't in a tile
+    // left unrendered)
+    fn render_tiles(&self, width: u32, height: u32, tiles: &[Tile], source: &dyn PixelSource,
+                    progress: &mut ProgressReport) -> Vec<FracOutput> {
+        let mut pixels: Vec<FracOutput> = vec![UNRENDERED; (width as usize) * (height as usize)];
+        let next_tile: AtomicUsize = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel::<(Tile, Vec<FracOutput>)>();
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let sender = sender.clone();
+                let next_tile: &AtomicUsize = &next_tile;
+                scope.spawn(move || {
+                    while !self.cancel.load(Ordering::Relaxed) {
+                        let tile: Tile = match tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                            Some(tile) => *tile,
+                            None => break,
+                        };
+                        let tile_pixels: Vec<FracOutput> = render_tile(source, tile, self.mode);
+                        if sender.send((tile, tile_pixels)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            // Only the worker threads hold senders now, so the loop below
+            // ends once they've all finished
+            drop(sender);
+
+            loop {
+                match receiver.recv_timeout(PROGRESS_INTERVAL) {
+                    Ok((tile, tile_pixels)) => {
+                        for (tile_row, row_pixels) in tile_pixels.chunks(tile.width as usize).enumerate() {
+                            let start: usize = (tile.row as usize + tile_row) * width as usize + tile.col as usize;
+                            pixels[start..start + row_pixels.len()].copy_from_slice(row_pixels);
+                        }
+                        progress.add((tile.width as u64) * (tile.height as u64));
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                progress.print(false);
+            }
+        });
+        pixels
+    }
+
+    // Render `samples` samples of each pixel in `pixels`, for supersampling.
+    // `sample_to_complex` gives the complex number of each sample of a
+    // pixel. The samples are handed to `on_chunk` for a chunk of pixels at
+    // a time (along with the pixels), `samples` in a row for each pixel, so
+    // the samples of a big image never all have to be kept at once.
+    pub fn render_samples(&self, fractal: &Fractal, pixels: &[PixPos], samples: u32,
+                          sample_to_complex: &(dyn Fn(PixPos, u32) -> MyComplex<f32> + Sync),
+                          on_chunk: &mut dyn FnMut(&[PixPos], &[FracOutput])) {
+        let chunk_len: usize = (SAMPLE_CHUNK_SIZE / samples.max(1) as usize).max(1);
+        let mut progress = ProgressReport::new(pixels.len() as u64 * samples as u64, self.progress);
+        for chunk in pixels.chunks(chunk_len) {
+            // Row `row` of the chunk's image is the samples of `chunk[row]`
+            let chunk_to_complex = |pos: PixPos| -> MyComplex<f32> { sample_to_complex(chunk[pos.row as usize], pos.col) };
+            let sampler = FracSampler::new(fractal, &chunk_to_complex);
+            let tiles: Vec<Tile> = self.tiles_in_rows(samples, 0..chunk.len() as u32);
+            let chunk_samples: Vec<FracOutput> = self.render_tiles(samples, chunk.len() as u32, &tiles, &sampler, &mut progress);
+            if self.was_cancelled() {
+                break;
+            }
+            on_chunk(chunk, &chunk_samples);
+        }
+        progress.print(true);
+    }
+}
+
+// Work out the pixels of a tile, row by row starting at its bottom row
+fn render_tile(source: &dyn PixelSource, tile: Tile, mode: RenderMode) -> Vec<FracOutput> {
+    let mut tile_pixels: Vec<FracOutput> = vec![UNRENDERED; (tile.width * tile.height) as usize];
+    match mode {
+        RenderMode::Full => {
+            for (tile_row, row_pixels) in tile_pixels.chunks_mut(tile.width as usize).enumerate() {
+                source.row_span(tile.row + tile_row as u32, tile.col, row_pixels);
+            }
+        }
+        RenderMode::Subdivide => {
+            // Calculate the border of the whole tile, then work inwards.
+            // Rectangles inside the tile are given in tile coordinates.
+            let mut subdivider = Subdivider { source, tile, pixels: &mut tile_pixels };
+            subdivider.calc_row(0, 0, tile.width);
+            subdivider.calc_row(tile.height - 1, 0, tile.width);
+            subdivider.calc_col(0, 1, tile.height.saturating_sub(2));
+            subdivider.calc_col(tile.width - 1, 1, tile.height.saturating_sub(2));
+            subdivider.subdivide(Tile { row: 0, col: 0, width: tile.width, height: tile.height });
+        }
+    }
+    tile_pixels
+}
+
+// Rectangles smaller than this (in pixels on a side) aren't split any
+// further; the inside is just calculated
+const MIN_SUBDIVIDE_SIZE: u32 = 6;
+
+// Whether two pixels are in the same region for the subdivision renderer, so
+// a rectangle bordered by them can be filled in with copies of one. Only
+// pixels with exactly the same output are. The smooth values and distance
+// estimates of escaping points, and the final |z|, trap hits and interior
+// data of the points that were iterated inside the set, are different from
+// one orbit to the next, so Mandelbrot and Julia sets are only filled in
+// where the whole border is inside the bulbs Fractal finds without iterating
+// (which all end with |z| of 0).
+fn same_region(a: FracOutput, b: FracOutput) -> bool {
+    match a {
+        FracOutput::MandelJulia{iters, max_iters, final_mag, ..} => iters >= max_iters && final_mag == 0.0 && a == b,
+        FracOutput::Newton{..} => a == b,
+    }
+}
+
+// The state of the Mariani-Silver subdivision of one tile
+struct Subdivider<'a> {
+    source: &'a dyn PixelSource,
+    tile: Tile,
+    pixels: &'a mut [FracOutput],
+}
+
+impl<'a> Subdivider<'a> {
+    fn index(&self, row: u32, col: u32) -> usize {
+        (row * self.tile.width + col) as usize
+    }
+
+    // Calculate `len` pixels of a row of the tile, starting at `col`
+    fn calc_row(&mut self, row: u32, col: u32, len: u32) {
+        let start: usize = self.index(row, col);
+        self.source.row_span(self.tile.row + row, self.tile.col + col, &mut self.pixels[start..start + len as usize]);
+    }
+
+    // Calculate `len` pixels of a column of the tile, starting at `row`
+    fn calc_col(&mut self, col: u32, row: u32, len: u32) {
+        self.calc_rect(Tile { row, col, width: 1, height: len });
+    }
+
+    // Calculate every pixel in a rectangle of the tile in one batch
+    fn calc_rect(&mut self, rect: Tile) {
+        let positions: Vec<PixPos> = (rect.row..rect.row + rect.height)
+            .flat_map(|row| (rect.col..rect.col + rect.width).map(move |col| PixPos{row, col}))
+            .collect();
+        let image_positions: Vec<PixPos> = positions.iter()
+            .map(|pos| PixPos{row: self.tile.row + pos.row, col: self.tile.col + pos.col})
+            .collect();
+        let mut rect_pixels: Vec<FracOutput> = vec![UNRENDERED; positions.len()];
+        self.source.pixel_list(&image_positions, &mut rect_pixels);
+
+        for (pos, pixel) in positions.iter().zip(rect_pixels) {
+            let idx: usize = self.index(pos.row, pos.col);
+            self.pixels[idx] = pixel;
+        }
+    }
+
+    // Fill in the inside of a rectangle whose border has already been
+    // calculated
+    fn subdivide(&mut self, rect: Tile) {
+        if rect.width <= 2 || rect.height <= 2 {
+            // It's all border
+            return;
+        }
+        let (top, right): (u32, u32) = (rect.row + rect.height - 1, rect.col + rect.width - 1);
+
+        let first: FracOutput = self.pixels[self.index(rect.row, rect.col)];
+        let border_same: bool = (rect.col..=right).all(|col| {
+                same_region(first, self.pixels[self.index(rect.row, col)])
+                && same_region(first, self.pixels[self.index(top, col)])
+            })
+            && (rect.row + 1..top).all(|row| {
+                same_region(first, self.pixels[self.index(row, rect.col)])
+                && same_region(first, self.pixels[self.index(row, right)])
+            });
+
+        if border_same {
+            for row in rect.row + 1..top {
+                let start: usize = self.index(row, rect.col + 1);
+                self.pixels[start..start + rect.width as usize - 2].fill(first);
+            }
+        } else if rect.width < MIN_SUBDIVIDE_SIZE || rect.height < MIN_SUBDIVIDE_SIZE {
+            self.calc_rect(Tile { row: rect.row + 1, col: rect.col + 1, width: rect.width - 2, height: rect.height - 2 });
+        } else if rect.width >= rect.height {
+            // Split it with a column down the middle. Both halves share the
+            // new column as part of their border.
+            let mid: u32 = rect.col + rect.width / 2;
+            self.calc_col(mid, rect.row + 1, rect.height - 2);
+            self.subdivide(Tile { width: mid - rect.col + 1, ..rect });
+            self.subdivide(Tile { col: mid, width: right - mid + 1, ..rect });
+        } else {
+            // Split it with a row across the middle
+            let mid: u32 = rect.row + rect.height / 2;
+            self.calc_row(mid, rect.col + 1, rect.width - 2);
+            self.subdivide(Tile { height: mid - rect.row + 1, ..rect });
+            self.subdivide(Tile { row: mid, height: top - mid + 1, ..rect });
+        }
+    }
+}
+
+// The spacing of the pixels calculated by the first pass of the
+// progressive renderer
+const PROGRESSIVE_START_STEP: u32 = 16;
+
+impl TileScheduler {
+    // Render the image in passes of increasing detail: first every 16th
+    // pixel in each direction, then every 8th, 4th, 2nd, and finally all of
+    // them. After each pass, `on_pass` is called with the pass's pixel
+    // spacing and a preview image the full size of the final image, where
+    // each calculated pixel is copied over the block of pixels it stands
+    // for. Pixels found by an earlier pass aren't calculated again, so this
+    // costs about the same as `render`. If the render is cancelled, the
+    // unfinished pass is thrown away and the last preview is returned.
+    pub fn render_progressive(&self, width: u32, height: u32, source: &dyn PixelSource,
+                              on_pass: &mut dyn FnMut(u32, &FracImage)) -> FracImage {
+        let mut preview = FracImage { width, height, pixels: vec![UNRENDERED; (width as usize) * (height as usize)] };
+
+        // Each pass is rendered as its own smaller image
+        let mut pass_scheduler = TileScheduler {
+            threads: self.threads,
+            tile_size: self.tile_size,
+            mode: self.mode,
+            mirror: None,
+            progress: false,
+            cancel: Arc::clone(&self.cancel),
+        };
+
+        let mut step: u32 = PROGRESSIVE_START_STEP;
+        let mut first_pass: bool = true;
+        loop {
+            // The rows of a pass can only be mirrored if the axis lines up
+            // with them
+            pass_scheduler.mirror = self.mirror.as_ref()
+                .filter(|mirror| mirror.axis_sum.is_multiple_of(step))
+                .map(|mirror| RowMirror::new(mirror.axis_sum / step, mirror.root_map.clone()));
+
+            let pass_source = PassSource { source, step, known: if first_pass { None } else { Some(&preview) } };
+            let pass: FracImage = pass_scheduler.render(width.div_ceil(step), height.div_ceil(step), &pass_source);
+            if self.was_cancelled() {
+                break;
+            }
+
+            // Copy each pixel of the pass over its block of the preview
+            for pass_row in 0..pass.height() {
+                for pass_col in 0..pass.width() {
+                    let val: FracOutput = pass.pixel(PixPos{row: pass_row, col: pass_col});
+                    for row in pass_row * step..((pass_row + 1) * step).min(height) {
+                        let start: usize = (row * width + pass_col * step) as usize;
+                        let end: usize = (row * width + ((pass_col + 1) * step).min(width)) as usize;
+                        preview.pixels[start..end].fill(val);
+                    }
+                }
+            }
+            on_pass(step, &preview);
+
+            if step == 1 {
+                break;
+            }
+            step /= 2;
+            first_pass = false;
+        }
+        preview
+    }
+}
+
+// The pixels of one pass of the progressive renderer. Pixel (row, col) of
+// the pass is pixel (row * step, col * step) of the image. The pixels that
+// the previous pass (with twice the step) already found are copied from its
+// preview, `known`, instead of being calculated again.
+struct PassSource<'a> {
+    source: &'a dyn PixelSource,
+    step: u32,
+    known: Option<&'a FracImage>,
+}
+
+impl<'a> PassSource<'a> {
+    fn image_pos(&self, pos: PixPos) -> PixPos {
+        PixPos{row: pos.row * self.step, col: pos.col * self.step}
+    }
+
+    fn is_known(&self, pos: PixPos) -> bool {
+        self.known.is_some() && pos.row.is_multiple_of(2) && pos.col.is_multiple_of(2)
+    }
+}
+
+impl<'a> PixelSource for PassSource<'a> {
+    fn pixel(&self, pos: PixPos) -> FracOutput {
+        match self.known {
+            Some(known) if self.is_known(pos) => known.pixel(self.image_pos(pos)),
+            _ => self.source.pixel(self.image_pos(pos)),
+        }
+    }
+
+    fn row_span(&self, row: u32, col_start: u32, out: &mut [FracOutput]) {
+        let positions: Vec<PixPos> = (0..out.len() as u32).map(|offset| PixPos{row, col: col_start + offset}).collect();
+        self.pixel_list(&positions, out);
+    }
+
+    fn pixel_list(&self, positions: &[PixPos], out: &mut [FracOutput]) {
+        // Only send the new pixels to the source, in one batch
+        let new_positions: Vec<PixPos> = positions.iter()
+            .filter(|pos| !self.is_known(**pos))
+            .map(|pos| self.image_pos(*pos))
+            .collect();
+        let mut new_pixels: Vec<FracOutput> = vec![UNRENDERED; new_positions.len()];
+        self.source.pixel_list(&new_positions, &mut new_pixels);
+
+        let mut new_pixels = new_pixels.into_iter();
+        for (pos, out_val) in positions.iter().zip(out.iter_mut()) {
+            *out_val = match self.known {
+                Some(known) if self.is_known(*pos) => known.pixel(self.image_pos(*pos)),
+                _ => new_pixels.next().unwrap(),
+            };
+        }
+    }
+}
+
+// The most samples `render_samples` keeps at once
+const SAMPLE_CHUNK_SIZE: usize = 1 << 22;
+
+// How often the progress line is updated
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// Keeps track of how many pixels are done and prints a progress line to
+// stderr (when turned on)
+struct ProgressReport {
+    total: u64,
+    done: u64,
+    enabled: bool,
+    start: Instant,
+    last_print: Option<Instant>,
+}
+
+impl ProgressReport {
+    fn new(total: u64, enabled: bool) -> Self {
+        ProgressReport { total, done: 0, enabled, start: Instant::now(), last_print: None }
+    }
+
+    fn add(&mut self, pixels: u64) {
+        self.done += pixels;
+    }
+
+    // Print the progress line if it's been long enough since the last one.
+    // The final print always happens and ends the line.
+    fn print(&mut self, last: bool) {
+        if !self.enabled {
+            return;
+        }
+        if !last && self.last_print.is_some_and(|time| time.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        self.last_print = Some(Instant::now());
+
+        let elapsed: f64 = self.start.elapsed().as_secs_f64();
+        let percent: f64 = 100.0 * self.done as f64 / self.total.max(1) as f64;
+        let rate: f64 = if elapsed > 0.0 { self.done as f64 / elapsed } else { 0.0 };
+        let eta: String = if rate > 0.0 {
+            let secs_left: u64 = ((self.total - self.done) as f64 / rate).round() as u64;
+            format!("{}:{:02}", secs_left / 60, secs_left % 60)
+        } else {
+            String::from("--:--")
+        };
+
+        eprint!("\r{:5.1}% | {:.2} Mpix/s | ETA {}   ", percent, rate / 1.0e6, eta);
+        if last {
+            eprintln!();
+        }
+    }
+}
+
+// Render the image on `threads` threads with the default tile scheduler
+// settings. Every tile is written into its place in the image, so the
+// result is the same as `render`.
+pub fn render_parallel(width: u32, height: u32, threads: usize, source: &dyn PixelSource) -> FracImage {
+    TileScheduler::new(threads).render(width, height, source)
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_output(pix_pos: PixPos) -> FracOutput {
+        FracOutput::MandelJulia{iters: pix_pos.row * 1000 + pix_pos.col, max_iters: 100_000, final_mag: 2.0, period: 0, distance: 0.0,
+                                trap: NO_TRAP_HIT, interior: NO_INTERIOR_INFO}
+    }
+
+    // Rendering on any number of threads should put every pixel in the same
+    // place as rendering on one
+    #[test]
+    fn parallel_matches_serial() {
+        let serial: FracImage = render(37, 23, &test_output);
+        for threads in [1, 2, 7, 64] {
+            let parallel: FracImage = render_parallel(37, 23, threads, &test_output);
+            assert_eq!(parallel.pixels(), serial.pixels());
+        }
+    }
+
+    // Images only get made with exactly one output per pixel
+    #[test]
+    fn image_size_must_match() {
+        assert!(FracImage::new(3, 2, vec![UNRENDERED; 6]).is_ok());
+        assert!(matches!(FracImage::new(3, 2, vec![UNRENDERED; 5]), Err(FracError::InvalidInput(_))));
+    }
+
+    // Tiles that don't evenly divide the image still cover every pixel once
+    #[test]
+    fn tiles_cover_image() {
+        let mut scheduler = TileScheduler::new(3);
+        scheduler.set_tile_size(5);
+        assert_eq!(scheduler.render(12, 7, &test_output).pixels(), render(12, 7, &test_output).pixels());
+
+        let covered: u32 = scheduler.tiles(12, 7).iter().map(|tile| tile.width * tile.height).sum();
+        assert_eq!(covered, 12 * 7);
+    }
+
+    // Mirroring rows across the real axis should give the same image as
+    // rendering all of them, including the swapped Newton roots, whether
+    // the axis is in the middle of the image or not
+    #[test]
+    fn mirrored_matches_full() {
+        use super::super::fractals::FractalType;
+
+        for kind in [FractalType::Mandelbrot(), FractalType::Julia(MyComplex::new(-0.75, 0.0)),
+                     FractalType::Newton(vec![MyComplex::new(-0.5, 0.875), MyComplex::new(1.0, 0.0),
+                                              MyComplex::new(-0.5, -0.875)])] {
+            let fractal = Fractal::new(100, kind).unwrap();
+            let root_map: Vec<usize> = fractal.conjugate_root_map().unwrap();
+            for axis_row in [0, 12, 20, 30, 50] {
+                // Multiples of 1/32 keep the mirrored points exact
+                let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+                    MyComplex::new(-2.0 + pix_pos.col as f32 / 32.0, (pix_pos.row as f32 - axis_row as f32) / 16.0)
+                };
+                let sampler = FracSampler::new(&fractal, &pix_to_complex);
+
+                let mut scheduler = TileScheduler::new(2);
+                scheduler.set_tile_size(7);
+                scheduler.set_mirror(Some(RowMirror::new(2 * axis_row, root_map.clone())));
+                assert_eq!(scheduler.render(96, 40, &sampler).pixels(), render_parallel(96, 40, 2, &sampler).pixels());
+            }
+        }
+    }
+
+    // Orbit traps centered on the real axis keep the fractal symmetric, and
+    // the mirrored trap hits match the ones found by rendering every row
+    #[test]
+    fn mirrored_trap_matches_full() {
+        use super::super::fractals::FractalType;
+        use super::super::orbit_trap::OrbitTrap;
+
+        for kind in [FractalType::Mandelbrot(), FractalType::Julia(MyComplex::new(-0.75, 0.0))] {
+            let mut fractal = Fractal::new(100, kind).unwrap();
+            fractal.set_trap(Some(OrbitTrap::Cross{center: MyComplex::new(-0.5, 0.0), dir: MyComplex::new(1.0, 0.0)}));
+            let root_map: Vec<usize> = fractal.conjugate_root_map().unwrap();
+            let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+                MyComplex::new(-2.0 + pix_pos.col as f32 / 32.0, (pix_pos.row as f32 - 12.0) / 16.0)
+            };
+            let sampler = FracSampler::new(&fractal, &pix_to_complex);
+
+            let mut scheduler = TileScheduler::new(2);
+            scheduler.set_tile_size(7);
+            scheduler.set_mirror(Some(RowMirror::new(24, root_map)));
+            let mirrored: FracImage = scheduler.render(96, 40, &sampler);
+            assert_eq!(mirrored.pixels(), render_parallel(96, 40, 2, &sampler).pixels());
+            assert!(mirrored.pixels().iter().any(|pixel| matches!(pixel, FracOutput::MandelJulia{trap, ..} if trap.pos.i() < 0.0)));
+        }
+    }
+
+    // Interior data mirrors too: the angle of the average orbit point and
+    // the cycle multiplier are conjugated
+    #[test]
+    fn mirrored_interior_matches_full() {
+        use super::super::fractals::FractalType;
+
+        for kind in [FractalType::Mandelbrot(), FractalType::Julia(MyComplex::new(-0.5, 0.0))] {
+            let mut fractal = Fractal::new(200, kind).unwrap();
+            fractal.set_interior(true);
+            let root_map: Vec<usize> = fractal.conjugate_root_map().unwrap();
+            let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+                MyComplex::new(-2.0 + pix_pos.col as f32 / 32.0, (pix_pos.row as f32 - 12.0) / 16.0)
+            };
+            let sampler = FracSampler::new(&fractal, &pix_to_complex);
+
+            let mut scheduler = TileScheduler::new(2);
+            scheduler.set_tile_size(7);
+            scheduler.set_mirror(Some(RowMirror::new(24, root_map)));
+            let mirrored: FracImage = scheduler.render(96, 40, &sampler);
+            assert_eq!(mirrored.pixels(), render_parallel(96, 40, 2, &sampler).pixels());
+            assert!(mirrored.pixels().iter().any(|pixel| matches!(pixel, FracOutput::MandelJulia{interior, ..} if interior.angle < 0.0)));
+        }
+    }
+
+    // A cancelled render stops handing out tiles, and leaves the rest of the
+    // image unrendered
+    #[test]
+    fn cancelled_render() {
+        let scheduler = TileScheduler::new(2);
+        scheduler.cancel_flag().store(true, Ordering::Relaxed);
+        let image: FracImage = scheduler.render(40, 40, &test_output);
+
+        assert!(scheduler.was_cancelled());
+        assert!(image.pixels().iter().all(|pixel| *pixel == UNRENDERED));
+    }
+
+    // The subdivision renderer should find the same outputs as calculating
+    // every pixel, on views with big areas inside the set as well as views
+    // that are almost all boundary
+    #[test]
+    fn subdivide_matches_full() {
+        use super::super::fractals::FractalType;
+
+        let views: [(FractalType, [f32; 4]); 4] = [
+            (FractalType::Mandelbrot(), [-2.2, 0.8, -1.2, 1.2]),
+            (FractalType::Mandelbrot(), [-0.76, -0.72, 0.08, 0.12]),
+            (FractalType::Julia(MyComplex::new(-0.12, 0.75)), [-1.6, 1.6, -1.2, 1.2]),
+            (FractalType::Newton(vec![MyComplex::new(1.0, 0.0), MyComplex::new(-0.5, 0.866), MyComplex::new(-0.5, -0.866)]),
+             [-2.0, 2.0, -1.5, 1.5]),
+        ];
+        for (kind, [r_start, r_end, i_start, i_end]) in views {
+            let fractal = Fractal::new(300, kind).unwrap();
+            let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+                MyComplex::new(r_start + pix_pos.col as f32 / 160.0 * (r_end - r_start),
+                               i_start + pix_pos.row as f32 / 120.0 * (i_end - i_start))
+            };
+            let sampler = FracSampler::new(&fractal, &pix_to_complex);
+
+            let full: FracImage = render_parallel(160, 120, 2, &sampler);
+            let mut scheduler = TileScheduler::new(2);
+            scheduler.set_tile_size(40);
+            scheduler.set_mode(RenderMode::Subdivide);
+            let subdivided: FracImage = scheduler.render(160, 120, &sampler);
+
+            let mismatches: usize = full.pixels().iter().zip(subdivided.pixels())
+                .filter(|(full_val, sub_val)| full_val != sub_val)
+                .count();
+            assert_eq!(mismatches, 0);
+        }
+    }
+
+    // Pixels are only filled in with a trap hit that every pixel on the
+    // border shares, so none of them are left with another pixel's hit
+    #[test]
+    fn subdivide_keeps_trap_hits() {
+        use super::super::fractals::FractalType;
+        use super::super::orbit_trap::OrbitTrap;
+
+        let mut fractal = Fractal::new(200, FractalType::Mandelbrot()).unwrap();
+        fractal.set_trap(Some(OrbitTrap::Circle{center: MyComplex::new(-0.5, 0.25), radius: 0.25}));
+        let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+            MyComplex::new(-2.2 + pix_pos.col as f32 / 40.0, -1.2 + pix_pos.row as f32 / 40.0)
+        };
+        let sampler = FracSampler::new(&fractal, &pix_to_complex);
+
+        let mut scheduler = TileScheduler::new(2);
+        scheduler.set_tile_size(40);
+        scheduler.set_mode(RenderMode::Subdivide);
+        let subdivided: FracImage = scheduler.render(120, 96, &sampler);
+        let full: FracImage = render_parallel(120, 96, 2, &sampler);
+        for (full_val, sub_val) in full.pixels().iter().zip(subdivided.pixels()) {
+            match (full_val, sub_val) {
+                (FracOutput::MandelJulia{iters: full_iters, trap: full_trap, ..}, FracOutput::MandelJulia{iters: sub_iters, trap: sub_trap, ..}) => {
+                    assert_eq!((full_iters, full_trap), (sub_iters, sub_trap));
+                }
+                _ => panic!("Not a Mandelbrot output"),
+            }
+        }
+    }
+
+    // Escaping pixels, which are the only ones with a distance estimate,
+    // aren't filled in with another pixel's distance
+    #[test]
+    fn subdivide_keeps_distances() {
+        use super::super::fractals::FractalType;
+
+        let mut fractal = Fractal::new(200, FractalType::Mandelbrot()).unwrap();
+        fractal.set_distance_estimate(true);
+        let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+            MyComplex::new(-2.2 + pix_pos.col as f32 / 40.0, -1.2 + pix_pos.row as f32 / 40.0)
+        };
+        let sampler = FracSampler::new(&fractal, &pix_to_complex);
+
+        let mut scheduler = TileScheduler::new(2);
+        scheduler.set_tile_size(40);
+        scheduler.set_mode(RenderMode::Subdivide);
+        let subdivided: FracImage = scheduler.render(120, 96, &sampler);
+        let full: FracImage = render_parallel(120, 96, 2, &sampler);
+        for (full_val, sub_val) in full.pixels().iter().zip(subdivided.pixels()) {
+            match (full_val, sub_val) {
+                (FracOutput::MandelJulia{iters: full_iters, distance: full_distance, ..},
+                 FracOutput::MandelJulia{iters: sub_iters, distance: sub_distance, ..}) => {
+                    assert_eq!((full_iters, full_distance), (sub_iters, sub_distance));
+                }
+                _ => panic!("Not a Mandelbrot output"),
+            }
+        }
+    }
+
+    // Filled in pixels inside the set don't get another pixel's interior
+    // data either
+    #[test]
+    fn subdivide_keeps_interior_data() {
+        use super::super::fractals::FractalType;
+
+        let mut fractal = Fractal::new(200, FractalType::Mandelbrot()).unwrap();
+        fractal.set_interior(true);
+        let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+            MyComplex::new(-2.2 + pix_pos.col as f32 / 40.0, -1.2 + pix_pos.row as f32 / 40.0)
+        };
+        let sampler = FracSampler::new(&fractal, &pix_to_complex);
+
+        let mut scheduler = TileScheduler::new(2);
+        scheduler.set_tile_size(40);
+        scheduler.set_mode(RenderMode::Subdivide);
+        let subdivided: FracImage = scheduler.render(120, 96, &sampler);
+        let full: FracImage = render_parallel(120, 96, 2, &sampler);
+        for (full_val, sub_val) in full.pixels().iter().zip(subdivided.pixels()) {
+            match (full_val, sub_val) {
+                (FracOutput::MandelJulia{iters: full_iters, interior: full_interior, ..},
+                 FracOutput::MandelJulia{iters: sub_iters, interior: sub_interior, ..}) => {
+                    assert_eq!((full_iters, full_interior), (sub_iters, sub_interior));
+                }
+                _ => panic!("Not a Mandelbrot output"),
+            }
+        }
+    }
+
+    // Every pass of the progressive renderer should show the pixels it has
+    // found so far in blocks, end up with the same image as `render`, and
+    // never calculate a pixel twice
+    #[test]
+    fn progressive_passes() {
+        let calculated: AtomicUsize = AtomicUsize::new(0);
+        let counted_output = |pix_pos: PixPos| -> FracOutput {
+            calculated.fetch_add(1, Ordering::Relaxed);
+            test_output(pix_pos)
+        };
+
+        let mut steps: Vec<u32> = Vec::new();
+        let mut check_pass = |step: u32, preview: &FracImage| {
+            steps.push(step);
+            for row in 0..preview.height() {
+                for col in 0..preview.width() {
+                    let block_start = PixPos{row: row - row % step, col: col - col % step};
+                    assert_eq!(preview.pixel(PixPos{row, col}), test_output(block_start));
+                }
+            }
+        };
+        let image: FracImage = TileScheduler::new(3).render_progressive(50, 37, &counted_output, &mut check_pass);
+
+        assert_eq!(steps, vec![16, 8, 4, 2, 1]);
+        assert_eq!(image.pixels(), render(50, 37, &test_output).pixels());
+        assert_eq!(calculated.load(Ordering::Relaxed), 50 * 37);
+    }
+
+    // Every pattern puts one sample in each cell of the pixel's grid, and
+    // the rotated grid never puts two samples in the same row or column
+    #[test]
+    fn sample_patterns() {
+        for pattern in [SamplePattern::Grid, SamplePattern::Jittered, SamplePattern::RotatedGrid] {
+            for samples in [1, 4, 9, 16] {
+                let offsets: Vec<(f32, f32)> = (0..samples)
+                    .map(|sample| pattern.offset(samples, sample, PixPos{row: 3, col: 8}))
+                    .collect();
+                assert!(offsets.iter().all(|(x, y)| (0.0..1.0).contains(x) && (0.0..1.0).contains(y)));
+
+                if pattern != SamplePattern::RotatedGrid {
+                    let side: f32 = (samples as f32).sqrt();
+                    for (sample, (x, y)) in offsets.iter().enumerate() {
+                        let cell: u32 = (y * side) as u32 * side as u32 + (x * side) as u32;
+                        assert_eq!(cell, sample as u32);
+                    }
+                } else {
+                    for (idx, (x, y)) in offsets.iter().enumerate() {
+                        assert!(offsets[idx + 1..].iter().all(|(other_x, other_y)| {
+                            (x - other_x).abs() > 1e-3 && (y - other_y).abs() > 1e-3
+                        }));
+                    }
+                }
+            }
+        }
+        assert_eq!(SamplePattern::RotatedGrid.offset(4, 0, PixPos{row: 0, col: 0}), (0.375, 0.125));
+    }
+
+    // Every pixel in the list gets its samples, in order
+    #[test]
+    fn samples_of_pixel_list() {
+        use super::super::fractals::FractalType;
+
+        let fractal = Fractal::new(50, FractalType::Mandelbrot()).unwrap();
+        let sample_to_complex = |pixel: PixPos, sample: u32| -> MyComplex<f32> {
+            MyComplex::new(-2.0 + pixel.col as f32 * 0.1 + sample as f32 * 0.01, -1.0 + pixel.row as f32 * 0.1)
+        };
+        let pixels: Vec<PixPos> = vec![PixPos{row: 3, col: 9}, PixPos{row: 0, col: 0}, PixPos{row: 12, col: 20}];
+
+        let mut seen: Vec<PixPos> = Vec::new();
+        TileScheduler::new(2).render_samples(&fractal, &pixels, 9, &sample_to_complex, &mut |chunk, chunk_samples| {
+            for (pixel, pixel_samples) in chunk.iter().zip(chunk_samples.chunks(9)) {
+                for (sample, val) in pixel_samples.iter().enumerate() {
+                    assert_eq!(*val, fractal.complex_to_frac_output(sample_to_complex(*pixel, sample as u32)));
+                }
+                seen.push(*pixel);
+            }
+        });
+        assert_eq!(seen, pixels);
+    }
+
+    // Going through the batched kernels row by row gives the same image as
+    // sampling every pixel on its own
+    #[test]
+    fn sampler_row_spans() {
+        use super::super::fractals::FractalType;
+
+        let fractal = Fractal::new(200, FractalType::Mandelbrot()).unwrap();
+        let pix_to_complex = |pix_pos: PixPos| -> MyComplex<f32> {
+            MyComplex::new(-2.0 + pix_pos.col as f32 * 0.1, -1.2 + pix_pos.row as f32 * 0.1)
+        };
+        let sampler = FracSampler::new(&fractal, &pix_to_complex);
+        let by_pixel = |pix_pos: PixPos| -> FracOutput { sampler.pixel(pix_pos) };
+
+        assert_eq!(render_parallel(29, 25, 3, &sampler).pixels(), render(29, 25, &by_pixel).pixels());
+    }
+}
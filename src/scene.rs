@@ -0,0 +1,477 @@
+use std::{fs, path::Path};
+use serde::{Deserialize, Serialize};
+use super::animation::AnimationSpec;
+use super::atlas::{AtlasSpec, JuliaAtlas};
+use super::buddhabrot::DensitySpec;
+use super::error::{FracError, FracResult};
+use super::fractals::{Fractal, FractalType};
+use super::graphics::bmp_img_maker::BMPImg;
+use super::my_complex::MyComplex;
+use super::orbit_trap::TrapSpec;
+use super::viewport::Viewport;
+
+// Everything needed to render an image again: the fractal, the part of the
+// complex plane it shows, how it's colored and where it's written. A scene
+// can be saved as TOML or JSON, e.g.
+//
+//   max_iters = 500
+//
+//   [fractal]
+//   type = "newton"
+//   roots = ["1", "-0.5+0.866i", "-0.5-0.866i"]
+//
+//   [view]
+//   center = "0"
+//   zoom = 2.0
+//
+//   [color]
+//   palette = "ocean"
+//
+//   [output]
+//   width = 800
+//   height = 600
+//   file_name = "newton.bmp"
+//
+// Everything but the fractal and the image size has a default. Real numbers
+// are kept as f64 so they're written out the way they were typed. Complex
+// numbers are kept as the MyComplex<f32> the fractals use, so they only come
+// back out as typed up to f32 precision (about 7 significant digits).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    #[serde(default = "default_max_iters")]
+    pub max_iters: u32,
+    pub fractal: FractalSpec,
+    #[serde(default)]
+    pub view: ViewSpec,
+    #[serde(default)]
+    pub color: ColorSpec,
+    pub output: OutputSpec,
+    // A grid of Julia sets over the view, in place of the fractal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atlas: Option<AtlasSpec>,
+    // Render the density of the fractal's orbits (a Buddhabrot) instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density: Option<DensitySpec>,
+    // How the fractal changes over the frames of an animation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<AnimationSpec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum FractalSpec {
+    Mandelbrot,
+    Julia { seed: MyComplex<f32> },
+    Newton { roots: Vec<MyComplex<f32>> },
+}
+
+// Either a center and zoom, or the ranges of each axis (like the command
+// line, the ranges win if either one is given). The rotation is in degrees.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ViewSpec {
+    pub center: MyComplex<f32>,
+    pub zoom: f64,
+    pub rotate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub real_range: Option<(f64, f64)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imag_range: Option<(f64, f64)>,
+}
+
+// The names are the same as the values of the command line options
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorSpec {
+    pub colorizer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette_interp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette_mapping: Option<String>,
+    pub palette_offset: f64,
+    pub palette_scale: f64,
+    pub coloring: String,
+    // How wide (in pixels) the lines of 'distance' coloring fade out over
+    pub boundary_width: f64,
+    // Blend the pixels the edge of the set passes through into the inside
+    // color, by how much of the pixel the distance estimate says is covered
+    pub distance_antialias: bool,
+    // How far (in complex units) from an orbit trap 'trap' coloring goes
+    // through the palette over
+    pub trap_width: f64,
+    // How the points inside a Mandelbrot/Julia set are colored (see
+    // InteriorColoring)
+    pub interior: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub root_colors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trap: Option<TrapSpec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputSpec {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default = "default_file_name")]
+    pub file_name: String,
+    // Picked from the file name's extension when it isn't given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+    #[serde(default = "default_sample_pattern")]
+    pub sample_pattern: String,
+    #[serde(default)]
+    pub adaptive: bool,
+    // Record the InteriorInfo of every pixel in .npy output, even if the
+    // coloring doesn't need it, so the data can be recolored with it later
+    #[serde(default)]
+    pub npy_interior: bool,
+}
+
+fn default_max_iters() -> u32 { 1000 }
+fn default_file_name() -> String { String::from("fractal.bmp") }
+fn default_samples() -> u32 { 1 }
+fn default_sample_pattern() -> String { String::from("rotated") }
+
+impl Default for ViewSpec {
+    fn default() -> Self {
+        ViewSpec { center: MyComplex::new(0.0, 0.0), zoom: 1.0, rotate: 0.0, real_range: None, imag_range: None }
+    }
+}
+
+impl Default for ColorSpec {
+    fn default() -> Self {
+        ColorSpec {
+            colorizer: String::from("bands"),
+            palette: None,
+            palette_interp: None,
+            palette_mapping: None,
+            palette_offset: 0.0,
+            palette_scale: 0.03125,
+            coloring: String::from("iteration"),
+            boundary_width: 1.0,
+            distance_antialias: false,
+            trap_width: 0.25,
+            interior: String::from("flat"),
+            root_colors: Vec::new(),
+            trap: None,
+        }
+    }
+}
+
+impl ColorSpec {
+    // Whether the coloring needs the distance estimates of the pixels
+    pub fn uses_distance(&self) -> bool {
+        self.coloring == "distance" || self.distance_antialias
+    }
+
+    // Whether the coloring needs the InteriorInfo of the pixels
+    pub fn uses_interior(&self) -> bool {
+        self.interior != "flat"
+    }
+}
+
+impl OutputSpec {
+    pub fn new(width: u32, height: u32) -> Self {
+        OutputSpec {
+            width, height,
+            file_name: default_file_name(),
+            format: None,
+            samples: default_samples(),
+            sample_pattern: default_sample_pattern(),
+            adaptive: false,
+            npy_interior: false,
+        }
+    }
+
+    // "bmp" or "npy"
+    pub fn format_name(&self) -> &str {
+        match &self.format {
+            Some(format) => format,
+            None if self.file_name.ends_with(".npy") => "npy",
+            None => "bmp",
+        }
+    }
+}
+
+impl FractalSpec {
+    pub fn to_kind(&self) -> FractalType {
+        match self {
+            FractalSpec::Mandelbrot => FractalType::Mandelbrot(),
+            FractalSpec::Julia{seed} => FractalType::Julia(*seed),
+            FractalSpec::Newton{roots} => FractalType::Newton(roots.clone()),
+        }
+    }
+}
+
+// The start of the comment in every image, whatever version of the program
+// rendered it. The heading adds that version after it.
+const COMMENT_MARKER: &str = concat!("# Rendered with ", env!("CARGO_PKG_NAME"));
+
+fn comment_heading() -> String {
+    format!("{} {}\n", COMMENT_MARKER, env!("CARGO_PKG_VERSION"))
+}
+
+// The start and end of an axis range, making sure it isn't empty or
+// backwards
+fn axis_range(range: Option<(f64, f64)>, name: &str) -> FracResult<(f32, f32)> {
+    match range {
+        Some((start, end)) if start < end => Ok((start as f32, end as f32)),
+        Some((start, end)) => Err(FracError::InvalidInput(format!(
+            "The start of the {} ({}) has to be less than its end ({})", name, start, end))),
+        None => Ok((-2.0, 2.0)),
+    }
+}
+
+impl Scene {
+    // A scene with the default view, coloring and output options
+    pub fn new(fractal: FractalSpec, width: u32, height: u32) -> Self {
+        Scene {
+            max_iters: default_max_iters(),
+            fractal,
+            view: ViewSpec::default(),
+            color: ColorSpec::default(),
+            output: OutputSpec::new(width, height),
+            atlas: None,
+            density: None,
+            animation: None,
+        }
+    }
+
+    // Read a scene file, as JSON if its name ends in .json and as TOML
+    // otherwise
+    pub fn from_file(file_name: &str) -> FracResult<Self> {
+        let contents: String = fs::read_to_string(Path::new(file_name)).map_err(|err| FracError::io(file_name, err))?;
+        let parsed: Result<Scene, String> = if file_name.ends_with(".json") {
+            serde_json::from_str(&contents).map_err(|err| err.to_string())
+        } else {
+            toml::from_str(&contents).map_err(|err| err.to_string())
+        };
+        parsed.map_err(|err| FracError::Format(format!("{}: {}", file_name, err.trim_end())))
+    }
+
+    // Read the scene an image was rendered from out of the comment
+    // `to_comment` made for it
+    pub fn from_image(file_name: &str) -> FracResult<Self> {
+        let comment: String = BMPImg::read_comment(file_name)?.ok_or_else(|| FracError::Format(format!(
+            "{} doesn't have the parameters it was rendered with", file_name)))?;
+        Self::from_comment(&comment).map_err(|err| FracError::Format(format!("{}: {}", file_name, err)))
+    }
+
+    // The scene in a comment made by `to_comment`. The version in its
+    // heading is only used to explain why a scene from another version
+    // can't be read.
+    fn from_comment(comment: &str) -> Result<Self, String> {
+        let version: &str = match comment.strip_prefix(COMMENT_MARKER) {
+            Some(rest) if rest.starts_with(' ') => rest.lines().next().unwrap_or_default().trim(),
+            _ => return Err(String::from("the text at the end of the image isn't a scene saved by this program")),
+        };
+        toml::from_str(comment).map_err(|err| {
+            let message: String = err.to_string().trim_end().to_string();
+            if version == env!("CARGO_PKG_VERSION") {
+                message
+            } else {
+                format!("{} (the image was rendered with version {}, this is version {})", message, version, env!("CARGO_PKG_VERSION"))
+            }
+        })
+    }
+
+    // The scene as TOML with a heading naming the program and its version,
+    // to go at the end of the image it was rendered into. The heading is a
+    // TOML comment, so the whole thing can also be saved as a scene file.
+    pub fn to_comment(&self) -> FracResult<String> {
+        Ok(format!("{}{}", comment_heading(), self.to_toml()?))
+    }
+
+    pub fn to_toml(&self) -> FracResult<String> {
+        toml::to_string(self).map_err(|err| FracError::Format(format!("The scene can't be written as TOML: {}", err)))
+    }
+
+    pub fn to_json(&self) -> FracResult<String> {
+        serde_json::to_string_pretty(self).map_err(|err| FracError::Format(format!("The scene can't be written as JSON: {}", err)))
+    }
+
+    // Write the scene out, as JSON if the file name ends in .json and as
+    // TOML otherwise
+    pub fn write_file(&self, file_name: &str) -> FracResult<()> {
+        let contents: String = if file_name.ends_with(".json") { self.to_json()? } else { self.to_toml()? };
+        fs::write(Path::new(file_name), contents).map_err(|err| FracError::io(file_name, err))
+    }
+
+    // The distance estimates are worked out if the coloring needs them, and
+    // for raw data so it can be recolored with them later. Interior data
+    // means following every orbit all the way, so raw data only gets it if
+    // it's asked for.
+    pub fn fractal(&self) -> FracResult<Fractal> {
+        let mut fractal: Fractal = Fractal::new(self.max_iters, self.fractal.to_kind())?;
+        fractal.set_distance_estimate(self.color.uses_distance() || self.output.format_name() == "npy");
+        fractal.set_trap(self.color.trap.as_ref().map(TrapSpec::to_trap).transpose()?);
+        fractal.set_interior(self.records_interior());
+        Ok(fractal)
+    }
+
+    fn records_interior(&self) -> bool {
+        self.color.uses_interior() || (self.output.npy_interior && self.output.format_name() == "npy")
+    }
+
+    // The Julia set atlas, if the scene has one, with `fractal` (the scene's
+    // fractal) as its background if it asks for one
+    pub fn julia_atlas<'a>(&self, fractal: &'a Fractal) -> FracResult<Option<JuliaAtlas<'a>>> {
+        let spec: &AtlasSpec = match &self.atlas {
+            Some(spec) => spec,
+            None => return Ok(None),
+        };
+        let mut atlas = JuliaAtlas::new(self.viewport()?, spec.columns, spec.rows, self.max_iters)?;
+        atlas.set_gap(spec.gap)?;
+        atlas.set_cell_zoom(spec.cell_zoom as f32)?;
+        atlas.set_distance_estimate(self.color.uses_distance() || self.output.format_name() == "npy");
+        atlas.set_trap(self.color.trap.as_ref().map(TrapSpec::to_trap).transpose()?);
+        atlas.set_interior(self.records_interior());
+        if spec.background {
+            atlas.set_background(Some(fractal));
+        }
+        Ok(Some(atlas))
+    }
+
+    pub fn viewport(&self) -> FracResult<Viewport> {
+        let (width, height): (u32, u32) = (self.output.width, self.output.height);
+        if width == 0 || height == 0 {
+            return Err(FracError::InvalidInput(String::from("The width and height of the image have to be at least 1 pixel")));
+        }
+        let view: &ViewSpec = &self.view;
+        let mut viewport: Viewport = if view.real_range.is_some() || view.imag_range.is_some() {
+            Viewport::from_ranges(width, height, axis_range(view.real_range, "real range")?,
+                                  axis_range(view.imag_range, "imaginary range")?)
+        } else {
+            if !(view.zoom > 0.0 && view.zoom.is_finite()) {
+                return Err(FracError::InvalidInput(format!("The zoom has to be a positive number, not {}", view.zoom)));
+            }
+            Viewport::from_zoom(width, height, view.center, view.zoom as f32)
+        };
+        viewport.set_rotation((view.rotate as f32).to_radians());
+        Ok(viewport)
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fractals::{FracOutput, NO_INTERIOR_INFO};
+
+    // Only the fractal and the image size have to be given
+    #[test]
+    fn minimal_scene() {
+        let scene: Scene = toml::from_str("[fractal]\ntype = \"mandelbrot\"\n[output]\nwidth = 64\nheight = 48\n").unwrap();
+        assert_eq!(scene, Scene::new(FractalSpec::Mandelbrot, 64, 48));
+        assert_eq!(scene.output.format_name(), "bmp");
+    }
+
+    // Complex numbers can be written as text, plain numbers or pairs
+    #[test]
+    fn complex_values() {
+        let scene: Scene = toml::from_str("max_iters = 50\n\
+            [fractal]\ntype = \"newton\"\nroots = [\"1\", -1, [0.0, 1.0], \"1∠-90\"]\n\
+            [view]\ncenter = \"-0.5+0.25i\"\n\
+            [output]\nwidth = 10\nheight = 10\n").unwrap();
+        match &scene.fractal {
+            FractalSpec::Newton{roots} => {
+                assert_eq!(roots[..3], [MyComplex::new(1.0, 0.0), MyComplex::new(-1.0, 0.0), MyComplex::new(0.0, 1.0)]);
+                assert!((roots[3] - MyComplex::new(0.0, -1.0)).mag_sqr() < 1e-10);
+            }
+            other => panic!("Expected a Newton fractal, got {:?}", other),
+        }
+        assert_eq!(scene.view.center, MyComplex::new(-0.5, 0.25));
+    }
+
+    // Writing a scene out and reading it back gives the same scene
+    #[test]
+    fn scene_round_trip() {
+        let mut scene = Scene::new(FractalSpec::Julia{seed: MyComplex::new(-0.8, 0.156)}, 640, 480);
+        scene.view.real_range = Some((-1.5, 1.5));
+        scene.view.rotate = 12.5;
+        scene.color.palette = Some(String::from("fire"));
+        scene.color.root_colors = vec![String::from("#ff0000")];
+        scene.color.interior = String::from("multiplier");
+        scene.color.trap = Some(TrapSpec::Line{center: MyComplex::new(0.5, 0.0), angle: 30.0});
+        scene.output.samples = 4;
+
+        assert_eq!(toml::from_str::<Scene>(&scene.to_toml().unwrap()).unwrap(), scene);
+        assert_eq!(serde_json::from_str::<Scene>(&scene.to_json().unwrap()).unwrap(), scene);
+        assert!(scene.to_toml().unwrap().contains("seed = \"-0.8+0.156i\""));
+    }
+
+    // The scene written at the end of an image can be read back from it
+    #[test]
+    fn scene_in_image() {
+        let path = std::env::temp_dir().join("fractal_generator_scene_in_image.bmp");
+        let file_name: &str = path.to_str().unwrap();
+        let mut scene = Scene::new(FractalSpec::Newton{roots: vec![MyComplex::new(1.0, 0.0), MyComplex::new(-1.0, 0.0)]}, 4, 4);
+        scene.view.zoom = 8.0;
+
+        let bmp_img_obj = BMPImg::new(4, 4, file_name).unwrap();
+        bmp_img_obj.set_header().unwrap();
+        bmp_img_obj.write_colors(&[crate::graphics::Color{r: 0, g: 0, b: 0}; 16]).unwrap();
+        assert!(Scene::from_image(file_name).is_err());
+        bmp_img_obj.append_comment(&scene.to_comment().unwrap()).unwrap();
+        let read_scene: FracResult<Scene> = Scene::from_image(file_name);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_scene.unwrap(), scene);
+    }
+
+    // Scenes saved by other versions can still be read, as long as their
+    // options haven't changed
+    #[test]
+    fn comment_from_other_version() {
+        let scene = Scene::new(FractalSpec::Mandelbrot, 4, 4);
+        let comment: String = format!("{} 0.0.1\n{}", COMMENT_MARKER, scene.to_toml().unwrap());
+        assert_eq!(Scene::from_comment(&comment).unwrap(), scene);
+
+        let unknown_option: String = format!("{}colour = 1\n", comment);
+        assert!(Scene::from_comment(&unknown_option).unwrap_err().contains("version 0.0.1"));
+        assert!(Scene::from_comment(&scene.to_toml().unwrap()).is_err());
+        assert!(Scene::from_comment(&format!("{}_other 1.0\n{}", COMMENT_MARKER, scene.to_toml().unwrap())).is_err());
+    }
+
+    // Raw data only gets interior data when the coloring needs it or it's
+    // asked for
+    #[test]
+    fn npy_interior_data() {
+        let mut scene = Scene::new(FractalSpec::Mandelbrot, 4, 4);
+        scene.output.file_name = String::from("data.npy");
+        let inside = |scene: &Scene| -> FracOutput { scene.fractal().unwrap().complex_to_frac_output(MyComplex::new(-0.1, 0.1)) };
+        assert!(matches!(inside(&scene), FracOutput::MandelJulia{interior, ..} if interior == NO_INTERIOR_INFO));
+
+        scene.output.npy_interior = true;
+        assert!(matches!(inside(&scene), FracOutput::MandelJulia{interior, ..} if interior != NO_INTERIOR_INFO));
+        scene.output.file_name = String::from("image.bmp");
+        assert!(matches!(inside(&scene), FracOutput::MandelJulia{interior, ..} if interior == NO_INTERIOR_INFO));
+        scene.color.interior = String::from("angle");
+        assert!(matches!(inside(&scene), FracOutput::MandelJulia{interior, ..} if interior != NO_INTERIOR_INFO));
+    }
+
+    #[test]
+    fn invalid_scenes() {
+        assert!(toml::from_str::<Scene>("[fractal]\ntype = \"mandelbrot\"\n").is_err());
+        assert!(toml::from_str::<Scene>("colour = 1\n[fractal]\ntype = \"mandelbrot\"\n[output]\nwidth = 1\nheight = 1\n").is_err());
+        assert!(toml::from_str::<Scene>("[fractal]\ntype = \"julia\"\nseed = 0.3\nroots = []\n[output]\nwidth = 1\nheight = 1\n").is_err());
+
+        let mut scene = Scene::new(FractalSpec::Newton{roots: Vec::new()}, 10, 10);
+        assert!(scene.fractal().is_err());
+        scene.view.imag_range = Some((1.0, -1.0));
+        assert!(scene.viewport().is_err());
+        scene.view.imag_range = None;
+        scene.view.zoom = 0.0;
+        assert!(scene.viewport().is_err());
+        scene.view.zoom = 2.0;
+        scene.output.width = 0;
+        assert!(scene.viewport().is_err());
+    }
+}
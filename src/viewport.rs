@@ -0,0 +1,195 @@
+use super::graphics::PixPos;
+use super::my_complex::MyComplex;
+
+// The span (in complex units) across the shorter side of the image at a
+// zoom of 1, which shows the whole Mandelbrot set
+pub const DEFAULT_SPAN: f32 = 4.0;
+
+// The part of the complex plane an image shows: where its center is, how
+// big each pixel is, and how far the view is turned. Positions inside the
+// image are given in pixels across and up from its bottom left corner (the
+// same order the pixels are stored in), so pixel (row, col) covers the
+// square from (col, row) to (col + 1, row + 1) and is sampled in its middle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    width: u32,
+    height: u32,
+    center: MyComplex<f32>,
+    // The size of a pixel along the real and imaginary axes (before the
+    // view is turned)
+    pixel_width: f32,
+    pixel_height: f32,
+    // Counter-clockwise, in radians
+    rotation: f32,
+    cos_rot: f32,
+    sin_rot: f32,
+}
+
+impl Viewport {
+    // A view with square pixels, centered on `center`, with `span` complex
+    // units across the shorter side of the image
+    pub fn new(width: u32, height: u32, center: MyComplex<f32>, span: f32) -> Self {
+        let pixel_size: f32 = span / width.min(height).max(1) as f32;
+        Viewport {
+            width, height, center,
+            pixel_width: pixel_size, pixel_height: pixel_size,
+            rotation: 0.0, cos_rot: 1.0, sin_rot: 0.0,
+        }
+    }
+
+    // A view magnified `zoom` times from the default span
+    pub fn from_zoom(width: u32, height: u32, center: MyComplex<f32>, zoom: f32) -> Self {
+        Viewport::new(width, height, center, DEFAULT_SPAN / zoom)
+    }
+
+    // A view whose edges are at the given start and end coordinates of each
+    // axis. The pixels are only square if the ranges have the same aspect
+    // ratio as the image.
+    pub fn from_ranges(width: u32, height: u32, real_range: (f32, f32), imag_range: (f32, f32)) -> Self {
+        let center = MyComplex::new((real_range.0 + real_range.1) / 2.0, (imag_range.0 + imag_range.1) / 2.0);
+        Viewport {
+            width, height, center,
+            pixel_width: (real_range.1 - real_range.0) / width.max(1) as f32,
+            pixel_height: (imag_range.1 - imag_range.0) / height.max(1) as f32,
+            rotation: 0.0, cos_rot: 1.0, sin_rot: 0.0,
+        }
+    }
+
+    // Turn the view counter-clockwise around its center
+    pub fn set_rotation(&mut self, radians: f32) {
+        self.rotation = radians;
+        self.cos_rot = radians.cos();
+        self.sin_rot = radians.sin();
+    }
+
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn center(&self) -> MyComplex<f32> { self.center }
+    pub fn rotation(&self) -> f32 { self.rotation }
+    pub fn pixel_size(&self) -> (f32, f32) { (self.pixel_width, self.pixel_height) }
+
+    // The complex number at a point in the image
+    pub fn point_to_complex(&self, x: f32, y: f32) -> MyComplex<f32> {
+        let across: f32 = (x - self.width as f32 / 2.0) * self.pixel_width;
+        let up: f32 = (y - self.height as f32 / 2.0) * self.pixel_height;
+        MyComplex::new(self.center.r() + across * self.cos_rot - up * self.sin_rot,
+                       self.center.i() + across * self.sin_rot + up * self.cos_rot)
+    }
+
+    // The complex number in the middle of a pixel
+    pub fn pixel_to_complex(&self, pos: PixPos) -> MyComplex<f32> {
+        self.point_to_complex(pos.col as f32 + 0.5, pos.row as f32 + 0.5)
+    }
+
+    // The point in the image at a complex number (the inverse of
+    // point_to_complex). It can be outside the image.
+    pub fn complex_to_point(&self, z: MyComplex<f32>) -> (f32, f32) {
+        let diff: MyComplex<f32> = z - self.center;
+        let across: f32 = diff.r() * self.cos_rot + diff.i() * self.sin_rot;
+        let up: f32 = -diff.r() * self.sin_rot + diff.i() * self.cos_rot;
+        (across / self.pixel_width + self.width as f32 / 2.0, up / self.pixel_height + self.height as f32 / 2.0)
+    }
+
+    // The pixel a complex number is in, if it's inside the image
+    pub fn complex_to_pixel(&self, z: MyComplex<f32>) -> Option<PixPos> {
+        let (x, y): (f32, f32) = self.complex_to_point(z);
+        if x >= 0.0 && y >= 0.0 && x < self.width as f32 && y < self.height as f32 {
+            Some(PixPos{row: y as u32, col: x as u32})
+        } else {
+            None
+        }
+    }
+
+    // For fractals that are symmetric across the real axis: if the rows of
+    // the image line up on either side of the real axis, the sum of the
+    // indices of any row and its mirror image (see render::RowMirror).
+    // Returns None if the view is turned, the rows don't line up, or none
+    // of the mirror images are in the image.
+    pub fn mirror_axis_sum(&self) -> Option<u32> {
+        if self.rotation.rem_euclid(std::f32::consts::TAU) != 0.0 {
+            return None;
+        }
+        // The middle of row `row` is at center.i + (row + 0.5 - height / 2)
+        // * pixel_height, which is the negative of the middle of row
+        // `axis_sum - row`
+        let axis_sum: f32 = self.height as f32 - 1.0 - 2.0 * self.center.i() / self.pixel_height;
+        let lines_up: bool = (axis_sum - axis_sum.round()).abs() < 1e-3;
+        if lines_up && axis_sum.round() > 0.0 && axis_sum.round() < 2.0 * (self.height as f32 - 1.0) {
+            Some(axis_sum.round() as u32)
+        } else {
+            None
+        }
+    }
+}
+
+/*------------------------------------------------------------------------
+                                TESTS
+------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: MyComplex<f32>, b: MyComplex<f32>) {
+        assert!((a - b).mag_sqr() < 1e-10, "{:?} != {:?}", a, b);
+    }
+
+    // The shorter side gets the whole span and the pixels stay square
+    #[test]
+    fn square_pixels() {
+        let view = Viewport::from_zoom(400, 200, MyComplex::new(-0.5, 0.0), 2.0);
+        assert_eq!(view.pixel_size(), (0.01, 0.01));
+        assert_close(view.point_to_complex(0.0, 0.0), MyComplex::new(-2.5, -1.0));
+        assert_close(view.point_to_complex(400.0, 200.0), MyComplex::new(1.5, 1.0));
+        assert_close(view.pixel_to_complex(PixPos{row: 0, col: 0}), MyComplex::new(-2.495, -0.995));
+    }
+
+    // The edges of a view made from ranges are at the ends of the ranges
+    #[test]
+    fn ranges_are_edges() {
+        let view = Viewport::from_ranges(300, 100, (-2.0, 1.0), (-1.5, 0.5));
+        assert_close(view.point_to_complex(0.0, 0.0), MyComplex::new(-2.0, -1.5));
+        assert_close(view.point_to_complex(300.0, 100.0), MyComplex::new(1.0, 0.5));
+    }
+
+    // Turning the view a quarter turn points the image's x axis up the
+    // imaginary axis
+    #[test]
+    fn rotation() {
+        let mut view = Viewport::new(100, 100, MyComplex::new(1.0, 1.0), 2.0);
+        view.set_rotation(std::f32::consts::FRAC_PI_2);
+        assert_close(view.point_to_complex(100.0, 50.0), MyComplex::new(1.0, 2.0));
+        assert_close(view.point_to_complex(50.0, 100.0), MyComplex::new(0.0, 1.0));
+    }
+
+    // Going from a point to a complex number and back gives the same point
+    #[test]
+    fn conversions_round_trip() {
+        let mut view = Viewport::from_zoom(640, 480, MyComplex::new(-0.7436, 0.1318), 300.0);
+        view.set_rotation(0.6);
+        for (x, y) in [(0.0, 0.0), (320.5, 12.25), (639.0, 479.0)] {
+            let (back_x, back_y): (f32, f32) = view.complex_to_point(view.point_to_complex(x, y));
+            assert!((back_x - x).abs() < 0.05 && (back_y - y).abs() < 0.05);
+        }
+        assert_eq!(view.complex_to_pixel(view.pixel_to_complex(PixPos{row: 17, col: 402})), Some(PixPos{row: 17, col: 402}));
+        assert_eq!(view.complex_to_pixel(MyComplex::new(5.0, 5.0)), None);
+    }
+
+    // Views centered on the real axis mirror around the middle of the image
+    #[test]
+    fn mirror_axis() {
+        assert_eq!(Viewport::from_zoom(300, 200, MyComplex::new(-0.5, 0.0), 1.0).mirror_axis_sum(), Some(199));
+        assert_eq!(Viewport::from_zoom(300, 201, MyComplex::new(-0.5, 0.0), 1.0).mirror_axis_sum(), Some(200));
+        // Shifted up by exactly 10 pixels
+        assert_eq!(Viewport::from_zoom(300, 200, MyComplex::new(-0.5, 0.2), 1.0).mirror_axis_sum(), Some(179));
+        // Shifted by half a pixel the rows still line up, but not by a
+        // quarter of one
+        assert_eq!(Viewport::from_zoom(300, 200, MyComplex::new(-0.5, 0.01), 1.0).mirror_axis_sum(), Some(198));
+        assert_eq!(Viewport::from_zoom(300, 200, MyComplex::new(-0.5, 0.005), 1.0).mirror_axis_sum(), None);
+        // Doesn't reach the real axis
+        assert_eq!(Viewport::from_zoom(300, 200, MyComplex::new(-0.5, 1.0), 4.0).mirror_axis_sum(), None);
+
+        let mut turned = Viewport::from_zoom(300, 200, MyComplex::new(-0.5, 0.0), 1.0);
+        turned.set_rotation(0.1);
+        assert_eq!(turned.mirror_axis_sum(), None);
+    }
+}